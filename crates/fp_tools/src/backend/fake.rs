@@ -0,0 +1,319 @@
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+use crate::commands::parse::{LdifEntry, UserRecord, contains_name, names_equal};
+use crate::commands::user::DomainUser;
+use crate::commands::validate;
+use crate::error::{Error, Result};
+use crate::password::policy::DomainPasswordPolicy;
+use super::DirectoryBackend;
+
+///
+/// Primary group of every account
+///
+const PRIMARY_GROUP: &str = "Domain Users";
+
+#[derive(Debug, Clone)]
+struct FakeUser {
+    name: String,
+    surname: String,
+    given_name: String,
+    password: String
+}
+
+#[derive(Debug, Clone)]
+struct FakeGroup {
+    name: String,
+    members: Vec<String>
+}
+
+#[derive(Debug, Default)]
+struct Domain {
+    /// Keyed by the lowercase name, as AD names are case-insensitive
+    users: BTreeMap<String, FakeUser>,
+    groups: BTreeMap<String, FakeGroup>,
+    /// Lowercase names of the accounts and groups every change is refused for
+    refused: HashSet<String>
+}
+
+impl Domain {
+    fn group_mut(&mut self, group: &str) -> Result<&mut FakeGroup> {
+        self.groups.get_mut(&group.to_lowercase()).ok_or_else(|| Error::NotFound(format!("group {}", group)))
+    }
+}
+
+///
+/// Directory kept in memory, for tests: a domain with the built-in
+/// `Domain Users` group, where every change is applied at once.
+///
+/// Changes to the names marked with `refuse` fail with a constraint
+/// violation, as a real domain does for bad passwords or policies.
+///
+#[derive(Debug)]
+pub struct FakeDirectory {
+    domain: Mutex<Domain>
+}
+
+impl Default for FakeDirectory {
+    fn default() -> FakeDirectory {
+        let directory = FakeDirectory { domain: Mutex::new(Domain::default()) };
+        directory.add_group(PRIMARY_GROUP);
+        directory
+    }
+}
+
+impl FakeDirectory {
+    ///
+    /// Create a domain with no users
+    ///
+    pub fn new() -> FakeDirectory {
+        FakeDirectory::default()
+    }
+
+    ///
+    /// Add an existing user, creating the groups it is member of
+    ///
+    /// Arguments:
+    /// * `name`: account name
+    /// * `surname`, `given_name`: names of the user
+    /// * `groups`: groups the user is member of, primary group excluded
+    ///
+    pub fn with_user(self, name: &str, surname: &str, given_name: &str, groups: &[&str]) -> FakeDirectory {
+        self.domain().users.insert(name.to_lowercase(), FakeUser {
+            name: name.to_string(),
+            surname: surname.to_string(),
+            given_name: given_name.to_string(),
+            password: String::new()
+        });
+
+        for group in groups {
+            self.add_group(group);
+            self.domain().group_mut(group).expect("group just added").members.push(name.to_string());
+        }
+        self
+    }
+
+    ///
+    /// Add an existing group with no members
+    ///
+    pub fn with_group(self, group: &str) -> FakeDirectory {
+        self.add_group(group);
+        self
+    }
+
+    ///
+    /// Refuse every change to an account or group, existing or not
+    ///
+    pub fn refuse(self, name: &str) -> FakeDirectory {
+        self.domain().refused.insert(name.to_lowercase());
+        self
+    }
+
+    ///
+    /// Get the password last set for a user
+    ///
+    pub fn password(&self, username: &str) -> Option<String> {
+        self.domain().users.get(&username.to_lowercase()).map(|user| user.password.clone())
+    }
+
+    fn add_group(&self, group: &str) {
+        self.domain()
+            .groups
+            .entry(group.to_lowercase())
+            .or_insert_with(|| FakeGroup { name: group.to_string(), members: Vec::new() });
+    }
+
+    fn domain(&self) -> MutexGuard<'_, Domain> {
+        self.domain.lock().expect("fake directory poisoned")
+    }
+
+    ///
+    /// Lock the domain for a change to the given names
+    ///
+    fn change(&self, names: &[&str]) -> Result<MutexGuard<'_, Domain>> {
+        let domain = self.domain();
+
+        match names.iter().find(|name| domain.refused.contains(&name.to_lowercase())) {
+            Some(name) => Err(Error::ConstraintViolation(format!("change to {} refused", name))),
+            None => Ok(domain)
+        }
+    }
+
+    fn user(&self, username: &str) -> Result<FakeUser> {
+        self.domain()
+            .users
+            .get(&username.to_lowercase())
+            .cloned()
+            .ok_or_else(|| Error::NotFound(format!("user {}", username)))
+    }
+}
+
+impl DirectoryBackend for FakeDirectory {
+    fn list_users(&self) -> Result<Vec<String>> {
+        Ok(self.domain().users.values().map(|user| user.name.clone()).collect())
+    }
+
+    fn user_exists(&self, username: &str) -> Result<bool> {
+        Ok(self.domain().users.contains_key(&username.to_lowercase()))
+    }
+
+    fn show_user(&self, username: &str) -> Result<UserRecord> {
+        let user = self.user(username)?;
+        let member_of = self.user_groups(username)?
+            .into_iter()
+            .filter(|group| !names_equal(group, PRIMARY_GROUP))
+            .collect();
+
+        Ok(UserRecord {
+            dn: format!("CN={},CN=Users,DC=samdom,DC=example,DC=com", user.name),
+            name: user.name,
+            given_name: Some(user.given_name).filter(|name| !name.is_empty()),
+            surname: Some(user.surname).filter(|name| !name.is_empty()),
+            home_directory: None,
+            profile_path: None,
+            member_of,
+            entry: LdifEntry::default()
+        })
+    }
+
+    fn create_user(&self, user: &DomainUser) -> Result<()> {
+        validate::account_name(&user.common_name)?;
+        let mut domain = self.change(&[&user.common_name])?;
+
+        let key = user.common_name.to_lowercase();
+        if domain.users.contains_key(&key) || domain.groups.contains_key(&key) {
+            return Err(Error::AlreadyExists(format!("user {}", user.common_name)));
+        }
+
+        domain.users.insert(key, FakeUser {
+            name: user.common_name.clone(),
+            surname: user.last_name.clone(),
+            given_name: user.first_name.clone(),
+            password: user.password.clone()
+        });
+        Ok(())
+    }
+
+    fn delete_user(&self, username: &str) -> Result<()> {
+        let mut domain = self.change(&[username])?;
+
+        domain.users
+            .remove(&username.to_lowercase())
+            .ok_or_else(|| Error::NotFound(format!("user {}", username)))?;
+        for group in domain.groups.values_mut() {
+            group.members.retain(|member| !names_equal(member, username));
+        }
+        Ok(())
+    }
+
+    fn set_names(&self, username: &str, surname: &str, given_name: &str) -> Result<()> {
+        let mut domain = self.change(&[username])?;
+        let user = domain.users
+            .get_mut(&username.to_lowercase())
+            .ok_or_else(|| Error::NotFound(format!("user {}", username)))?;
+
+        user.surname = surname.to_string();
+        user.given_name = given_name.to_string();
+        Ok(())
+    }
+
+    fn set_password(&self, username: &str, password: &str) -> Result<()> {
+        let mut domain = self.change(&[username])?;
+        let user = domain.users
+            .get_mut(&username.to_lowercase())
+            .ok_or_else(|| Error::NotFound(format!("user {}", username)))?;
+
+        user.password = password.to_string();
+        Ok(())
+    }
+
+    fn user_groups(&self, username: &str) -> Result<Vec<String>> {
+        let user = self.user(username)?;
+        let domain = self.domain();
+
+        // Listed like `user getgroups`: primary group first
+        let mut groups = vec![PRIMARY_GROUP.to_string()];
+        groups.extend(
+            domain.groups
+                .values()
+                .filter(|group| contains_name(&group.members, &user.name))
+                .map(|group| group.name.clone())
+        );
+        Ok(groups)
+    }
+
+    fn list_groups(&self) -> Result<Vec<String>> {
+        Ok(self.domain().groups.values().map(|group| group.name.clone()).collect())
+    }
+
+    fn group_exists(&self, group: &str) -> Result<bool> {
+        Ok(self.domain().groups.contains_key(&group.to_lowercase()))
+    }
+
+    fn create_group(&self, group: &str) -> Result<()> {
+        validate::group_name(group)?;
+        let mut domain = self.change(&[group])?;
+
+        let key = group.to_lowercase();
+        if domain.groups.contains_key(&key) || domain.users.contains_key(&key) {
+            return Err(Error::AlreadyExists(format!("group {}", group)));
+        }
+
+        domain.groups.insert(key, FakeGroup { name: group.to_string(), members: Vec::new() });
+        Ok(())
+    }
+
+    fn delete_group(&self, group: &str) -> Result<()> {
+        let mut domain = self.change(&[group])?;
+
+        domain.groups
+            .remove(&group.to_lowercase())
+            .map(|_| ())
+            .ok_or_else(|| Error::NotFound(format!("group {}", group)))
+    }
+
+    fn list_members(&self, group: &str) -> Result<Vec<String>> {
+        let mut domain = self.domain();
+        let members = domain.group_mut(group)?.members.clone();
+
+        // Every account has the primary group, without being listed as member
+        if names_equal(group, PRIMARY_GROUP) {
+            return Ok(domain.users.values().map(|user| user.name.clone()).collect());
+        }
+        Ok(members)
+    }
+
+    fn is_member(&self, group: &str, username: &str) -> Result<bool> {
+        Ok(contains_name(&self.list_members(group)?, username))
+    }
+
+    fn add_member(&self, group: &str, username: &str) -> Result<()> {
+        let mut domain = self.change(&[group, username])?;
+        if !domain.users.contains_key(&username.to_lowercase()) {
+            return Err(Error::NotFound(format!("user {}", username)));
+        }
+
+        let group = domain.group_mut(group)?;
+        if contains_name(&group.members, username) {
+            return Err(Error::AlreadyExists(format!("{} in group {}", username, group.name)));
+        }
+
+        group.members.push(username.to_string());
+        Ok(())
+    }
+
+    fn remove_member(&self, group: &str, username: &str) -> Result<()> {
+        let mut domain = self.change(&[group, username])?;
+
+        let group = domain.group_mut(group)?;
+        if !contains_name(&group.members, username) {
+            return Err(Error::NotFound(format!("{} in group {}", username, group.name)));
+        }
+
+        group.members.retain(|member| !names_equal(member, username));
+        Ok(())
+    }
+
+    fn password_policy(&self) -> Result<DomainPasswordPolicy> {
+        Ok(DomainPasswordPolicy::default())
+    }
+}
@@ -0,0 +1,545 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+use ldap3::{LdapResult, Mod, Scope, SearchEntry};
+use crate::error::{Error, Result};
+use super::{AttrValue, LdapConnection};
+
+/// Sub-authorities of the domain SID, S-1-5-21-1-2-3
+const DOMAIN_SUB_AUTHORITIES: [u32; 4] = [21, 1, 2, 3];
+
+/// Relative identifier of `Domain Users`, the primary group of new users
+const DOMAIN_USERS_RID: u32 = 513;
+
+/// Relative identifier given to the first object added
+const FIRST_RID: u32 = 1100;
+
+/// Attributes that can be written but are never returned by a search
+const WRITE_ONLY: &[&str] = &["unicodePwd"];
+
+// Result codes of RFC 4511
+const NO_SUCH_ATTRIBUTE: u32 = 16;
+const CONSTRAINT_VIOLATION: u32 = 19;
+const ATTRIBUTE_OR_VALUE_EXISTS: u32 = 20;
+const NO_SUCH_OBJECT: u32 = 32;
+const UNWILLING_TO_PERFORM: u32 = 53;
+const NOT_ALLOWED_ON_NON_LEAF: u32 = 66;
+const ENTRY_ALREADY_EXISTS: u32 = 68;
+
+#[derive(Debug, Clone)]
+struct Entry {
+    dn: String,
+    /// Keyed by the lowercase name, as attribute names are case-insensitive
+    attrs: BTreeMap<String, (String, Vec<Vec<u8>>)>
+}
+
+impl Entry {
+    fn new(dn: &str, attrs: &[(&str, &[&[u8]])]) -> Entry {
+        let mut entry = Entry { dn: dn.to_string(), attrs: BTreeMap::new() };
+        for (attr, values) in attrs {
+            entry.set(attr, values.iter().map(|value| value.to_vec()).collect());
+        }
+        entry
+    }
+
+    fn values(&self, attr: &str) -> &[Vec<u8>] {
+        self.attrs.get(&attr.to_lowercase()).map_or(&[], |(_, values)| values)
+    }
+
+    ///
+    /// Set the values of an attribute, removing it when there are none
+    ///
+    fn set(&mut self, attr: &str, values: Vec<Vec<u8>>) {
+        let key = attr.to_lowercase();
+        if values.is_empty() {
+            self.attrs.remove(&key);
+        } else {
+            let name = self.attrs.get(&key).map_or(attr, |(name, _)| name).to_string();
+            self.attrs.insert(key, (name, values));
+        }
+    }
+
+    fn is_a(&self, class: &str) -> bool {
+        self.values("objectClass").iter().any(|value| value.eq_ignore_ascii_case(class.as_bytes()))
+    }
+}
+
+#[derive(Debug)]
+struct Directory {
+    /// Keyed by the lowercase DN
+    entries: BTreeMap<String, Entry>,
+    next_rid: u32
+}
+
+impl Directory {
+    fn entry(&self, dn: &str) -> Result<&Entry> {
+        self.entries
+            .get(&dn.to_lowercase())
+            .ok_or_else(|| failure(NO_SUCH_OBJECT, &format!("no such object {}", dn)))
+    }
+
+    ///
+    /// Values of an attribute, with `memberOf` computed from the groups
+    ///
+    fn values(&self, entry: &Entry, attr: &str) -> Vec<Vec<u8>> {
+        if !attr.eq_ignore_ascii_case("memberOf") {
+            return entry.values(attr).to_vec();
+        }
+
+        self.entries
+            .values()
+            .filter(|group| group.values("member").iter().any(|member| same_dn(member, &entry.dn)))
+            .map(|group| group.dn.as_bytes().to_vec())
+            .collect()
+    }
+
+    fn matches(&self, entry: &Entry, filter: &Filter) -> bool {
+        match filter {
+            Filter::And(filters) => filters.iter().all(|filter| self.matches(entry, filter)),
+            Filter::Or(filters) => filters.iter().any(|filter| self.matches(entry, filter)),
+            Filter::Not(filter) => !self.matches(entry, filter),
+            Filter::Present(attr) => !self.values(entry, attr).is_empty(),
+            Filter::Equal(attr, value) => self.values(entry, attr).iter().any(|candidate| {
+                // SIDs are compared as bytes, strings ignoring case
+                if attr.eq_ignore_ascii_case("objectSid") {
+                    candidate == value
+                } else {
+                    candidate.eq_ignore_ascii_case(value)
+                }
+            })
+        }
+    }
+
+    fn account_name_taken(&self, name: &[u8]) -> bool {
+        self.entries
+            .values()
+            .any(|entry| entry.values("sAMAccountName").iter().any(|taken| taken.eq_ignore_ascii_case(name)))
+    }
+
+    ///
+    /// Check a new `unicodePwd` against the minimum length of the domain
+    ///
+    fn check_password(&self, values: &[Vec<u8>]) -> Result<()> {
+        let min_length: usize = self.entries
+            .values()
+            .find(|entry| entry.is_a("domain"))
+            .and_then(|domain| domain.values("minPwdLength").first().cloned())
+            .and_then(|length| String::from_utf8(length).ok()?.parse().ok())
+            .unwrap_or(0);
+
+        for value in values {
+            match decode_password(value) {
+                Some(password) if password.chars().count() >= min_length => {},
+                Some(_) => {
+                    return Err(failure(CONSTRAINT_VIOLATION, "check_password_restrictions: the password is too short"));
+                },
+                None => return Err(failure(CONSTRAINT_VIOLATION, "unicodePwd is not a quoted UTF-16LE string"))
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&self, entry: &mut Entry, change: Mod<AttrValue>) -> Result<()> {
+        match change {
+            Mod::Add(attr, values) => {
+                let attr = text(&attr);
+                let mut current = entry.values(&attr).to_vec();
+                for value in values {
+                    if current.iter().any(|existing| existing.eq_ignore_ascii_case(value.as_ref())) {
+                        return Err(failure(ATTRIBUTE_OR_VALUE_EXISTS, &format!("{} already has this value", attr)));
+                    }
+                    current.push(value.as_ref().to_vec());
+                }
+                entry.set(&attr, current);
+            },
+            Mod::Delete(attr, values) => {
+                let attr = text(&attr);
+                let mut current = entry.values(&attr).to_vec();
+                if current.is_empty() {
+                    return Err(failure(NO_SUCH_ATTRIBUTE, &format!("no attribute {}", attr)));
+                }
+                if values.is_empty() {
+                    current.clear();
+                }
+                for value in values {
+                    let before = current.len();
+                    current.retain(|existing| !existing.eq_ignore_ascii_case(value.as_ref()));
+                    if current.len() == before {
+                        return Err(failure(NO_SUCH_ATTRIBUTE, &format!("{} does not have this value", attr)));
+                    }
+                }
+                entry.set(&attr, current);
+            },
+            Mod::Replace(attr, values) => {
+                let attr = text(&attr);
+                let values: Vec<Vec<u8>> = values.iter().map(|value| value.as_ref().to_vec()).collect();
+                if attr.eq_ignore_ascii_case("unicodePwd") {
+                    self.check_password(&values)?;
+                }
+                entry.set(&attr, values);
+            },
+            Mod::Increment(attr, _) => {
+                return Err(failure(UNWILLING_TO_PERFORM, &format!("cannot increment {}", text(&attr))));
+            }
+        }
+        Ok(())
+    }
+}
+
+///
+/// LDAP server kept in memory, for tests: a domain with the `CN=Users`
+/// container and the built-in `Domain Users` group (RID 513).
+///
+/// Like AD, users and groups get an `objectSid` when added, users have
+/// `Domain Users` as primary group without being listed as its members,
+/// `memberOf` is computed from the `member` links, and passwords shorter
+/// than `minPwdLength` are refused. Filters are limited to `&`, `|`, `!`,
+/// presence and equality.
+///
+/// Clones share the same directory, so a test can inspect it after
+/// handing a clone to the backend.
+///
+#[derive(Debug, Clone)]
+pub struct FakeLdap {
+    directory: Arc<Mutex<Directory>>
+}
+
+impl FakeLdap {
+    ///
+    /// Create a domain with no users
+    ///
+    /// Arguments:
+    /// * `base_dn`: naming context of the domain, e.g. `DC=samdom,DC=example,DC=com`
+    ///
+    pub fn new(base_dn: &str) -> FakeLdap {
+        let users = format!("CN=Users,{}", base_dn);
+        let domain_users = format!("CN=Domain Users,{}", users);
+
+        let entries = [
+            Entry::new(base_dn, &[
+                ("objectClass", &[b"top", b"domain", b"domainDNS"]),
+                ("objectSid", &[&sid(&[])]),
+                ("pwdProperties", &[b"1"]),
+                ("minPwdLength", &[b"7"]),
+                ("pwdHistoryLength", &[b"24"])
+            ]),
+            Entry::new(&users, &[("objectClass", &[b"top", b"container"]), ("cn", &[b"Users"])]),
+            Entry::new(&domain_users, &[
+                ("objectClass", &[b"top", b"group"]),
+                ("cn", &[b"Domain Users"]),
+                ("sAMAccountName", &[b"Domain Users"]),
+                ("objectSid", &[&sid(&[DOMAIN_USERS_RID])])
+            ])
+        ];
+
+        FakeLdap {
+            directory: Arc::new(Mutex::new(Directory {
+                entries: entries.into_iter().map(|entry| (entry.dn.to_lowercase(), entry)).collect(),
+                next_rid: FIRST_RID
+            }))
+        }
+    }
+
+    ///
+    /// Get the values of an attribute, write-only ones included
+    ///
+    /// Arguments:
+    /// * `dn`: distinguished name of the entry
+    /// * `attr`: attribute name, in any case
+    ///
+    /// Returns:
+    /// * the values, empty if the entry or the attribute is missing
+    ///
+    pub fn attribute(&self, dn: &str, attr: &str) -> Vec<Vec<u8>> {
+        let directory = self.directory();
+        directory.entry(dn).map_or_else(|_| Vec::new(), |entry| directory.values(entry, attr))
+    }
+
+    fn directory(&self) -> MutexGuard<'_, Directory> {
+        self.directory.lock().expect("fake ldap poisoned")
+    }
+}
+
+impl LdapConnection for FakeLdap {
+    fn search(&mut self, base: &str, scope: Scope, filter: &str, attrs: &[&str]) -> Result<Vec<SearchEntry>> {
+        let filter = Filter::parse(filter)?;
+        let directory = self.directory();
+        directory.entry(base)?;
+
+        let in_scope = |dn: &str| match scope {
+            Scope::Base => same_dn(dn.as_bytes(), base),
+            Scope::OneLevel => parent(dn).is_some_and(|parent| same_dn(parent.as_bytes(), base)),
+            Scope::Subtree => {
+                same_dn(dn.as_bytes(), base) || dn.to_lowercase().ends_with(&format!(",{}", base.to_lowercase()))
+            }
+        };
+
+        Ok(directory.entries
+            .values()
+            .filter(|entry| in_scope(&entry.dn) && directory.matches(entry, &filter))
+            .map(|entry| {
+                let mut names: Vec<String> = if attrs.contains(&"*") {
+                    let stored = entry.attrs.values().map(|(name, _)| name.clone());
+                    stored.chain(["memberOf".to_string()]).collect()
+                } else {
+                    Vec::new()
+                };
+                names.extend(attrs.iter().filter(|attr| !["*", "1.1"].contains(attr)).map(|attr| {
+                    entry.attrs.get(&attr.to_lowercase()).map_or(attr.to_string(), |(name, _)| name.clone())
+                }));
+                names.retain(|name| !WRITE_ONLY.iter().any(|hidden| hidden.eq_ignore_ascii_case(name)));
+
+                let mut found = SearchEntry { dn: entry.dn.clone(), attrs: HashMap::new(), bin_attrs: HashMap::new() };
+                for name in names {
+                    let values = directory.values(entry, &name);
+                    if values.is_empty() {
+                        continue;
+                    }
+
+                    // Like ldap3, values that are not UTF-8 are returned as binary
+                    match values.iter().map(|value| String::from_utf8(value.clone())).collect() {
+                        Ok(text) => {
+                            found.attrs.insert(name, text);
+                        },
+                        Err(_) => {
+                            found.bin_attrs.insert(name, values);
+                        }
+                    }
+                }
+                found
+            })
+            .collect())
+    }
+
+    fn add(&mut self, dn: &str, attrs: Vec<(AttrValue, HashSet<AttrValue>)>) -> Result<()> {
+        let mut directory = self.directory();
+        if directory.entries.contains_key(&dn.to_lowercase()) {
+            return Err(failure(ENTRY_ALREADY_EXISTS, &format!("{} already exists", dn)));
+        }
+        if let Some(parent) = parent(dn) {
+            directory.entry(parent)?;
+        }
+
+        let mut entry = Entry { dn: dn.to_string(), attrs: BTreeMap::new() };
+        for (attr, values) in &attrs {
+            entry.set(&text(attr), values.iter().map(|value| value.as_ref().to_vec()).collect());
+        }
+
+        if entry.values("sAMAccountName").iter().any(|name| directory.account_name_taken(name)) {
+            return Err(failure(ENTRY_ALREADY_EXISTS, &format!("account name of {} already in use", dn)));
+        }
+        directory.check_password(entry.values("unicodePwd"))?;
+
+        if entry.is_a("user") || entry.is_a("group") {
+            entry.set("objectSid", vec![sid(&[directory.next_rid])]);
+            directory.next_rid += 1;
+        }
+        if entry.is_a("user") && entry.values("primaryGroupID").is_empty() {
+            entry.set("primaryGroupID", vec![DOMAIN_USERS_RID.to_string().into_bytes()]);
+        }
+
+        directory.entries.insert(dn.to_lowercase(), entry);
+        Ok(())
+    }
+
+    fn modify(&mut self, dn: &str, mods: Vec<Mod<AttrValue>>) -> Result<()> {
+        let mut directory = self.directory();
+        let mut entry = directory.entry(dn)?.clone();
+
+        // Changes are atomic: the entry is replaced only if all of them apply
+        for change in mods {
+            directory.apply(&mut entry, change)?;
+        }
+
+        directory.entries.insert(dn.to_lowercase(), entry);
+        Ok(())
+    }
+
+    fn delete(&mut self, dn: &str) -> Result<()> {
+        let mut directory = self.directory();
+        directory.entry(dn)?;
+
+        if directory.entries.values().any(|entry| parent(&entry.dn).is_some_and(|parent| same_dn(parent.as_bytes(), dn))) {
+            return Err(failure(NOT_ALLOWED_ON_NON_LEAF, &format!("{} has children", dn)));
+        }
+
+        directory.entries.remove(&dn.to_lowercase());
+        for entry in directory.entries.values_mut() {
+            let members: Vec<Vec<u8>> = entry.values("member")
+                .iter()
+                .filter(|member| !same_dn(member, dn))
+                .cloned()
+                .collect();
+            if members.len() != entry.values("member").len() {
+                entry.set("member", members);
+            }
+        }
+        Ok(())
+    }
+}
+
+///
+/// Search filter, limited to what the backend sends
+///
+#[derive(Debug)]
+enum Filter {
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+    Present(String),
+    Equal(String, Vec<u8>)
+}
+
+impl Filter {
+    ///
+    /// Parse a filter in the string form of RFC 4515
+    ///
+    fn parse(filter: &str) -> Result<Filter> {
+        let (parsed, rest) = Filter::parse_one(filter.as_bytes())
+            .ok_or_else(|| Error::Ldap(format!("unsupported filter {}", filter)))?;
+
+        if !rest.is_empty() {
+            return Err(Error::Ldap(format!("unsupported filter {}", filter)));
+        }
+        Ok(parsed)
+    }
+
+    fn parse_one(input: &[u8]) -> Option<(Filter, &[u8])> {
+        let input = input.strip_prefix(b"(")?;
+
+        let (filter, rest) = match input.first()? {
+            b'&' => {
+                let (filters, rest) = Filter::parse_list(&input[1..])?;
+                (Filter::And(filters), rest)
+            },
+            b'|' => {
+                let (filters, rest) = Filter::parse_list(&input[1..])?;
+                (Filter::Or(filters), rest)
+            },
+            b'!' => {
+                let (filter, rest) = Filter::parse_one(&input[1..])?;
+                (Filter::Not(Box::new(filter)), rest)
+            },
+            _ => {
+                let end = input.iter().position(|byte| *byte == b')')?;
+                (Filter::parse_item(&input[..end])?, &input[end..])
+            }
+        };
+
+        Some((filter, rest.strip_prefix(b")")?))
+    }
+
+    fn parse_list(mut input: &[u8]) -> Option<(Vec<Filter>, &[u8])> {
+        let mut filters = Vec::new();
+        while input.first() == Some(&b'(') {
+            let (filter, rest) = Filter::parse_one(input)?;
+            filters.push(filter);
+            input = rest;
+        }
+        Some((filters, input))
+    }
+
+    fn parse_item(item: &[u8]) -> Option<Filter> {
+        let equals = item.iter().position(|byte| *byte == b'=')?;
+        let attr = std::str::from_utf8(&item[..equals]).ok()?;
+        let value = &item[equals + 1..];
+
+        // Approximate, ordering and extensible matches are not supported
+        if attr.is_empty() || attr.contains(['~', '<', '>', ':']) {
+            return None;
+        }
+
+        match value {
+            b"*" => Some(Filter::Present(attr.to_string())),
+            _ if value.contains(&b'*') => None,
+            _ => Some(Filter::Equal(attr.to_string(), unescape(value)?))
+        }
+    }
+}
+
+///
+/// Decode the `\xx` escapes of a filter value
+///
+fn unescape(value: &[u8]) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'\\' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    Some(bytes)
+}
+
+///
+/// Get the parent of a DN: what follows its first unescaped comma
+///
+fn parent(dn: &str) -> Option<&str> {
+    let bytes = dn.as_bytes();
+    let mut escaped = false;
+
+    for (index, byte) in bytes.iter().enumerate() {
+        match byte {
+            b'\\' if !escaped => escaped = true,
+            b',' if !escaped => return Some(&dn[index + 1..]),
+            _ => escaped = false
+        }
+    }
+    None
+}
+
+fn same_dn(dn: &[u8], other: &str) -> bool {
+    dn.eq_ignore_ascii_case(other.as_bytes())
+}
+
+fn text(value: &AttrValue) -> String {
+    String::from_utf8_lossy(value.as_ref()).into_owned()
+}
+
+///
+/// Decode a `unicodePwd` value: a quoted UTF-16LE string
+///
+fn decode_password(value: &[u8]) -> Option<String> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+
+    let units: Vec<u16> = value.chunks(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+    let quoted = String::from_utf16(&units).ok()?;
+    Some(quoted.strip_prefix('"')?.strip_suffix('"')?.to_string())
+}
+
+///
+/// Build a binary SID of the domain
+///
+/// Arguments:
+/// * `rid`: relative identifier of the object, empty for the domain itself
+///
+fn sid(rid: &[u32]) -> Vec<u8> {
+    let sub_authorities: Vec<u32> = DOMAIN_SUB_AUTHORITIES.iter().chain(rid).copied().collect();
+
+    // Revision 1, count of sub-authorities, NT authority (5), sub-authorities
+    let mut sid = vec![1, sub_authorities.len() as u8, 0, 0, 0, 0, 0, 5];
+    for sub_authority in sub_authorities {
+        sid.extend_from_slice(&sub_authority.to_le_bytes());
+    }
+    sid
+}
+
+///
+/// Error the directory reports for a failed operation
+///
+fn failure(rc: u32, text: &str) -> Error {
+    Error::from(LdapResult {
+        rc,
+        matched: String::new(),
+        text: text.to_string(),
+        refs: Vec::new(),
+        ctrls: Vec::new()
+    })
+}
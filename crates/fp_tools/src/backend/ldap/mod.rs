@@ -0,0 +1,779 @@
+pub mod fake;
+
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, MutexGuard};
+use ldap3::adapters::{Adapter, EntriesOnly, PagedResults};
+use ldap3::{dn_escape, ldap_escape, LdapConn, LdapConnSettings, Mod, Scope, SearchEntry};
+use zeroize::Zeroizing;
+use crate::commands::parse::{self, LdifEntry, UserRecord};
+use crate::commands::user::{self, DomainUser};
+use crate::commands::validate;
+use crate::config::tools_config::{LdapSettings, ToolsConfiguration};
+use crate::error::{Error, Result};
+use crate::password::policy::DomainPasswordPolicy;
+use super::DirectoryBackend;
+
+/// Page size used for searches, AD refuses more than 1000 entries at once
+const PAGE_SIZE: i32 = 500;
+
+/// Normal enabled account, see the `userAccountControl` documentation
+const UF_NORMAL_ACCOUNT: &str = "512";
+
+/// Global security group, see the `groupType` documentation
+const GROUP_TYPE_GLOBAL_SECURITY: &str = "-2147483646";
+
+/// `pwdProperties` flag requiring complex passwords
+const DOMAIN_PASSWORD_COMPLEX: i64 = 1;
+
+const USER_FILTER: &str = "(&(objectClass=user)(!(objectClass=computer)))";
+const GROUP_FILTER: &str = "(objectClass=group)";
+
+///
+/// Operations of an LDAP connection used by the backend, so that it can
+/// run against a domain controller, a test server or `fake::FakeLdap`
+///
+/// Failures are reported as the directory reports them, the result code
+/// mapped to an `Error` (e.g. 68 to `Error::AlreadyExists`).
+///
+pub trait LdapConnection: Send {
+    ///
+    /// Search under `base`, fetching every page of the results
+    ///
+    fn search(&mut self, base: &str, scope: Scope, filter: &str, attrs: &[&str]) -> Result<Vec<SearchEntry>>;
+
+    ///
+    /// Add an entry with the given attributes
+    ///
+    fn add(&mut self, dn: &str, attrs: Vec<(AttrValue, HashSet<AttrValue>)>) -> Result<()>;
+
+    ///
+    /// Change the attributes of an entry
+    ///
+    fn modify(&mut self, dn: &str, mods: Vec<Mod<AttrValue>>) -> Result<()>;
+
+    ///
+    /// Delete an entry
+    ///
+    fn delete(&mut self, dn: &str) -> Result<()>;
+}
+
+impl LdapConnection for LdapConn {
+    fn search(&mut self, base: &str, scope: Scope, filter: &str, attrs: &[&str]) -> Result<Vec<SearchEntry>> {
+        let adapters: Vec<Box<dyn Adapter<_, _>>> = vec![
+            Box::new(EntriesOnly::new()),
+            Box::new(PagedResults::new(PAGE_SIZE))
+        ];
+
+        let mut stream = self.streaming_search_with(adapters, base, scope, filter, attrs.to_vec())?;
+
+        let mut entries = Vec::new();
+        while let Some(entry) = stream.next()? {
+            entries.push(SearchEntry::construct(entry));
+        }
+        stream.result().success()?;
+
+        Ok(entries)
+    }
+
+    fn add(&mut self, dn: &str, attrs: Vec<(AttrValue, HashSet<AttrValue>)>) -> Result<()> {
+        LdapConn::add(self, dn, attrs)?.success()?;
+        Ok(())
+    }
+
+    fn modify(&mut self, dn: &str, mods: Vec<Mod<AttrValue>>) -> Result<()> {
+        LdapConn::modify(self, dn, mods)?.success()?;
+        Ok(())
+    }
+
+    fn delete(&mut self, dn: &str) -> Result<()> {
+        LdapConn::delete(self, dn)?.success()?;
+        Ok(())
+    }
+}
+
+///
+/// Directory backend talking to the domain controller over LDAP(S)
+///
+/// Passwords are written to `unicodePwd`, so the DC only accepts them on
+/// an encrypted connection (`ldaps://` or StartTLS).
+///
+pub struct LdapBackend {
+    config: ToolsConfiguration,
+    settings: LdapSettings,
+    conn: Mutex<Box<dyn LdapConnection>>
+}
+
+impl LdapBackend {
+    ///
+    /// Connect and bind to the directory described by the configuration
+    ///
+    /// Arguments:
+    /// * `config`: system configuration, the `ldap` section is required
+    ///
+    pub fn new(config: &ToolsConfiguration) -> Result<LdapBackend> {
+        let settings = settings(config)?;
+
+        let conn_settings = LdapConnSettings::new()
+            .set_starttls(settings.start_tls)
+            .set_no_tls_verify(settings.no_tls_verify);
+
+        let mut conn = LdapConn::with_settings(conn_settings, &settings.url)?;
+        conn.simple_bind(&settings.bind_dn, &settings.bind_pass)?.success()?;
+
+        LdapBackend::with_connection(config, Box::new(conn))
+    }
+
+    ///
+    /// Use a connection that is already bound
+    ///
+    /// Arguments:
+    /// * `config`: system configuration, the `ldap` section is required
+    /// * `conn`: bound connection, e.g. a `FakeLdap` in tests
+    ///
+    pub fn with_connection(config: &ToolsConfiguration, conn: Box<dyn LdapConnection>) -> Result<LdapBackend> {
+        Ok(LdapBackend {
+            config: config.clone(),
+            settings: settings(config)?,
+            conn: Mutex::new(conn)
+        })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Box<dyn LdapConnection>> {
+        self.conn.lock().expect("ldap connection poisoned")
+    }
+
+    ///
+    /// Distinguished name for a new object in the users container
+    ///
+    fn object_dn(&self, name: &str) -> String {
+        format!(
+            "CN={},{},{}",
+            dn_escape(name),
+            self.settings.users_container,
+            self.settings.base_dn
+        )
+    }
+
+    ///
+    /// Run a paged subtree search under the base DN
+    ///
+    fn search(&self, filter: &str, attrs: &[&str]) -> Result<Vec<SearchEntry>> {
+        self.conn().search(&self.settings.base_dn, Scope::Subtree, filter, attrs)
+    }
+
+    ///
+    /// Find the distinguished name of an object by its account name
+    ///
+    fn find_dn(&self, class_filter: &str, name: &str) -> Result<Option<String>> {
+        let filter = format!("(&{}(sAMAccountName={}))", class_filter, ldap_escape(name));
+
+        Ok(self.search(&filter, &["1.1"])?
+            .into_iter()
+            .next()
+            .map(|entry| entry.dn))
+    }
+
+    fn user_dn(&self, username: &str) -> Result<String> {
+        self.find_dn(USER_FILTER, username)?
+            .ok_or_else(|| Error::NotFound(format!("user {}", username)))
+    }
+
+    fn group_dn(&self, group: &str) -> Result<String> {
+        self.find_dn(GROUP_FILTER, group)?
+            .ok_or_else(|| Error::NotFound(format!("group {}", group)))
+    }
+
+    fn account_names(&self, filter: &str) -> Result<Vec<String>> {
+        Ok(self.search(filter, &["sAMAccountName"])?
+            .into_iter()
+            .filter_map(|mut entry| entry.attrs.remove("sAMAccountName"))
+            .flatten()
+            .collect())
+    }
+}
+
+impl DirectoryBackend for LdapBackend {
+    fn list_users(&self) -> Result<Vec<String>> {
+        self.account_names(USER_FILTER)
+    }
+
+    fn user_exists(&self, username: &str) -> Result<bool> {
+        Ok(self.find_dn(USER_FILTER, username)?.is_some())
+    }
+
+    fn show_user(&self, username: &str) -> Result<UserRecord> {
+        let filter = format!("(&{}(sAMAccountName={}))", USER_FILTER, ldap_escape(username));
+
+        self.search(&filter, &["*"])?
+            .into_iter()
+            .map(|entry| {
+                let mut attributes: Vec<(String, String)> = entry.attrs
+                    .into_iter()
+                    .flat_map(|(attr, values)| {
+                        values.into_iter().map(move |value| (attr.clone(), value))
+                    })
+                    .collect();
+                attributes.sort();
+
+                LdifEntry { dn: entry.dn, attributes }
+            })
+            .find_map(UserRecord::from_entry)
+            .ok_or_else(|| Error::NotFound(format!("user {}", username)))
+    }
+
+    fn create_user(&self, user: &DomainUser) -> Result<()> {
+        let home_directory = user::home_directory(&self.config, &user.common_name);
+        let profile_path = user::profile_path(&self.config, &user.common_name);
+
+        validate::account_name(&user.common_name)?;
+        validate::unc_path(&home_directory)?;
+        validate::unc_path(&profile_path)?;
+
+        let dn = self.object_dn(&user.common_name);
+        let upn = format!("{}@{}", user.common_name, self.config.domain_fqdn);
+
+        let attrs: Vec<(AttrValue, HashSet<AttrValue>)> = vec![
+            values("objectClass", &["top", "person", "organizationalPerson", "user"]),
+            values("cn", &[&user.common_name]),
+            values("sAMAccountName", &[&user.common_name]),
+            values("userPrincipalName", &[&upn]),
+            values("sn", &[&user.last_name]),
+            values("givenName", &[&user.first_name]),
+            values("homeDrive", &[user::HOME_DRIVE]),
+            values("homeDirectory", &[&home_directory]),
+            values("profilePath", &[&profile_path]),
+            (AttrValue::from("unicodePwd"), HashSet::from([AttrValue(encode_password(&user.password))])),
+            values("userAccountControl", &[UF_NORMAL_ACCOUNT])
+        ];
+
+        self.conn().add(&dn, attrs)
+    }
+
+    fn delete_user(&self, username: &str) -> Result<()> {
+        let dn = self.user_dn(username)?;
+        self.conn().delete(&dn)
+    }
+
+    fn set_names(&self, username: &str, surname: &str, given_name: &str) -> Result<()> {
+        let dn = self.user_dn(username)?;
+        let mods = vec![
+            Mod::Replace(AttrValue::from("sn"), HashSet::from([AttrValue::from(surname)])),
+            Mod::Replace(AttrValue::from("givenName"), HashSet::from([AttrValue::from(given_name)]))
+        ];
+
+        self.conn().modify(&dn, mods)
+    }
+
+    fn set_password(&self, username: &str, password: &str) -> Result<()> {
+        let dn = self.user_dn(username)?;
+        let mods = vec![Mod::Replace(
+            AttrValue::from("unicodePwd"),
+            HashSet::from([AttrValue(encode_password(password))])
+        )];
+
+        self.conn().modify(&dn, mods)
+    }
+
+    fn user_groups(&self, username: &str) -> Result<Vec<String>> {
+        let filter = format!("(&{}(sAMAccountName={}))", USER_FILTER, ldap_escape(username));
+        let entry = self.search(&filter, &["primaryGroupID", "objectSid"])?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::NotFound(format!("user {}", username)))?;
+
+        // The primary group (Domain Users) is not a `member` link, but
+        // samba-tool lists it first
+        let mut groups = match primary_group_filter(&entry) {
+            Some(filter) => self.account_names(&filter)?,
+            None => Vec::new()
+        };
+
+        let filter = format!("(&{}(member={}))", GROUP_FILTER, ldap_escape(&entry.dn));
+        for group in self.account_names(&filter)? {
+            if !parse::contains_name(&groups, &group) {
+                groups.push(group);
+            }
+        }
+
+        Ok(groups)
+    }
+
+    fn list_groups(&self) -> Result<Vec<String>> {
+        self.account_names(GROUP_FILTER)
+    }
+
+    fn group_exists(&self, group: &str) -> Result<bool> {
+        Ok(self.find_dn(GROUP_FILTER, group)?.is_some())
+    }
+
+    fn create_group(&self, group: &str) -> Result<()> {
+        validate::group_name(group)?;
+
+        let dn = self.object_dn(group);
+        let attrs: Vec<(AttrValue, HashSet<AttrValue>)> = vec![
+            values("objectClass", &["top", "group"]),
+            values("cn", &[group]),
+            values("sAMAccountName", &[group]),
+            values("groupType", &[GROUP_TYPE_GLOBAL_SECURITY])
+        ];
+
+        self.conn().add(&dn, attrs)
+    }
+
+    fn delete_group(&self, group: &str) -> Result<()> {
+        let dn = self.group_dn(group)?;
+        self.conn().delete(&dn)
+    }
+
+    fn list_members(&self, group: &str) -> Result<Vec<String>> {
+        let filter = format!("(&{}(sAMAccountName={}))", GROUP_FILTER, ldap_escape(group));
+        let entry = self.search(&filter, &["objectSid"])?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::NotFound(format!("group {}", group)))?;
+
+        // Users whose primary group this is have no `memberOf` link to it
+        let mut filter = format!("(memberOf={})", ldap_escape(&entry.dn));
+        if let Some(rid) = object_sid(&entry).as_deref().and_then(relative_id) {
+            filter = format!("(|{}(&{}(primaryGroupID={})))", filter, USER_FILTER, rid);
+        }
+
+        self.account_names(&filter)
+    }
+
+    fn is_member(&self, group: &str, username: &str) -> Result<bool> {
+        Ok(parse::contains_name(&self.list_members(group)?, username))
+    }
+
+    fn add_member(&self, group: &str, username: &str) -> Result<()> {
+        let group_dn = self.group_dn(group)?;
+        let user_dn = self.user_dn(username)?;
+        let mods = vec![Mod::Add(AttrValue::from("member"), HashSet::from([AttrValue::from(user_dn.as_str())]))];
+
+        self.conn().modify(&group_dn, mods)
+    }
+
+    fn remove_member(&self, group: &str, username: &str) -> Result<()> {
+        let group_dn = self.group_dn(group)?;
+        let user_dn = self.user_dn(username)?;
+        let mods = vec![Mod::Delete(AttrValue::from("member"), HashSet::from([AttrValue::from(user_dn.as_str())]))];
+
+        self.conn().modify(&group_dn, mods)
+    }
+
+    fn password_policy(&self) -> Result<DomainPasswordPolicy> {
+        // The policy is stored on the domain object itself
+        let entries = self.conn().search(
+            &self.settings.base_dn,
+            Scope::Base,
+            "(objectClass=*)",
+            &["pwdProperties", "minPwdLength", "pwdHistoryLength"]
+        )?;
+
+        let entry = entries
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::NotFound(format!("domain object {}", self.settings.base_dn)))?;
+
+        let number = |attr: &str| -> Option<i64> {
+            entry.attrs.get(attr)?.first()?.parse().ok()
+        };
+
+        let mut policy = DomainPasswordPolicy::default();
+        if let Some(properties) = number("pwdProperties") {
+            policy.complexity = properties & DOMAIN_PASSWORD_COMPLEX != 0;
+        }
+        if let Some(length) = number("minPwdLength") {
+            policy.min_length = length.max(0) as usize;
+        }
+        if let Some(length) = number("pwdHistoryLength") {
+            policy.history_length = length.max(0) as usize;
+        }
+
+        Ok(policy)
+    }
+}
+
+///
+/// Encode a password the way AD expects it in `unicodePwd`:
+/// surrounded by double quotes and encoded as UTF-16LE
+///
+/// Arguments:
+/// * `password`: clear text password
+///
+/// Returns:
+/// * the encoded attribute value, wiped from memory when dropped
+///
+pub fn encode_password(password: &str) -> Zeroizing<Vec<u8>> {
+    let quoted = Zeroizing::new(format!("\"{}\"", password));
+
+    Zeroizing::new(quoted
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect())
+}
+
+///
+/// Value of an attribute sent to the directory. Values may hold a
+/// password, so they are wiped from memory when dropped.
+///
+#[derive(PartialEq, Eq)]
+pub struct AttrValue(Zeroizing<Vec<u8>>);
+
+impl From<&str> for AttrValue {
+    fn from(value: &str) -> AttrValue {
+        AttrValue(Zeroizing::new(value.as_bytes().to_vec()))
+    }
+}
+
+impl AsRef<[u8]> for AttrValue {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Hash for AttrValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_slice().hash(state);
+    }
+}
+
+///
+/// `ldap` section of the configuration, required by this backend
+///
+fn settings(config: &ToolsConfiguration) -> Result<LdapSettings> {
+    config.ldap.clone().ok_or_else(|| {
+        Error::Config("ldap backend selected but no 'ldap' section found".to_string())
+    })
+}
+
+fn values(attr: &str, values: &[&str]) -> (AttrValue, HashSet<AttrValue>) {
+    (
+        AttrValue::from(attr),
+        values.iter().map(|value| AttrValue::from(*value)).collect()
+    )
+}
+
+///
+/// Build the filter finding the primary group of a user, from the
+/// `objectSid` and `primaryGroupID` attributes of its entry
+///
+/// Returns:
+/// * the filter, `None` if the attributes are missing or malformed
+///
+fn primary_group_filter(entry: &SearchEntry) -> Option<String> {
+    let rid: u32 = entry.attrs.get("primaryGroupID")?.first()?.parse().ok()?;
+    let group_sid = primary_group_sid(&object_sid(entry)?, rid)?;
+    let escaped: String = group_sid.iter().map(|byte| format!("\\{:02x}", byte)).collect();
+    Some(format!("(&{}(objectSid={}))", GROUP_FILTER, escaped))
+}
+
+///
+/// Build the SID of a group of the domain of a user: the user SID, with
+/// the relative identifier of the group in place of the one of the user
+///
+/// Arguments:
+/// * `user_sid`: binary SID of the user
+/// * `rid`: relative identifier of the group, e.g. 513 for Domain Users
+///
+/// Returns:
+/// * the binary SID of the group, `None` if `user_sid` is malformed
+///
+fn primary_group_sid(user_sid: &[u8], rid: u32) -> Option<Vec<u8>> {
+    relative_id(user_sid)?;
+
+    let mut sid = user_sid.to_vec();
+    sid[user_sid.len() - 4..].copy_from_slice(&rid.to_le_bytes());
+    Some(sid)
+}
+
+///
+/// Get the relative identifier of a SID, the last of its sub-authorities
+///
+/// Arguments:
+/// * `sid`: binary SID
+///
+/// Returns:
+/// * the RID, `None` if `sid` is malformed
+///
+fn relative_id(sid: &[u8]) -> Option<u32> {
+    // Revision 1, count of sub-authorities, 6 bytes of authority, then the
+    // 4-byte little-endian sub-authorities, the RID last
+    let count = usize::from(*sid.get(1)?);
+    if sid[0] != 1 || count == 0 || sid.len() != 8 + 4 * count {
+        return None;
+    }
+
+    Some(u32::from_le_bytes(sid[sid.len() - 4..].try_into().ok()?))
+}
+
+///
+/// Get the binary `objectSid` of an entry
+///
+fn object_sid(entry: &SearchEntry) -> Option<Vec<u8>> {
+    // SIDs are binary, but may happen to be valid UTF-8
+    match entry.bin_attrs.get("objectSid") {
+        Some(values) => values.first().cloned(),
+        None => Some(entry.attrs.get("objectSid")?.first()?.as_bytes().to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::config::tools_config;
+    use super::fake::FakeLdap;
+    use super::*;
+
+    const BASE_DN: &str = "DC=samdom,DC=example,DC=com";
+
+    fn backend() -> (LdapBackend, FakeLdap) {
+        let mut config = tools_config::tests::config();
+        config.ldap = Some(LdapSettings {
+            url: "ldaps://dc1.samdom.example.com".to_string(),
+            bind_dn: format!("CN=Administrator,CN=Users,{}", BASE_DN),
+            bind_pass: "secret".to_string(),
+            base_dn: BASE_DN.to_string(),
+            users_container: "CN=Users".to_string(),
+            start_tls: false,
+            no_tls_verify: false
+        });
+
+        let server = FakeLdap::new(BASE_DN);
+        let backend = LdapBackend::with_connection(&config, Box::new(server.clone())).unwrap();
+        (backend, server)
+    }
+
+    fn domain_user(name: &str) -> DomainUser {
+        DomainUser {
+            common_name: name.to_string(),
+            last_name: "Rossi".to_string(),
+            first_name: "Mario".to_string(),
+            groups: Vec::new(),
+            password: "Segreta-42".to_string()
+        }
+    }
+
+    fn user_dn(name: &str) -> String {
+        format!("CN={},CN=Users,{}", name, BASE_DN)
+    }
+
+    /// S-1-5-21-1-2-3-<rid>
+    fn sid(rid: u32) -> Vec<u8> {
+        let mut sid = vec![1, 5, 0, 0, 0, 0, 0, 5];
+        for sub_authority in [21, 1, 2, 3, rid] {
+            sid.extend_from_slice(&u32::to_le_bytes(sub_authority));
+        }
+        sid
+    }
+
+    fn entry(attrs: &[(&str, &str)], bin_attrs: &[(&str, Vec<u8>)]) -> SearchEntry {
+        SearchEntry {
+            dn: "CN=mario.rossi,CN=Users,DC=samdom,DC=example,DC=com".to_string(),
+            attrs: attrs.iter().map(|(attr, value)| (attr.to_string(), vec![value.to_string()])).collect(),
+            bin_attrs: bin_attrs.iter().map(|(attr, value)| (attr.to_string(), vec![value.clone()])).collect::<HashMap<_, _>>()
+        }
+    }
+
+    #[test]
+    fn password_is_quoted_utf16le() {
+        assert_eq!(encode_password("aè").as_slice(), &[0x22, 0, 0x61, 0, 0xe8, 0, 0x22, 0]);
+    }
+
+    #[test]
+    fn primary_group_sid_replaces_the_rid() {
+        assert_eq!(primary_group_sid(&sid(1105), 513), Some(sid(513)));
+    }
+
+    #[test]
+    fn primary_group_sid_rejects_malformed_sids() {
+        assert_eq!(primary_group_sid(&[], 513), None);
+        assert_eq!(primary_group_sid(&sid(1105)[..20], 513), None);
+        assert_eq!(primary_group_sid(&[2, 1, 0, 0, 0, 0, 0, 5, 1, 0, 0, 0], 513), None);
+    }
+
+    #[test]
+    fn primary_group_filter_escapes_the_binary_sid() {
+        let entry = entry(&[("primaryGroupID", "513")], &[("objectSid", sid(1105))]);
+        let escaped: String = sid(513).iter().map(|byte| format!("\\{:02x}", byte)).collect();
+
+        assert_eq!(primary_group_filter(&entry), Some(format!("(&(objectClass=group)(objectSid={}))", escaped)));
+    }
+
+    #[test]
+    fn primary_group_filter_reads_sids_that_look_like_text() {
+        // Every byte of this SID is ASCII, so ldap3 returns it as a string
+        let text = String::from_utf8(sid(0x41)).unwrap();
+        let entry = entry(&[("primaryGroupID", "513"), ("objectSid", &text)], &[]);
+
+        assert!(primary_group_filter(&entry).is_some());
+    }
+
+    #[test]
+    fn primary_group_filter_needs_both_attributes() {
+        assert_eq!(primary_group_filter(&entry(&[("primaryGroupID", "513")], &[])), None);
+        assert_eq!(primary_group_filter(&entry(&[], &[("objectSid", sid(1105))])), None);
+    }
+
+    #[test]
+    fn needs_the_ldap_section() {
+        let config = tools_config::tests::config();
+        let result = LdapBackend::with_connection(&config, Box::new(FakeLdap::new(BASE_DN)));
+
+        assert!(matches!(result, Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn creates_users_in_the_users_container() {
+        let (backend, server) = backend();
+        backend.create_user(&domain_user("mario.rossi")).unwrap();
+
+        assert!(backend.user_exists("MARIO.ROSSI").unwrap());
+        assert_eq!(backend.list_users().unwrap(), vec!["mario.rossi"]);
+        assert_eq!(
+            server.attribute(&user_dn("mario.rossi"), "unicodePwd"),
+            vec![encode_password("Segreta-42").to_vec()]
+        );
+        assert_eq!(
+            server.attribute(&user_dn("mario.rossi"), "userPrincipalName"),
+            vec![b"mario.rossi@samdom.example.com".to_vec()]
+        );
+
+        let record = backend.show_user("mario.rossi").unwrap();
+        assert_eq!(record.dn, user_dn("mario.rossi"));
+        assert_eq!(record.surname.as_deref(), Some("Rossi"));
+        assert_eq!(record.given_name.as_deref(), Some("Mario"));
+        assert!(record.entry.get("unicodePwd").is_none());
+    }
+
+    #[test]
+    fn refuses_duplicate_accounts() {
+        let (backend, _) = backend();
+        backend.create_user(&domain_user("mario.rossi")).unwrap();
+
+        assert!(matches!(backend.create_user(&domain_user("mario.rossi")), Err(Error::AlreadyExists(_))));
+        assert!(matches!(backend.create_group("Mario.Rossi"), Err(Error::AlreadyExists(_))));
+    }
+
+    #[test]
+    fn refuses_weak_passwords() {
+        let (backend, server) = backend();
+        let mut user = domain_user("mario.rossi");
+        user.password = "short".to_string();
+
+        assert!(matches!(backend.create_user(&user), Err(Error::ConstraintViolation(_))));
+        assert!(!backend.user_exists("mario.rossi").unwrap());
+
+        backend.create_user(&domain_user("mario.rossi")).unwrap();
+        assert!(matches!(backend.set_password("mario.rossi", "short"), Err(Error::ConstraintViolation(_))));
+        assert_eq!(
+            server.attribute(&user_dn("mario.rossi"), "unicodePwd"),
+            vec![encode_password("Segreta-42").to_vec()]
+        );
+    }
+
+    #[test]
+    fn changes_names_and_passwords() {
+        let (backend, server) = backend();
+        backend.create_user(&domain_user("mario.rossi")).unwrap();
+
+        backend.set_names("mario.rossi", "Bianchi", "Maria").unwrap();
+        backend.set_password("mario.rossi", "Nuova-Segreta-7").unwrap();
+
+        let record = backend.show_user("mario.rossi").unwrap();
+        assert_eq!(record.surname.as_deref(), Some("Bianchi"));
+        assert_eq!(record.given_name.as_deref(), Some("Maria"));
+        assert_eq!(
+            server.attribute(&user_dn("mario.rossi"), "unicodePwd"),
+            vec![encode_password("Nuova-Segreta-7").to_vec()]
+        );
+    }
+
+    #[test]
+    fn primary_group_members_are_listed() {
+        let (backend, _) = backend();
+        backend.create_user(&domain_user("mario.rossi")).unwrap();
+        backend.create_user(&domain_user("anna.verdi")).unwrap();
+
+        // Neither is a `member` of Domain Users, it is their primary group
+        let mut members = backend.list_members("Domain Users").unwrap();
+        members.sort();
+        assert_eq!(members, vec!["anna.verdi", "mario.rossi"]);
+        assert!(backend.is_member("domain users", "MARIO.ROSSI").unwrap());
+    }
+
+    #[test]
+    fn manages_group_members() {
+        let (backend, _) = backend();
+        backend.create_user(&domain_user("mario.rossi")).unwrap();
+        backend.create_user(&domain_user("anna.verdi")).unwrap();
+        backend.create_group("3A").unwrap();
+
+        backend.add_member("3A", "mario.rossi").unwrap();
+        assert_eq!(backend.list_members("3A").unwrap(), vec!["mario.rossi"]);
+        assert!(backend.is_member("3a", "mario.rossi").unwrap());
+        assert!(!backend.is_member("3A", "anna.verdi").unwrap());
+        assert_eq!(backend.user_groups("mario.rossi").unwrap(), vec!["Domain Users", "3A"]);
+        assert_eq!(backend.show_user("mario.rossi").unwrap().member_of, vec!["3A"]);
+        assert!(backend.add_member("3A", "mario.rossi").is_err());
+
+        backend.remove_member("3A", "mario.rossi").unwrap();
+        assert!(backend.list_members("3A").unwrap().is_empty());
+        assert_eq!(backend.user_groups("mario.rossi").unwrap(), vec!["Domain Users"]);
+        assert!(backend.remove_member("3A", "mario.rossi").is_err());
+    }
+
+    #[test]
+    fn deletes_users_and_groups() {
+        let (backend, _) = backend();
+        backend.create_user(&domain_user("mario.rossi")).unwrap();
+        backend.create_group("3A").unwrap();
+        backend.add_member("3A", "mario.rossi").unwrap();
+
+        backend.delete_user("mario.rossi").unwrap();
+        assert!(!backend.user_exists("mario.rossi").unwrap());
+        assert!(backend.list_members("3A").unwrap().is_empty());
+
+        backend.delete_group("3A").unwrap();
+        assert!(!backend.group_exists("3A").unwrap());
+        assert_eq!(backend.list_groups().unwrap(), vec!["Domain Users"]);
+    }
+
+    #[test]
+    fn missing_objects_are_not_found() {
+        let (backend, _) = backend();
+        backend.create_group("3A").unwrap();
+
+        assert!(matches!(backend.show_user("nobody"), Err(Error::NotFound(_))));
+        assert!(matches!(backend.delete_user("nobody"), Err(Error::NotFound(_))));
+        assert!(matches!(backend.set_password("nobody", "Segreta-42"), Err(Error::NotFound(_))));
+        assert!(matches!(backend.user_groups("nobody"), Err(Error::NotFound(_))));
+        assert!(matches!(backend.add_member("3A", "nobody"), Err(Error::NotFound(_))));
+        assert!(matches!(backend.list_members("4B"), Err(Error::NotFound(_))));
+        assert!(matches!(backend.delete_group("4B"), Err(Error::NotFound(_))));
+    }
+
+    #[test]
+    fn escapes_names_in_filters_and_dns() {
+        let (backend, _) = backend();
+        backend.create_group("Docenti (ruolo)").unwrap();
+        backend.create_group("Docenti").unwrap();
+        backend.create_user(&domain_user("mario.rossi")).unwrap();
+
+        backend.add_member("Docenti (ruolo)", "mario.rossi").unwrap();
+        assert_eq!(backend.list_members("Docenti (ruolo)").unwrap(), vec!["mario.rossi"]);
+        assert!(backend.list_members("Docenti").unwrap().is_empty());
+        assert!(!backend.user_exists("*").unwrap());
+    }
+
+    #[test]
+    fn reads_the_password_policy_of_the_domain() {
+        let (backend, _) = backend();
+
+        assert_eq!(
+            backend.password_policy().unwrap(),
+            DomainPasswordPolicy { complexity: true, min_length: 7, history_length: 24 }
+        );
+    }
+}
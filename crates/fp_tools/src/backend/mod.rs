@@ -0,0 +1,127 @@
+pub mod fake;
+pub mod ldap;
+pub mod plan;
+pub mod samba_tool;
+
+use crate::commands::parse::UserRecord;
+use crate::commands::user::DomainUser;
+use crate::config::tools_config::{BackendKind, ToolsConfiguration};
+use crate::error::Result;
+use crate::password::policy::DomainPasswordPolicy;
+use ldap::LdapBackend;
+use samba_tool::SambaToolBackend;
+
+///
+/// Operations that a directory service must provide to manage domain
+/// users, groups and memberships.
+///
+/// Executables should only talk to this trait, so that the underlying
+/// implementation (samba-tool, LDAP, a fake directory for tests) can be
+/// swapped without touching them.
+///
+/// Backends are shared by the workers of a parallel batch, so they must be
+/// usable from several threads at once.
+///
+pub trait DirectoryBackend: Send + Sync {
+    ///
+    /// List the names of all domain users
+    ///
+    fn list_users(&self) -> Result<Vec<String>>;
+
+    ///
+    /// Check if a user exists in the domain, names are case-insensitive
+    ///
+    fn user_exists(&self, username: &str) -> Result<bool>;
+
+    ///
+    /// Show the attributes of a domain user
+    ///
+    fn show_user(&self, username: &str) -> Result<UserRecord>;
+
+    ///
+    /// Create a new domain user
+    ///
+    fn create_user(&self, user: &DomainUser) -> Result<()>;
+
+    ///
+    /// Delete a domain user
+    ///
+    fn delete_user(&self, username: &str) -> Result<()>;
+
+    ///
+    /// Change surname and given name of a domain user
+    ///
+    fn set_names(&self, username: &str, surname: &str, given_name: &str) -> Result<()>;
+
+    ///
+    /// Set a new password for a domain user
+    ///
+    fn set_password(&self, username: &str, password: &str) -> Result<()>;
+
+    ///
+    /// List the groups a domain user is member of
+    ///
+    fn user_groups(&self, username: &str) -> Result<Vec<String>>;
+
+    ///
+    /// List the names of all domain groups
+    ///
+    fn list_groups(&self) -> Result<Vec<String>>;
+
+    ///
+    /// Check if a group exists in the domain
+    ///
+    fn group_exists(&self, group: &str) -> Result<bool>;
+
+    ///
+    /// Create a new domain group
+    ///
+    fn create_group(&self, group: &str) -> Result<()>;
+
+    ///
+    /// Delete a domain group
+    ///
+    fn delete_group(&self, group: &str) -> Result<()>;
+
+    ///
+    /// List the members of a domain group
+    ///
+    fn list_members(&self, group: &str) -> Result<Vec<String>>;
+
+    ///
+    /// Check if a user is member of a domain group
+    ///
+    fn is_member(&self, group: &str, username: &str) -> Result<bool>;
+
+    ///
+    /// Add a user to a domain group
+    ///
+    fn add_member(&self, group: &str, username: &str) -> Result<()>;
+
+    ///
+    /// Remove a user from a domain group
+    ///
+    fn remove_member(&self, group: &str, username: &str) -> Result<()>;
+
+    ///
+    /// Read the password policy enforced by the domain
+    ///
+    fn password_policy(&self) -> Result<DomainPasswordPolicy>;
+}
+
+///
+/// Create the directory backend described by the tools configuration
+///
+/// Arguments:
+/// * `config`: system configuration
+///
+/// Returns:
+/// * a boxed `DirectoryBackend` ready to be used, or the reason why the
+///   backend could not be set up
+///
+pub fn from_config(config: &ToolsConfiguration) -> Result<Box<dyn DirectoryBackend>> {
+    match config.backend {
+        BackendKind::SambaTool => Ok(Box::new(SambaToolBackend::new(config))),
+        BackendKind::Ldap => Ok(Box::new(LdapBackend::new(config)?))
+    }
+}
@@ -0,0 +1,363 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Mutex, MutexGuard};
+use serde::Serialize;
+use crate::commands::parse::{self, UserRecord};
+use crate::commands::user::DomainUser;
+use crate::commands::validate;
+use crate::error::{Error, Result};
+use crate::password::policy::DomainPasswordPolicy;
+use super::DirectoryBackend;
+
+///
+/// Change to the directory that a run would make
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    CreateUser {
+        username: String,
+        last_name: String,
+        first_name: String
+    },
+    DeleteUser {
+        username: String
+    },
+    SetNames {
+        username: String,
+        last_name: String,
+        first_name: String
+    },
+    SetPassword {
+        username: String
+    },
+    CreateGroup {
+        group: String
+    },
+    DeleteGroup {
+        group: String
+    },
+    AddMember {
+        group: String,
+        username: String
+    },
+    RemoveMember {
+        group: String,
+        username: String
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::CreateUser { username, last_name, first_name } => {
+                write!(f, "create user {} ({} {})", username, first_name, last_name)
+            },
+            Operation::DeleteUser { username } => write!(f, "delete user {}", username),
+            Operation::SetNames { username, last_name, first_name } => {
+                write!(f, "rename user {} to {} {}", username, first_name, last_name)
+            },
+            Operation::SetPassword { username } => write!(f, "set password of {}", username),
+            Operation::CreateGroup { group } => write!(f, "create group {}", group),
+            Operation::DeleteGroup { group } => write!(f, "delete group {}", group),
+            Operation::AddMember { group, username } => write!(f, "add {} to group {}", username, group),
+            Operation::RemoveMember { group, username } => write!(f, "remove {} from group {}", username, group)
+        }
+    }
+}
+
+///
+/// Changes recorded so far; the sets hold lowercase names, as AD names are
+/// case-insensitive
+///
+#[derive(Default)]
+struct Overlay {
+    operations: Vec<Operation>,
+    created_users: Vec<String>,
+    deleted_users: HashSet<String>,
+    created_groups: Vec<String>,
+    deleted_groups: HashSet<String>,
+    /// (group, user) pairs, as given
+    added_members: Vec<(String, String)>,
+    /// (group, user) pairs, lowercase
+    removed_members: HashSet<(String, String)>
+}
+
+impl Overlay {
+    fn has_created(names: &[String], name: &str) -> bool {
+        names.iter().any(|created| parse::names_equal(created, name))
+    }
+
+    fn forget_member(&mut self, group: &str, username: &str) {
+        self.added_members.retain(|(g, u)| !(parse::names_equal(g, group) && parse::names_equal(u, username)));
+    }
+}
+
+///
+/// Directory backend planning the changes instead of making them
+///
+/// Queries go to the wrapped backend, mutations are validated and
+/// recorded as `Operation`s. The answers to later queries take the recorded
+/// changes into account, so a group planned for creation exists for the
+/// following rows of a batch. Attributes of users created by the plan
+/// cannot be shown.
+///
+pub struct PlanBackend<'a> {
+    inner: &'a dyn DirectoryBackend,
+    overlay: Mutex<Overlay>
+}
+
+impl<'a> PlanBackend<'a> {
+    ///
+    /// Arguments:
+    /// * `inner`: backend answering the queries
+    ///
+    pub fn new(inner: &'a dyn DirectoryBackend) -> PlanBackend<'a> {
+        PlanBackend { inner, overlay: Mutex::new(Overlay::default()) }
+    }
+
+    ///
+    /// Get the operations recorded so far, in order
+    ///
+    pub fn operations(&self) -> Vec<Operation> {
+        self.overlay().operations.clone()
+    }
+
+    fn overlay(&self) -> MutexGuard<'_, Overlay> {
+        self.overlay.lock().expect("plan overlay poisoned")
+    }
+
+    fn record(&self, operation: Operation) {
+        self.overlay().operations.push(operation);
+    }
+
+    ///
+    /// Names listed by the wrapped backend, plus the created and minus the
+    /// deleted ones
+    ///
+    fn merge(listed: Vec<String>, created: &[String], deleted: &HashSet<String>) -> Vec<String> {
+        let mut names: Vec<String> = listed
+            .into_iter()
+            .filter(|name| !deleted.contains(&name.to_lowercase()))
+            .collect();
+
+        for name in created {
+            if !deleted.contains(&name.to_lowercase()) && !parse::contains_name(&names, name) {
+                names.push(name.clone());
+            }
+        }
+
+        names
+    }
+}
+
+impl DirectoryBackend for PlanBackend<'_> {
+    fn list_users(&self) -> Result<Vec<String>> {
+        let listed = self.inner.list_users()?;
+        let overlay = self.overlay();
+        Ok(Self::merge(listed, &overlay.created_users, &overlay.deleted_users))
+    }
+
+    fn user_exists(&self, username: &str) -> Result<bool> {
+        {
+            let overlay = self.overlay();
+            if overlay.deleted_users.contains(&username.to_lowercase()) {
+                return Ok(false);
+            }
+            if Overlay::has_created(&overlay.created_users, username) {
+                return Ok(true);
+            }
+        }
+
+        self.inner.user_exists(username)
+    }
+
+    fn show_user(&self, username: &str) -> Result<UserRecord> {
+        if !self.user_exists(username)? {
+            return Err(Error::NotFound(format!("user {}", username)));
+        }
+        self.inner.show_user(username)
+    }
+
+    fn create_user(&self, user: &DomainUser) -> Result<()> {
+        validate::account_name(&user.common_name)?;
+
+        if self.user_exists(&user.common_name)? {
+            return Err(Error::AlreadyExists(format!("user {}", user.common_name)));
+        }
+
+        let mut overlay = self.overlay();
+        overlay.deleted_users.remove(&user.common_name.to_lowercase());
+        overlay.created_users.push(user.common_name.clone());
+        overlay.operations.push(Operation::CreateUser {
+            username: user.common_name.clone(),
+            last_name: user.last_name.clone(),
+            first_name: user.first_name.clone()
+        });
+
+        Ok(())
+    }
+
+    fn delete_user(&self, username: &str) -> Result<()> {
+        if !self.user_exists(username)? {
+            return Err(Error::NotFound(format!("user {}", username)));
+        }
+
+        let mut overlay = self.overlay();
+        overlay.created_users.retain(|created| !parse::names_equal(created, username));
+        overlay.added_members.retain(|(_, member)| !parse::names_equal(member, username));
+        overlay.deleted_users.insert(username.to_lowercase());
+        overlay.operations.push(Operation::DeleteUser { username: username.to_string() });
+
+        Ok(())
+    }
+
+    fn set_names(&self, username: &str, surname: &str, given_name: &str) -> Result<()> {
+        validate::account_name(username)?;
+
+        self.record(Operation::SetNames {
+            username: username.to_string(),
+            last_name: surname.to_string(),
+            first_name: given_name.to_string()
+        });
+        Ok(())
+    }
+
+    fn set_password(&self, username: &str, _password: &str) -> Result<()> {
+        validate::account_name(username)?;
+
+        self.record(Operation::SetPassword { username: username.to_string() });
+        Ok(())
+    }
+
+    fn user_groups(&self, username: &str) -> Result<Vec<String>> {
+        let created = Overlay::has_created(&self.overlay().created_users, username);
+        let listed = if created { Vec::new() } else { self.inner.user_groups(username)? };
+
+        let overlay = self.overlay();
+        let user = username.to_lowercase();
+        let mut groups: Vec<String> = listed
+            .into_iter()
+            .filter(|group| !overlay.removed_members.contains(&(group.to_lowercase(), user.clone())))
+            .collect();
+
+        for (group, member) in &overlay.added_members {
+            if parse::names_equal(member, username) && !parse::contains_name(&groups, group) {
+                groups.push(group.clone());
+            }
+        }
+
+        Ok(groups)
+    }
+
+    fn list_groups(&self) -> Result<Vec<String>> {
+        let listed = self.inner.list_groups()?;
+        let overlay = self.overlay();
+        Ok(Self::merge(listed, &overlay.created_groups, &overlay.deleted_groups))
+    }
+
+    fn group_exists(&self, group: &str) -> Result<bool> {
+        {
+            let overlay = self.overlay();
+            if overlay.deleted_groups.contains(&group.to_lowercase()) {
+                return Ok(false);
+            }
+            if Overlay::has_created(&overlay.created_groups, group) {
+                return Ok(true);
+            }
+        }
+
+        self.inner.group_exists(group)
+    }
+
+    fn create_group(&self, group: &str) -> Result<()> {
+        validate::group_name(group)?;
+
+        if self.group_exists(group)? {
+            return Err(Error::AlreadyExists(format!("group {}", group)));
+        }
+
+        let mut overlay = self.overlay();
+        overlay.deleted_groups.remove(&group.to_lowercase());
+        overlay.created_groups.push(group.to_string());
+        overlay.operations.push(Operation::CreateGroup { group: group.to_string() });
+
+        Ok(())
+    }
+
+    fn delete_group(&self, group: &str) -> Result<()> {
+        if !self.group_exists(group)? {
+            return Err(Error::NotFound(format!("group {}", group)));
+        }
+
+        let mut overlay = self.overlay();
+        overlay.created_groups.retain(|created| !parse::names_equal(created, group));
+        overlay.deleted_groups.insert(group.to_lowercase());
+        overlay.operations.push(Operation::DeleteGroup { group: group.to_string() });
+
+        Ok(())
+    }
+
+    fn list_members(&self, group: &str) -> Result<Vec<String>> {
+        let created = Overlay::has_created(&self.overlay().created_groups, group);
+        let listed = if created { Vec::new() } else { self.inner.list_members(group)? };
+
+        // Deleted users leave their groups
+        let overlay = self.overlay();
+        let key = group.to_lowercase();
+        let mut members: Vec<String> = listed
+            .into_iter()
+            .filter(|member| !overlay.deleted_users.contains(&member.to_lowercase()))
+            .filter(|member| !overlay.removed_members.contains(&(key.clone(), member.to_lowercase())))
+            .collect();
+
+        for (added_group, member) in &overlay.added_members {
+            if parse::names_equal(added_group, group) && !parse::contains_name(&members, member) {
+                members.push(member.clone());
+            }
+        }
+
+        Ok(members)
+    }
+
+    fn is_member(&self, group: &str, username: &str) -> Result<bool> {
+        Ok(parse::contains_name(&self.list_members(group)?, username))
+    }
+
+    fn add_member(&self, group: &str, username: &str) -> Result<()> {
+        validate::group_name(group)?;
+        validate::account_name(username)?;
+
+        if !self.group_exists(group)? {
+            return Err(Error::NotFound(format!("group {}", group)));
+        }
+        if !self.user_exists(username)? {
+            return Err(Error::NotFound(format!("user {}", username)));
+        }
+
+        let mut overlay = self.overlay();
+        overlay.removed_members.remove(&(group.to_lowercase(), username.to_lowercase()));
+        overlay.forget_member(group, username);
+        overlay.added_members.push((group.to_string(), username.to_string()));
+        overlay.operations.push(Operation::AddMember { group: group.to_string(), username: username.to_string() });
+
+        Ok(())
+    }
+
+    fn remove_member(&self, group: &str, username: &str) -> Result<()> {
+        if !self.is_member(group, username)? {
+            return Err(Error::NotFound(format!("{} in group {}", username, group)));
+        }
+
+        let mut overlay = self.overlay();
+        overlay.forget_member(group, username);
+        overlay.removed_members.insert((group.to_lowercase(), username.to_lowercase()));
+        overlay.operations.push(Operation::RemoveMember { group: group.to_string(), username: username.to_string() });
+
+        Ok(())
+    }
+
+    fn password_policy(&self) -> Result<DomainPasswordPolicy> {
+        self.inner.password_policy()
+    }
+}
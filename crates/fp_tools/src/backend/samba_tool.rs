@@ -0,0 +1,112 @@
+use crate::commands::{domain, group, user};
+use crate::commands::common::{CommandRunner, SystemRunner};
+use crate::commands::parse::UserRecord;
+use crate::commands::user::DomainUser;
+use crate::config::tools_config::ToolsConfiguration;
+use crate::error::Result;
+use crate::password::policy::DomainPasswordPolicy;
+use super::DirectoryBackend;
+
+///
+/// Directory backend driving the `samba-tool` command line program
+///
+pub struct SambaToolBackend {
+    config: ToolsConfiguration,
+    runner: Box<dyn CommandRunner>
+}
+
+impl SambaToolBackend {
+    ///
+    /// Create a new samba-tool backend spawning real processes
+    ///
+    /// Arguments:
+    /// * `config`: system configuration, `sambaPath` is the program to run
+    ///
+    pub fn new(config: &ToolsConfiguration) -> SambaToolBackend {
+        SambaToolBackend::with_runner(config, Box::new(SystemRunner))
+    }
+
+    ///
+    /// Create a new samba-tool backend using a specific command runner
+    ///
+    /// Arguments:
+    /// * `config`: system configuration, `sambaPath` is the program to run
+    /// * `runner`: command runner, e.g. a `FakeRunner` in tests
+    ///
+    pub fn with_runner(config: &ToolsConfiguration, runner: Box<dyn CommandRunner>) -> SambaToolBackend {
+        SambaToolBackend {
+            config: config.clone(),
+            runner
+        }
+    }
+}
+
+impl DirectoryBackend for SambaToolBackend {
+    fn list_users(&self) -> Result<Vec<String>> {
+        user::list_users(self.runner.as_ref(), &self.config)
+    }
+
+    fn user_exists(&self, username: &str) -> Result<bool> {
+        user::is_existing_user(self.runner.as_ref(), &self.config, username)
+    }
+
+    fn show_user(&self, username: &str) -> Result<UserRecord> {
+        user::show_user(self.runner.as_ref(), &self.config, username)
+    }
+
+    fn create_user(&self, user: &DomainUser) -> Result<()> {
+        user::add_user(self.runner.as_ref(), &self.config, user)
+    }
+
+    fn delete_user(&self, username: &str) -> Result<()> {
+        user::delete_user(self.runner.as_ref(), &self.config, username)
+    }
+
+    fn set_names(&self, username: &str, surname: &str, given_name: &str) -> Result<()> {
+        user::rename_user(self.runner.as_ref(), &self.config, username, surname, given_name)
+    }
+
+    fn set_password(&self, username: &str, password: &str) -> Result<()> {
+        user::change_password(self.runner.as_ref(), &self.config, username, password)
+    }
+
+    fn user_groups(&self, username: &str) -> Result<Vec<String>> {
+        user::get_groups(self.runner.as_ref(), &self.config, username)
+    }
+
+    fn list_groups(&self) -> Result<Vec<String>> {
+        group::list_groups(self.runner.as_ref(), &self.config)
+    }
+
+    fn group_exists(&self, group: &str) -> Result<bool> {
+        group::is_existing_group(self.runner.as_ref(), &self.config, group)
+    }
+
+    fn create_group(&self, group: &str) -> Result<()> {
+        group::add_group(self.runner.as_ref(), &self.config, group)
+    }
+
+    fn delete_group(&self, group: &str) -> Result<()> {
+        group::delete_group(self.runner.as_ref(), &self.config, group)
+    }
+
+    fn list_members(&self, group: &str) -> Result<Vec<String>> {
+        group::list_members(self.runner.as_ref(), &self.config, group)
+    }
+
+    fn is_member(&self, group: &str, username: &str) -> Result<bool> {
+        group::check_membership(self.runner.as_ref(), &self.config, group, username)
+    }
+
+    fn add_member(&self, group: &str, username: &str) -> Result<()> {
+        group::add_member(self.runner.as_ref(), &self.config, group, username)
+    }
+
+    fn remove_member(&self, group: &str, username: &str) -> Result<()> {
+        group::remove_member(self.runner.as_ref(), &self.config, group, username)
+    }
+
+    fn password_policy(&self) -> Result<DomainPasswordPolicy> {
+        domain::password_settings(self.runner.as_ref(), &self.config)
+    }
+}
@@ -0,0 +1,635 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions, Permissions};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::sync::{Mutex, MutexGuard};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+use crate::backend::DirectoryBackend;
+use crate::commands::parse::contains_name;
+use crate::commands::user::DomainUser;
+use crate::error::{Error, Result};
+
+///
+/// Single domain change of a batch run
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum Step {
+    CreateGroup {
+        group: String
+    },
+
+    CreateUser {
+        user: DomainUser,
+        /// `user.password` has been generated, it must be handed out
+        generated_password: bool
+    },
+
+    AddMember {
+        group: String,
+        username: String
+    },
+
+    RemoveMember {
+        group: String,
+        username: String
+    },
+
+    SetNames {
+        username: String,
+        last_name: String,
+        first_name: String,
+        /// Names before the change, so that it can be undone
+        previous_last_name: String,
+        previous_first_name: String
+    },
+
+    DeleteUser {
+        username: String
+    },
+
+    DeleteGroup {
+        group: String
+    }
+}
+
+impl Step {
+    ///
+    /// Account the step is about, `None` for group steps
+    ///
+    pub fn username(&self) -> Option<&str> {
+        match self {
+            Step::CreateUser { user, .. } => Some(&user.common_name),
+            Step::AddMember { username, .. }
+            | Step::RemoveMember { username, .. }
+            | Step::SetNames { username, .. }
+            | Step::DeleteUser { username } => Some(username),
+            Step::CreateGroup { .. } | Step::DeleteGroup { .. } => None
+        }
+    }
+
+    ///
+    /// Make the change in the domain
+    ///
+    /// Arguments:
+    /// * `backend`: directory backend
+    ///
+    pub fn apply(&self, backend: &dyn DirectoryBackend) -> Result<()> {
+        match self {
+            Step::CreateGroup { group } => backend.create_group(group),
+            Step::CreateUser { user, .. } => backend.create_user(user),
+            Step::AddMember { group, username } => backend.add_member(group, username),
+            Step::RemoveMember { group, username } => backend.remove_member(group, username),
+            Step::SetNames { username, last_name, first_name, .. } => backend.set_names(username, last_name, first_name),
+            Step::DeleteUser { username } => backend.delete_user(username),
+            Step::DeleteGroup { group } => backend.delete_group(group)
+        }
+    }
+
+    ///
+    /// Check whether the change is already in the domain
+    ///
+    /// Arguments:
+    /// * `backend`: directory backend
+    ///
+    pub fn is_applied(&self, backend: &dyn DirectoryBackend) -> Result<bool> {
+        match self {
+            Step::CreateGroup { group } => backend.group_exists(group),
+            Step::CreateUser { user, .. } => backend.user_exists(&user.common_name),
+            Step::AddMember { group, username } => Ok(contains_name(&backend.list_members(group)?, username)),
+            Step::RemoveMember { group, username } => Ok(!contains_name(&backend.list_members(group)?, username)),
+            Step::SetNames { username, last_name, first_name, .. } => {
+                let record = backend.show_user(username)?;
+                Ok(record.surname.as_deref() == Some(last_name.as_str()) && record.given_name.as_deref() == Some(first_name.as_str()))
+            },
+            Step::DeleteUser { username } => Ok(!backend.user_exists(username)?),
+            Step::DeleteGroup { group } => Ok(!backend.group_exists(group)?)
+        }
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::CreateGroup { group } => write!(f, "create group {}", group),
+            Step::CreateUser { user, .. } => write!(f, "create user {}", user.common_name),
+            Step::AddMember { group, username } => write!(f, "add {} to group {}", username, group),
+            Step::RemoveMember { group, username } => write!(f, "remove {} from group {}", username, group),
+            Step::SetNames { username, last_name, first_name, previous_last_name, previous_first_name } => write!(
+                f,
+                "rename {}: '{} {}' -> '{} {}'",
+                username, previous_first_name, previous_last_name, first_name, last_name
+            ),
+            Step::DeleteUser { username } => write!(f, "delete user {}", username),
+            Step::DeleteGroup { group } => write!(f, "delete group {}", group)
+        }
+    }
+}
+
+///
+/// Line of the journal file
+///
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "entry", rename_all = "snake_case")]
+enum Entry {
+    Planned { index: usize, step: Step },
+    Started { index: usize },
+    Completed { index: usize },
+    Failed { index: usize, error: String }
+}
+
+///
+/// Progress of a step, as recorded by the journal
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepState {
+    /// Not attempted yet, or failed
+    Pending,
+
+    /// Attempted by a run that stopped before recording the outcome: the
+    /// change may or may not be in the domain
+    InDoubt,
+
+    Completed
+}
+
+///
+/// On-disk record of the steps of a batch and of their progress
+///
+/// The file holds one JSON entry per line: the planned steps first, then a
+/// `started` entry before each attempt and a `completed` or `failed` entry
+/// after it, each flushed to disk before going on. A run that dies midway
+/// leaves behind the exact point where it stopped.
+///
+/// The planned steps include the passwords of the new users, so the file
+/// is readable by its owner only, like the credentials file. Passwords of
+/// the users already created are removed with `scrub_passwords` once they
+/// have been handed out.
+///
+/// Progress can be recorded from several threads at once.
+///
+pub struct Journal {
+    path: String,
+    file: Option<Mutex<File>>,
+    steps: Vec<Step>,
+    states: Mutex<Vec<StepState>>
+}
+
+impl Journal {
+    ///
+    /// Start the journal of a new batch, replacing any previous file
+    ///
+    /// Arguments:
+    /// * `path`: journal file
+    /// * `steps`: planned steps, in order
+    ///
+    /// Returns:
+    /// * the `Journal`, or the error of the file
+    ///
+    pub fn create(path: &str, steps: Vec<Step>) -> Result<Journal> {
+        let mut file = create_private(path)?;
+
+        for (index, step) in steps.iter().enumerate() {
+            write_entry(&mut file, &Entry::Planned { index, step: step.clone() })?;
+        }
+        file.sync_all()?;
+
+        let states = Mutex::new(vec![StepState::Pending; steps.len()]);
+        Ok(Journal { path: path.to_string(), file: Some(Mutex::new(file)), steps, states })
+    }
+
+    ///
+    /// Journal kept in memory only, for runs that must not leave files behind
+    ///
+    /// Arguments:
+    /// * `steps`: planned steps, in order
+    ///
+    pub fn in_memory(steps: Vec<Step>) -> Journal {
+        let states = Mutex::new(vec![StepState::Pending; steps.len()]);
+        Journal { path: String::new(), file: None, steps, states }
+    }
+
+    ///
+    /// Reopen the journal of a previous run, to resume it
+    ///
+    /// A truncated last line, left by a run killed while writing it, is
+    /// dropped from the file.
+    ///
+    /// Arguments:
+    /// * `path`: journal file
+    ///
+    /// Returns:
+    /// * the `Journal` with the progress of the previous run, or
+    ///   `Error::InvalidInput` if the file is not a journal
+    ///
+    pub fn open(path: &str) -> Result<Journal> {
+        let content = fs::read_to_string(path)?;
+        let lines: Vec<&str> = content.split_inclusive('\n').collect();
+
+        let mut steps = Vec::new();
+        let mut states = Vec::new();
+        let mut valid_length = 0;
+        let invalid = |line: usize, what: String| Error::InvalidInput(format!("{}, line {}: {}", path, line + 1, what));
+
+        for (number, line) in lines.iter().enumerate() {
+            let entry: Entry = match serde_json::from_str(line) {
+                Ok(entry) if line.ends_with('\n') => entry,
+                Ok(_) => break,
+                Err(_) if number + 1 == lines.len() => break,
+                Err(err) => return Err(invalid(number, err.to_string()))
+            };
+            valid_length += line.len();
+
+            match entry {
+                Entry::Planned { index, step } if index == steps.len() => {
+                    steps.push(step);
+                    states.push(StepState::Pending);
+                },
+                Entry::Planned { index, .. } => return Err(invalid(number, format!("step {} out of order", index))),
+                Entry::Started { index } | Entry::Completed { index } | Entry::Failed { index, .. } if index >= steps.len() => {
+                    return Err(invalid(number, format!("unknown step {}", index)))
+                },
+                Entry::Started { index } => states[index] = StepState::InDoubt,
+                Entry::Completed { index } => states[index] = StepState::Completed,
+                Entry::Failed { index, .. } => states[index] = StepState::Pending
+            }
+        }
+
+        let file = OpenOptions::new().append(true).open(path)?;
+        file.set_permissions(Permissions::from_mode(0o600))?;
+        if valid_length < content.len() {
+            file.set_len(valid_length as u64)?;
+        }
+
+        Ok(Journal { path: path.to_string(), file: Some(Mutex::new(file)), steps, states: Mutex::new(states) })
+    }
+
+    ///
+    /// Get the journal file, empty for in-memory journals
+    ///
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    ///
+    /// Get the planned steps, in order
+    ///
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    ///
+    /// Get the progress of a step
+    ///
+    pub fn state(&self, index: usize) -> StepState {
+        self.states()[index]
+    }
+
+    ///
+    /// Number of completed steps
+    ///
+    pub fn completed(&self) -> usize {
+        self.states().iter().filter(|state| **state == StepState::Completed).count()
+    }
+
+    ///
+    /// Returns:
+    /// * `true` if every planned step has been completed
+    ///
+    pub fn is_finished(&self) -> bool {
+        self.completed() == self.steps.len()
+    }
+
+    ///
+    /// Users created by the batch whose generated password has not been
+    /// scrubbed yet
+    ///
+    pub fn generated_credentials(&self) -> Vec<&DomainUser> {
+        let states = self.states();
+
+        self.steps
+            .iter()
+            .zip(states.iter())
+            .filter_map(|(step, state)| match step {
+                Step::CreateUser { user, generated_password: true } if *state == StepState::Completed && !user.password.is_empty() => {
+                    Some(user)
+                },
+                _ => None
+            })
+            .collect()
+    }
+
+    ///
+    /// Remove the passwords of the users already created, once they have
+    /// been saved elsewhere: only the ones needed to resume the batch stay
+    /// in the journal. The file is rewritten aside and renamed over the old
+    /// one, so that it is never left half written.
+    ///
+    /// Returns:
+    /// * `Ok(())` on success, `Error::Io` otherwise
+    ///
+    pub fn scrub_passwords(&mut self) -> Result<()> {
+        let states = self.states().clone();
+
+        for (step, state) in self.steps.iter_mut().zip(&states) {
+            if let (Step::CreateUser { user, .. }, StepState::Completed) = (step, state) {
+                user.password.zeroize();
+            }
+        }
+
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+
+        let temporary = format!("{}.tmp", self.path);
+        let mut replacement = create_private(&temporary)?;
+
+        for (index, step) in self.steps.iter().enumerate() {
+            write_entry(&mut replacement, &Entry::Planned { index, step: step.clone() })?;
+        }
+        for (index, state) in states.iter().enumerate() {
+            match state {
+                StepState::InDoubt => write_entry(&mut replacement, &Entry::Started { index })?,
+                StepState::Completed => write_entry(&mut replacement, &Entry::Completed { index })?,
+                StepState::Pending => {}
+            }
+        }
+        replacement.sync_all()?;
+
+        fs::rename(&temporary, &self.path)?;
+        *file.get_mut().expect("journal file poisoned") = replacement;
+        Ok(())
+    }
+
+    ///
+    /// Append a step that has just been made in the domain, for runs that
+    /// decide their changes as they go instead of planning them
+    ///
+    /// Arguments:
+    /// * `step`: change made
+    ///
+    pub fn record(&mut self, step: Step) -> Result<()> {
+        let index = self.steps.len();
+
+        self.write(&[Entry::Planned { index, step: step.clone() }, Entry::Completed { index }])?;
+        self.steps.push(step);
+        self.states().push(StepState::Completed);
+        Ok(())
+    }
+
+    ///
+    /// Make a step that is not completed yet, recording its progress. A step
+    /// left in doubt by an interrupted run is checked against the domain
+    /// first, so that it is never made twice.
+    ///
+    /// Arguments:
+    /// * `backend`: directory backend
+    /// * `index`: step to make
+    ///
+    /// Returns:
+    /// * `None` if the step is in the domain, the error refusing it
+    ///   otherwise, or the error that prevented the journal from being written
+    ///
+    pub fn run_step(&self, backend: &dyn DirectoryBackend, index: usize) -> Result<Option<Error>> {
+        let step = &self.steps[index];
+
+        let applied = match self.state(index) {
+            StepState::Completed => Ok(true),
+            StepState::InDoubt => step.is_applied(backend),
+            StepState::Pending => Ok(false)
+        };
+
+        let result = match applied {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                self.start(index)?;
+                step.apply(backend)
+            },
+            Err(err) => Err(err)
+        };
+
+        match result {
+            Ok(()) => {
+                self.complete(index)?;
+                Ok(None)
+            },
+            Err(err) => {
+                self.fail(index, &err)?;
+                Ok(Some(err))
+            }
+        }
+    }
+
+    ///
+    /// Record that a step is about to be attempted
+    ///
+    pub fn start(&self, index: usize) -> Result<()> {
+        self.write(&[Entry::Started { index }])?;
+        self.states()[index] = StepState::InDoubt;
+        Ok(())
+    }
+
+    ///
+    /// Record that a step is in the domain
+    ///
+    pub fn complete(&self, index: usize) -> Result<()> {
+        self.write(&[Entry::Completed { index }])?;
+        self.states()[index] = StepState::Completed;
+        Ok(())
+    }
+
+    ///
+    /// Record that a step has been refused: it is attempted again on resume
+    ///
+    pub fn fail(&self, index: usize, err: &Error) -> Result<()> {
+        self.write(&[Entry::Failed { index, error: err.to_string() }])?;
+        self.states()[index] = StepState::Pending;
+        Ok(())
+    }
+
+    fn states(&self) -> MutexGuard<'_, Vec<StepState>> {
+        self.states.lock().expect("journal states poisoned")
+    }
+
+    ///
+    /// Append entries to the file and flush them to disk
+    ///
+    fn write(&self, entries: &[Entry]) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+
+        let mut file = file.lock().expect("journal file poisoned");
+        for entry in entries {
+            write_entry(&mut file, entry)?;
+        }
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+///
+/// Create or truncate a file readable by its owner only, whatever the
+/// permissions of the file it replaces
+///
+fn create_private(path: &str) -> Result<File> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+
+    // The mode only applies to new files
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    Ok(file)
+}
+
+fn write_entry(file: &mut File, entry: &Entry) -> Result<()> {
+    let line = serde_json::to_string(entry).map_err(|err| Error::InvalidInput(err.to_string()))?;
+    writeln!(file, "{}", line)?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use super::*;
+
+    /// Journal file of a test, removed when dropped
+    pub(crate) struct TempJournal(pub(crate) String);
+
+    impl TempJournal {
+        pub(crate) fn new(name: &str) -> TempJournal {
+            let path = std::env::temp_dir().join(format!("fp-tools-{}-{}.jsonl", name, std::process::id()));
+            let _ = fs::remove_file(&path);
+            TempJournal(path.to_string_lossy().into_owned())
+        }
+    }
+
+    impl Drop for TempJournal {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// Journal whose file cannot be written any more, as on a full disk
+    pub(crate) fn unwritable(path: &TempJournal) -> Journal {
+        fs::write(&path.0, "").unwrap();
+        let file = File::open(&path.0).unwrap();
+        Journal { path: path.0.clone(), file: Some(Mutex::new(file)), steps: Vec::new(), states: Mutex::new(Vec::new()) }
+    }
+
+    fn user(name: &str, password: &str) -> DomainUser {
+        DomainUser {
+            common_name: name.to_string(),
+            last_name: "Rossi".to_string(),
+            first_name: "Mario".to_string(),
+            groups: vec!["3B".to_string()],
+            password: password.to_string()
+        }
+    }
+
+    fn steps() -> Vec<Step> {
+        vec![
+            Step::CreateGroup { group: "3B".to_string() },
+            Step::CreateUser { user: user("mario.rossi", "Secret#123"), generated_password: true },
+            Step::AddMember { group: "3B".to_string(), username: "mario.rossi".to_string() }
+        ]
+    }
+
+    #[test]
+    fn reopened_journal_has_the_recorded_progress() {
+        let path = TempJournal::new("round-trip");
+
+        let journal = Journal::create(&path.0, steps()).unwrap();
+        journal.start(0).unwrap();
+        journal.complete(0).unwrap();
+        journal.start(1).unwrap();
+        journal.fail(1, &Error::AlreadyExists("user mario.rossi".to_string())).unwrap();
+        journal.start(2).unwrap();
+        drop(journal);
+
+        let journal = Journal::open(&path.0).unwrap();
+        assert_eq!(journal.steps(), steps().as_slice());
+        assert_eq!(journal.state(0), StepState::Completed);
+        assert_eq!(journal.state(1), StepState::Pending);
+        assert_eq!(journal.state(2), StepState::InDoubt);
+        assert_eq!(journal.completed(), 1);
+        assert!(!journal.is_finished());
+    }
+
+    #[test]
+    fn truncated_last_line_is_dropped() {
+        let path = TempJournal::new("truncated");
+
+        let journal = Journal::create(&path.0, steps()).unwrap();
+        journal.complete(0).unwrap();
+        drop(journal);
+
+        let mut file = OpenOptions::new().append(true).open(&path.0).unwrap();
+        file.write_all(b"{\"entry\":\"compl").unwrap();
+        drop(file);
+
+        let journal = Journal::open(&path.0).unwrap();
+        assert_eq!(journal.state(0), StepState::Completed);
+        assert_eq!(journal.state(1), StepState::Pending);
+
+        // Entries recorded after the recovery must be readable
+        journal.complete(1).unwrap();
+        drop(journal);
+
+        let journal = Journal::open(&path.0).unwrap();
+        assert_eq!(journal.state(1), StepState::Completed);
+        assert!(fs::read_to_string(&path.0).unwrap().lines().all(|line| line.ends_with('}')));
+    }
+
+    #[test]
+    fn corrupt_line_before_the_last_is_an_error() {
+        let path = TempJournal::new("corrupt");
+
+        drop(Journal::create(&path.0, steps()).unwrap());
+        let content = fs::read_to_string(&path.0).unwrap().replacen("planned", "plan", 1);
+        fs::write(&path.0, content).unwrap();
+
+        assert!(matches!(Journal::open(&path.0), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn scrubbed_journal_keeps_only_the_passwords_still_needed() {
+        let path = TempJournal::new("scrub");
+        let mut steps = steps();
+        steps.push(Step::CreateUser { user: user("anna.bianchi", "Other#456"), generated_password: true });
+
+        let mut journal = Journal::create(&path.0, steps).unwrap();
+        journal.complete(0).unwrap();
+        journal.complete(1).unwrap();
+        journal.start(2).unwrap();
+        assert_eq!(journal.generated_credentials().len(), 1);
+
+        journal.scrub_passwords().unwrap();
+        assert!(journal.generated_credentials().is_empty());
+        drop(journal);
+
+        let content = fs::read_to_string(&path.0).unwrap();
+        assert!(!content.contains("Secret#123"));
+        assert!(content.contains("Other#456"));
+
+        let journal = Journal::open(&path.0).unwrap();
+        assert_eq!(journal.state(1), StepState::Completed);
+        assert_eq!(journal.state(2), StepState::InDoubt);
+        assert_eq!(journal.state(3), StepState::Pending);
+    }
+
+    #[test]
+    fn replaced_journal_is_readable_by_its_owner_only() {
+        let path = TempJournal::new("mode");
+        fs::write(&path.0, "").unwrap();
+        fs::set_permissions(&path.0, Permissions::from_mode(0o644)).unwrap();
+
+        drop(Journal::create(&path.0, steps()).unwrap());
+
+        assert_eq!(fs::metadata(&path.0).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,514 @@
+pub mod journal;
+pub mod rollback;
+pub mod update;
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, mpsc};
+use std::thread;
+use crate::backend::DirectoryBackend;
+use crate::commands::parse::names_equal;
+use crate::commands::user::DomainUser;
+use crate::error::Error;
+use journal::{Journal, Step, StepState};
+
+///
+/// Outcome of a single imported row
+///
+#[derive(Debug)]
+pub enum RowOutcome {
+    /// Every step of the row has been made: for an import, the user has
+    /// been created and joined to all of its groups
+    Created,
+
+    /// Every step of the row was completed by the run being resumed
+    PreviouslyCreated,
+
+    /// Something went wrong, the user may have been created partially
+    Failed(Error)
+}
+
+///
+/// Per-row report of a batch import
+///
+#[derive(Debug)]
+pub struct RowReport {
+    pub username: String,
+    pub outcome: RowOutcome,
+    /// Groups created in the domain while processing this row
+    pub created_groups: Vec<String>,
+    /// Groups the user has been added to
+    pub joined_groups: Vec<String>,
+    /// Journal indices of the steps of the row made by this run
+    pub steps: Vec<usize>
+}
+
+///
+/// Final counts of a batch import
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BatchSummary {
+    pub created: usize,
+    pub previously_created: usize,
+    pub failed: usize
+}
+
+impl BatchSummary {
+    fn record(&mut self, outcome: &RowOutcome) {
+        match outcome {
+            RowOutcome::Created => self.created += 1,
+            RowOutcome::PreviouslyCreated => self.previously_created += 1,
+            RowOutcome::Failed(_) => self.failed += 1
+        }
+    }
+}
+
+///
+/// Rows processed so far out of the whole batch
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub done: usize,
+    pub total: usize
+}
+
+///
+/// Steps of a batch import, and the users left out because they exist
+///
+#[derive(Debug, Default)]
+pub struct ImportPlan {
+    pub steps: Vec<Step>,
+    /// Users already in the domain, left untouched
+    pub existing: Vec<String>
+}
+
+///
+/// Plan the import of a batch of users: for every user, create the groups
+/// that do not exist yet, then the account, then add the memberships.
+/// Users that already exist, or appear twice, are left out.
+///
+/// Arguments:
+/// * `backend`: directory backend, only queried
+/// * `users`: users to import
+/// * `generated_password`: tells the users whose password has been generated
+///
+/// Returns:
+/// * the `ImportPlan`, or the error of the domain queries
+///
+pub fn plan_import<F>(backend: &dyn DirectoryBackend, users: &[DomainUser], generated_password: F) -> Result<ImportPlan, Error>
+where
+    F: Fn(&DomainUser) -> bool
+{
+    // Known names, lowercase as AD names are case-insensitive
+    let mut groups: HashSet<String> = backend.list_groups()?
+        .iter()
+        .map(|group| group.to_lowercase())
+        .collect();
+    let mut accounts: HashSet<String> = backend.list_users()?
+        .iter()
+        .map(|user| user.to_lowercase())
+        .collect();
+
+    let mut plan = ImportPlan::default();
+
+    for user in users {
+        if !accounts.insert(user.common_name.to_lowercase()) {
+            plan.existing.push(user.common_name.clone());
+            continue;
+        }
+
+        let wanted: Vec<&String> = user.groups.iter().filter(|group| !group.is_empty()).collect();
+
+        // Groups must exist before the user can join them
+        for group in &wanted {
+            if groups.insert(group.to_lowercase()) {
+                plan.steps.push(Step::CreateGroup { group: group.to_string() });
+            }
+        }
+
+        plan.steps.push(Step::CreateUser { user: user.clone(), generated_password: generated_password(user) });
+
+        for group in wanted {
+            plan.steps.push(Step::AddMember { group: group.to_string(), username: user.common_name.clone() });
+        }
+    }
+
+    Ok(plan)
+}
+
+///
+/// Run the pending steps of a journal on `workers` threads. The missing
+/// groups are created first, then the rows are imported concurrently, the
+/// steps of each row in order. Completed steps are never repeated; steps
+/// left in doubt by an interrupted run are checked against the domain
+/// first. A failed step ends its row, not the batch.
+///
+/// A row is a run of consecutive steps about the same account, along with
+/// the groups created right before them: plans of `plan_import` and
+/// `sync::compute` are laid out that way.
+///
+/// Arguments:
+/// * `backend`: directory backend
+/// * `journal`: planned steps and their progress, updated as steps run
+/// * `workers`: number of rows imported at the same time, at least 1
+/// * `on_row`: called with the report of every row as soon as it is processed,
+///   in completion order, along with the progress of the batch
+///
+/// Returns:
+/// * a `BatchSummary` with the final counts, or the error that prevented
+///   the journal from being written: the batch stops there, as its
+///   progress could not be resumed any more
+///
+pub fn run_import<F>(backend: &dyn DirectoryBackend, journal: &Journal, workers: usize, mut on_row: F) -> Result<BatchSummary, Error>
+where
+    F: FnMut(&RowReport, Progress)
+{
+    let rows = rows(journal.steps());
+    let pending: Vec<bool> = (0..journal.steps().len())
+        .map(|index| journal.state(index) != StepState::Completed)
+        .collect();
+
+    // Memberships depend on the groups, so these go first
+    let groups: Vec<usize> = (0..journal.steps().len())
+        .filter(|index| pending[*index] && matches!(journal.steps()[*index], Step::CreateGroup { .. }))
+        .collect();
+
+    let mut group_errors: HashMap<usize, Error> = HashMap::new();
+    run_parallel(&groups, workers, |index| Ok((*index, journal.run_step(backend, *index)?)), |(index, err)| {
+        if let Some(err) = err {
+            group_errors.insert(index, err);
+        }
+    })?;
+
+    // Each failure goes to the row that planned the group
+    let group_errors = Mutex::new(group_errors);
+    let mut summary = BatchSummary::default();
+    let total = rows.len();
+
+    let tasks: Vec<usize> = (0..total).collect();
+    run_parallel(&tasks, workers, |task| {
+        let row = rows[*task].clone();
+        let group_error = row.clone().find_map(|index| group_errors.lock().expect("group errors poisoned").remove(&index));
+        import_row(backend, journal, row, &pending, group_error)
+    }, |report| {
+        summary.record(&report.outcome);
+        on_row(&report, Progress { done: summary.created + summary.previously_created + summary.failed, total });
+    })?;
+
+    Ok(summary)
+}
+
+///
+/// Run `work` on every task with up to `workers` threads, handing the
+/// results to `on_result` on the calling thread as they come. The first
+/// error stops the workers from taking more tasks and is returned.
+///
+fn run_parallel<T, R, W, F>(tasks: &[T], workers: usize, work: W, mut on_result: F) -> Result<(), Error>
+where
+    T: Sync,
+    R: Send,
+    W: Fn(&T) -> Result<R, Error> + Sync,
+    F: FnMut(R)
+{
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..workers.clamp(1, tasks.len().max(1)) {
+            let sender = sender.clone();
+            let (next, stop, work) = (&next, &stop, &work);
+
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let Some(task) = tasks.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        break;
+                    };
+
+                    let result = work(task);
+                    if result.is_err() {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                    if sender.send(result).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let mut failure = None;
+        for result in receiver {
+            match result {
+                Ok(result) => on_result(result),
+                Err(err) => failure = failure.or(Some(err))
+            }
+        }
+
+        failure.map_or(Ok(()), Err)
+    })
+}
+
+///
+/// Split the steps into rows: the groups created for an account, then the
+/// steps about the account, e.g. its creation and its memberships
+///
+fn rows(steps: &[Step]) -> Vec<Range<usize>> {
+    let mut rows: Vec<Range<usize>> = Vec::new();
+    let mut start = 0;
+    let mut account: Option<&str> = None;
+
+    for (index, step) in steps.iter().enumerate() {
+        let opens_row = match (account, step.username()) {
+            // Groups go with the account after them
+            (Some(_), None) => true,
+            (Some(current), Some(username)) => !names_equal(current, username),
+            (None, _) => false
+        };
+
+        if opens_row {
+            rows.push(start..index);
+            start = index;
+        }
+        account = step.username();
+    }
+
+    if start < steps.len() {
+        rows.push(start..steps.len());
+    }
+
+    rows
+}
+
+///
+/// Run the remaining steps of a row. Its groups have been handled by then:
+/// `pending` tells the steps that were left when the run started, and
+/// `group_error` is the failure of the group that could not be created.
+///
+fn import_row(
+    backend: &dyn DirectoryBackend,
+    journal: &Journal,
+    row: Range<usize>,
+    pending: &[bool],
+    group_error: Option<Error>
+) -> Result<RowReport, Error> {
+    let username = journal.steps()[row.clone()]
+        .iter()
+        .find_map(Step::username)
+        .unwrap_or_default()
+        .to_string();
+
+    let mut report = RowReport {
+        username,
+        outcome: if row.clone().any(|index| pending[index]) { RowOutcome::Created } else { RowOutcome::PreviouslyCreated },
+        created_groups: Vec::new(),
+        joined_groups: Vec::new(),
+        steps: Vec::new()
+    };
+
+    if let Some(err) = group_error {
+        report.outcome = RowOutcome::Failed(err);
+        return Ok(report);
+    }
+
+    for index in row {
+        let step = &journal.steps()[index];
+
+        if let Step::CreateGroup { group } = step {
+            if pending[index] {
+                report.created_groups.push(group.clone());
+                report.steps.push(index);
+            }
+            continue;
+        }
+
+        if journal.state(index) == StepState::Completed {
+            continue;
+        }
+
+        if let Some(err) = journal.run_step(backend, index)? {
+            report.outcome = RowOutcome::Failed(err);
+            return Ok(report);
+        }
+
+        if let Step::AddMember { group, .. } = step {
+            report.joined_groups.push(group.clone());
+        }
+        report.steps.push(index);
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::fake::FakeDirectory;
+    use crate::backend::plan::{Operation, PlanBackend};
+    use crate::commands::parse::contains_name;
+    use crate::backend::samba_tool::SambaToolBackend;
+    use crate::commands::fake::FakeRunner;
+    use crate::config::tools_config::tests::config;
+    use journal::tests::TempJournal;
+    use super::*;
+
+    /// Empty domain: the fake runner answers every query with no output
+    pub(super) fn domain() -> SambaToolBackend {
+        SambaToolBackend::with_runner(&config(), Box::new(FakeRunner::new()))
+    }
+
+    pub(super) fn user(name: &str, groups: &[&str]) -> DomainUser {
+        DomainUser {
+            common_name: name.to_string(),
+            last_name: "Rossi".to_string(),
+            first_name: "Mario".to_string(),
+            groups: groups.iter().map(|group| group.to_string()).collect(),
+            password: "Secret#123".to_string()
+        }
+    }
+
+    fn created_users(backend: &PlanBackend) -> Vec<String> {
+        backend.operations()
+            .into_iter()
+            .filter_map(|operation| match operation {
+                Operation::CreateUser { username, .. } => Some(username),
+                _ => None
+            })
+            .collect()
+    }
+
+    fn import(backend: &PlanBackend, journal: &Journal, workers: usize) -> BatchSummary {
+        run_import(backend, journal, workers, |_, _| {}).unwrap()
+    }
+
+    #[test]
+    fn groups_are_planned_before_their_first_member() {
+        let domain = domain();
+        let users = [user("mario.rossi", &["3B"]), user("anna.bianchi", &["3B", "4C"]), user("Mario.Rossi", &["4C"])];
+
+        let plan = plan_import(&domain, &users, |_| false).unwrap();
+
+        let steps: Vec<String> = plan.steps.iter().map(ToString::to_string).collect();
+        assert_eq!(steps, [
+            "create group 3B",
+            "create user mario.rossi",
+            "add mario.rossi to group 3B",
+            "create group 4C",
+            "create user anna.bianchi",
+            "add anna.bianchi to group 3B",
+            "add anna.bianchi to group 4C"
+        ]);
+        assert_eq!(plan.existing, ["Mario.Rossi"]);
+        assert_eq!(rows(&plan.steps), [0..3, 3..7]);
+    }
+
+    #[test]
+    fn parallel_import_creates_every_row() {
+        let domain = domain();
+        let backend = PlanBackend::new(&domain);
+        let users: Vec<DomainUser> = (0..20)
+            .map(|index| user(&format!("user{}", index), &["shared", &format!("g{}", index % 3)]))
+            .collect();
+
+        let plan = plan_import(&backend, &users, |_| false).unwrap();
+        let journal = Journal::in_memory(plan.steps);
+        let mut progress = Vec::new();
+        let summary = run_import(&backend, &journal, 4, |report, done| {
+            assert!(matches!(report.outcome, RowOutcome::Created), "{:?}", report);
+            progress.push(done);
+        }).unwrap();
+
+        assert_eq!(summary, BatchSummary { created: 20, previously_created: 0, failed: 0 });
+        assert!(journal.is_finished());
+        assert_eq!(created_users(&backend).len(), 20);
+        assert_eq!(progress.last(), Some(&Progress { done: 20, total: 20 }));
+        for index in 0..20 {
+            let groups = backend.user_groups(&format!("user{}", index)).unwrap();
+            assert_eq!(groups.len(), 2, "user{}: {:?}", index, groups);
+        }
+    }
+
+    #[test]
+    fn failed_worker_leaves_the_journal_of_the_others_intact() {
+        let backend = FakeDirectory::new().refuse("user7");
+        let users: Vec<DomainUser> = (0..12)
+            .map(|index| user(&format!("user{}", index), &["shared", &format!("g{}", index % 3)]))
+            .collect();
+
+        let plan = plan_import(&backend, &users, |_| false).unwrap();
+        let path = TempJournal::new("failed-worker");
+        let journal = Journal::create(&path.0, plan.steps).unwrap();
+        let mut failed = Vec::new();
+        let summary = run_import(&backend, &journal, 4, |report, _| {
+            if let RowOutcome::Failed(_) = report.outcome {
+                failed.push(report.username.clone());
+            }
+        }).unwrap();
+
+        assert_eq!(summary, BatchSummary { created: 11, previously_created: 0, failed: 1 });
+        assert_eq!(failed, ["user7"]);
+
+        // The file holds the same progress: only the refused row is left
+        let reopened = Journal::open(&path.0).unwrap();
+        for (index, step) in reopened.steps().iter().enumerate() {
+            let expected = if step.username() == Some("user7") { StepState::Pending } else { StepState::Completed };
+            assert_eq!(reopened.state(index), expected, "{}", step);
+            assert_eq!(journal.state(index), expected, "{}", step);
+        }
+        assert_eq!(backend.list_users().unwrap().len(), 11);
+
+        // Resuming only retries the refused row
+        let summary = run_import(&backend, &reopened, 4, |_, _| {}).unwrap();
+        assert_eq!(summary, BatchSummary { created: 0, previously_created: 11, failed: 1 });
+    }
+
+    #[test]
+    fn failed_group_fails_its_rows_only() {
+        let domain = domain();
+        let backend = PlanBackend::new(&domain);
+        let users = [user("mario.rossi", &["a/b"]), user("anna.bianchi", &["3B"])];
+
+        let plan = plan_import(&backend, &users, |_| false).unwrap();
+        let journal = Journal::in_memory(plan.steps);
+        let mut failed = Vec::new();
+        let summary = run_import(&backend, &journal, 2, |report, _| {
+            if let RowOutcome::Failed(_) = report.outcome {
+                failed.push(report.username.clone());
+            }
+        }).unwrap();
+
+        assert_eq!(summary, BatchSummary { created: 1, previously_created: 0, failed: 1 });
+        assert_eq!(failed, ["mario.rossi"]);
+        assert_eq!(created_users(&backend), ["anna.bianchi"]);
+    }
+
+    #[test]
+    fn resume_skips_completed_steps() {
+        let domain = domain();
+        let backend = PlanBackend::new(&domain);
+        let users = [user("mario.rossi", &["3B"]), user("anna.bianchi", &["3B"])];
+        let plan = plan_import(&backend, &users, |_| false).unwrap();
+
+        // The first row has been imported, then the run died right after
+        // creating the second user, before recording it
+        let journal = Journal::in_memory(plan.steps);
+        for index in 0..3 {
+            assert!(journal.run_step(&backend, index).unwrap().is_none());
+        }
+        journal.start(3).unwrap();
+        journal.steps()[3].apply(&backend).unwrap();
+        assert_eq!(journal.state(3), StepState::InDoubt);
+
+        let summary = import(&backend, &journal, 2);
+
+        assert_eq!(summary, BatchSummary { created: 1, previously_created: 1, failed: 0 });
+        assert!(journal.is_finished());
+        assert_eq!(created_users(&backend), ["mario.rossi", "anna.bianchi"]);
+        assert!(contains_name(&backend.user_groups("anna.bianchi").unwrap(), "3B"));
+
+        // Nothing is left for a second resume
+        let summary = import(&backend, &journal, 2);
+        assert_eq!(summary, BatchSummary { created: 0, previously_created: 2, failed: 0 });
+        assert_eq!(created_users(&backend).len(), 2);
+    }
+}
@@ -0,0 +1,241 @@
+use crate::backend::DirectoryBackend;
+use crate::commands::parse::contains_name;
+use crate::error::{Error, Result};
+use super::journal::{Journal, Step, StepState};
+
+///
+/// Options of a rollback
+///
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RollbackOptions {
+    /// Add the users back to the groups the run removed them from
+    pub restore_groups: bool
+}
+
+///
+/// Changes undoing a batch run
+///
+#[derive(Debug, Default)]
+pub struct RollbackPlan {
+    /// Steps undoing the run, most recent change first
+    pub steps: Vec<Step>,
+
+    /// Changes of the run left in place
+    pub kept: Vec<Step>
+}
+
+///
+/// Outcome of a single step of a rollback
+///
+#[derive(Debug)]
+pub enum UndoOutcome {
+    Undone,
+
+    /// Undone by the rollback being resumed
+    PreviouslyUndone,
+
+    Failed(Error)
+}
+
+///
+/// Per-step report of a rollback
+///
+#[derive(Debug)]
+pub struct UndoReport<'a> {
+    pub step: &'a Step,
+    pub outcome: UndoOutcome
+}
+
+///
+/// Final counts of a rollback
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RollbackSummary {
+    pub undone: usize,
+    pub previously_undone: usize,
+    pub failed: usize
+}
+
+impl RollbackSummary {
+    fn record(&mut self, outcome: &UndoOutcome) {
+        match outcome {
+            UndoOutcome::Undone => self.undone += 1,
+            UndoOutcome::PreviouslyUndone => self.previously_undone += 1,
+            UndoOutcome::Failed(_) => self.failed += 1
+        }
+    }
+}
+
+///
+/// Work out the steps undoing the changes recorded in the journal of a
+/// run: created users and groups are deleted, added memberships removed,
+/// renamed users get their previous names back. Memberships of the deleted
+/// users are not removed one by one, they go with the account.
+///
+/// Steps the run did not complete are ignored; steps it left in doubt are
+/// checked against the domain.
+///
+/// Arguments:
+/// * `backend`: directory backend, only queried
+/// * `journal`: journal of the run to undo
+/// * `options`: rollback options
+///
+/// Returns:
+/// * the `RollbackPlan`, or the error of the domain queries
+///
+pub fn plan_rollback(backend: &dyn DirectoryBackend, journal: &Journal, options: RollbackOptions) -> Result<RollbackPlan> {
+    // Changes that are in the domain
+    let mut made: Vec<&Step> = Vec::new();
+    for (index, step) in journal.steps().iter().enumerate() {
+        let applied = match journal.state(index) {
+            StepState::Completed => true,
+            StepState::InDoubt => step.is_applied(backend)?,
+            StepState::Pending => false
+        };
+
+        if applied {
+            made.push(step);
+        }
+    }
+
+    let created: Vec<String> = made
+        .iter()
+        .filter_map(|step| match step {
+            Step::CreateUser { user, .. } => Some(user.common_name.clone()),
+            _ => None
+        })
+        .collect();
+
+    let mut plan = RollbackPlan::default();
+
+    for step in made.into_iter().rev() {
+        let undo = match step {
+            Step::CreateUser { user, .. } => Some(Step::DeleteUser { username: user.common_name.clone() }),
+            Step::CreateGroup { group } => Some(Step::DeleteGroup { group: group.clone() }),
+            Step::AddMember { username, .. } if contains_name(&created, username) => continue,
+            Step::AddMember { group, username } => Some(Step::RemoveMember { group: group.clone(), username: username.clone() }),
+            Step::RemoveMember { group, username } if options.restore_groups => {
+                Some(Step::AddMember { group: group.clone(), username: username.clone() })
+            },
+            Step::SetNames { username, last_name, first_name, previous_last_name, previous_first_name } => Some(Step::SetNames {
+                username: username.clone(),
+                last_name: previous_last_name.clone(),
+                first_name: previous_first_name.clone(),
+                previous_last_name: last_name.clone(),
+                previous_first_name: first_name.clone()
+            }),
+            Step::RemoveMember { .. } | Step::DeleteUser { .. } | Step::DeleteGroup { .. } => None
+        };
+
+        match undo {
+            Some(undo) => plan.steps.push(undo),
+            None => plan.kept.push(step.clone())
+        }
+    }
+
+    Ok(plan)
+}
+
+///
+/// Run the pending steps of a rollback journal, in order. A failed step
+/// does not stop the rollback. Groups that gained members after the run
+/// are not deleted.
+///
+/// Arguments:
+/// * `backend`: directory backend
+/// * `journal`: journal created with the steps of a `RollbackPlan`
+/// * `on_step`: called with the report of every step as soon as it is processed
+///
+/// Returns:
+/// * a `RollbackSummary` with the final counts, or the error that
+///   prevented the journal from being written
+///
+pub fn run_rollback<F>(backend: &dyn DirectoryBackend, journal: &Journal, mut on_step: F) -> Result<RollbackSummary>
+where
+    F: FnMut(&UndoReport)
+{
+    let mut summary = RollbackSummary::default();
+
+    for index in 0..journal.steps().len() {
+        let outcome = if journal.state(index) == StepState::Completed {
+            UndoOutcome::PreviouslyUndone
+        } else if let Some(err) = still_used(backend, &journal.steps()[index]) {
+            journal.fail(index, &err)?;
+            UndoOutcome::Failed(err)
+        } else {
+            match journal.run_step(backend, index)? {
+                None => UndoOutcome::Undone,
+                Some(err) => UndoOutcome::Failed(err)
+            }
+        };
+
+        summary.record(&outcome);
+        on_step(&UndoReport { step: &journal.steps()[index], outcome });
+    }
+
+    Ok(summary)
+}
+
+///
+/// Refuse to delete a group that still has members: they joined it after
+/// the run being undone
+///
+fn still_used(backend: &dyn DirectoryBackend, step: &Step) -> Option<Error> {
+    let Step::DeleteGroup { group } = step else {
+        return None;
+    };
+
+    let members = match backend.group_exists(group) {
+        Ok(true) => backend.list_members(group),
+        Ok(false) => return None,
+        Err(err) => return Some(err)
+    };
+
+    match members {
+        Ok(members) if members.is_empty() => None,
+        Ok(members) => Some(Error::ConstraintViolation(format!("group {} still has members: {}", group, members.join(", ")))),
+        Err(err) => Some(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::plan::PlanBackend;
+    use crate::batch::{self, tests::{domain, user}};
+    use super::*;
+
+    #[test]
+    fn rollback_undoes_the_import_but_keeps_groups_in_use() {
+        let domain = domain();
+        let backend = PlanBackend::new(&domain);
+        let users = [user("mario.rossi", &["3B"]), user("anna.bianchi", &["4C"])];
+
+        let plan = batch::plan_import(&backend, &users, |_| false).unwrap();
+        let journal = Journal::in_memory(plan.steps);
+        batch::run_import(&backend, &journal, 1, |_, _| {}).unwrap();
+
+        // Someone joined 3B after the import
+        backend.create_user(&user("luca.verdi", &[])).unwrap();
+        backend.add_member("3B", "luca.verdi").unwrap();
+
+        let plan = plan_rollback(&backend, &journal, RollbackOptions::default()).unwrap();
+        let steps: Vec<String> = plan.steps.iter().map(ToString::to_string).collect();
+        assert_eq!(steps, ["delete user anna.bianchi", "delete group 4C", "delete user mario.rossi", "delete group 3B"]);
+
+        let rollback = Journal::in_memory(plan.steps);
+        let mut failed = Vec::new();
+        let summary = run_rollback(&backend, &rollback, |report| {
+            if let UndoOutcome::Failed(err) = &report.outcome {
+                failed.push((report.step.to_string(), err.to_string()));
+            }
+        }).unwrap();
+
+        assert_eq!(summary, RollbackSummary { undone: 3, previously_undone: 0, failed: 1 });
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, "delete group 3B");
+        assert!(failed[0].1.contains("luca.verdi"));
+        assert!(!backend.user_exists("mario.rossi").unwrap());
+        assert!(backend.group_exists("3B").unwrap());
+        assert!(!backend.group_exists("4C").unwrap());
+    }
+}
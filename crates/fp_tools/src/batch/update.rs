@@ -0,0 +1,393 @@
+use std::collections::HashSet;
+use std::fmt;
+use crate::backend::DirectoryBackend;
+use crate::commands::parse::{contains_name, names_equal};
+use crate::commands::user::DomainUser;
+use crate::error::Error;
+use super::journal::{Journal, Step};
+
+///
+/// Primary group of every account, never removed by an update
+///
+const PRIMARY_GROUP: &str = "Domain Users";
+
+///
+/// Options of an update run
+///
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UpdateOptions {
+    /// Reset passwords to the ones found in the input
+    pub passwords: bool
+}
+
+///
+/// Single change applied to an existing account
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Surname { from: String, to: String },
+    GivenName { from: String, to: String },
+    Password,
+    CreateGroup(String),
+    JoinGroup(String),
+    LeaveGroup(String)
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Surname { from, to } => write!(f, "surname '{}' -> '{}'", from, to),
+            Change::GivenName { from, to } => write!(f, "given name '{}' -> '{}'", from, to),
+            Change::Password => write!(f, "password reset"),
+            Change::CreateGroup(group) => write!(f, "new group {}", group),
+            Change::JoinGroup(group) => write!(f, "+{}", group),
+            Change::LeaveGroup(group) => write!(f, "-{}", group)
+        }
+    }
+}
+
+///
+/// Outcome of the update of a single account
+///
+#[derive(Debug)]
+pub enum UpdateOutcome {
+    /// Every listed change has been applied
+    Updated(Vec<Change>),
+
+    /// The account already matches the input
+    Unchanged,
+
+    /// The account does not exist in the domain
+    Missing,
+
+    /// The update stopped on an error, after applying the listed changes
+    Failed(Error, Vec<Change>)
+}
+
+///
+/// Per-user report of an update run
+///
+#[derive(Debug)]
+pub struct UpdateReport {
+    pub username: String,
+    pub outcome: UpdateOutcome
+}
+
+///
+/// Final counts of an update run
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UpdateSummary {
+    pub updated: usize,
+    pub unchanged: usize,
+    pub missing: usize,
+    pub failed: usize
+}
+
+impl UpdateSummary {
+    fn record(&mut self, outcome: &UpdateOutcome) {
+        match outcome {
+            UpdateOutcome::Updated(_) => self.updated += 1,
+            UpdateOutcome::Unchanged => self.unchanged += 1,
+            UpdateOutcome::Missing => self.missing += 1,
+            UpdateOutcome::Failed(_, _) => self.failed += 1
+        }
+    }
+}
+
+///
+/// Bring existing accounts in line with the input: names, memberships and
+/// optionally passwords. Accounts that do not exist are reported as missing.
+///
+/// Only groups mentioned somewhere in the input are considered managed:
+/// a user leaves a group only if that group appears in the input and the
+/// user's row does not list it, so unrelated groups (staff, admins...) are
+/// never touched.
+///
+/// Every change is recorded in `journal` as soon as it is made, with the
+/// previous names and memberships, so that the run can be rolled back.
+/// Password resets are not recorded: the previous passwords are unknown.
+///
+/// Arguments:
+/// * `backend`: directory backend
+/// * `users`: desired state of the accounts
+/// * `options`: update options
+/// * `journal`: journal receiving the changes
+/// * `on_user`: called with the report of every user as soon as it is processed
+///
+/// Returns:
+/// * the `UpdateSummary` with the final counts, or the error that prevented
+///   the journal from being written: the run stops there, as the changes
+///   made after it could not be rolled back
+///
+pub fn update_users<F>(
+    backend: &dyn DirectoryBackend,
+    users: &[DomainUser],
+    options: UpdateOptions,
+    journal: &mut Journal,
+    mut on_user: F
+) -> Result<UpdateSummary, Error>
+where
+    F: FnMut(&UpdateReport)
+{
+    let managed: HashSet<String> = users
+        .iter()
+        .flat_map(|user| user.groups.iter())
+        .filter(|group| !group.is_empty())
+        .map(|group| group.to_lowercase())
+        .collect();
+
+    let mut summary = UpdateSummary::default();
+
+    for user in users {
+        let outcome = match backend.user_exists(&user.common_name) {
+            Ok(true) => update_user(backend, user, &managed, options, journal)?,
+            Ok(false) => UpdateOutcome::Missing,
+            Err(err) => UpdateOutcome::Failed(err, Vec::new())
+        };
+
+        let report = UpdateReport { username: user.common_name.clone(), outcome };
+        summary.record(&report.outcome);
+        on_user(&report);
+    }
+
+    Ok(summary)
+}
+
+///
+/// Why the update of a user stopped
+///
+enum Stop {
+    /// The domain refused a change: only this user fails
+    Refused(Error),
+
+    /// The journal cannot be written: the whole run stops
+    Journal(Error)
+}
+
+fn update_user(
+    backend: &dyn DirectoryBackend,
+    user: &DomainUser,
+    managed: &HashSet<String>,
+    options: UpdateOptions,
+    journal: &mut Journal) -> Result<UpdateOutcome, Error> {
+
+    let mut applied = Vec::new();
+
+    match apply_changes(backend, user, managed, options, journal, &mut applied) {
+        Ok(()) if applied.is_empty() => Ok(UpdateOutcome::Unchanged),
+        Ok(()) => Ok(UpdateOutcome::Updated(applied)),
+        Err(Stop::Refused(err)) => Ok(UpdateOutcome::Failed(err, applied)),
+        Err(Stop::Journal(err)) => Err(err)
+    }
+}
+
+fn apply_changes(
+    backend: &dyn DirectoryBackend,
+    user: &DomainUser,
+    managed: &HashSet<String>,
+    options: UpdateOptions,
+    journal: &mut Journal,
+    applied: &mut Vec<Change>) -> Result<(), Stop> {
+
+    let record = backend.show_user(&user.common_name).map_err(Stop::Refused)?;
+    let surname = record.surname.unwrap_or_default();
+    let given_name = record.given_name.unwrap_or_default();
+
+    // Empty input fields never overwrite what is in the domain
+    let new_surname = if user.last_name.is_empty() { &surname } else { &user.last_name };
+    let new_given_name = if user.first_name.is_empty() { &given_name } else { &user.first_name };
+
+    if *new_surname != surname || *new_given_name != given_name {
+        backend.set_names(&user.common_name, new_surname, new_given_name).map_err(Stop::Refused)?;
+        journal.record(Step::SetNames {
+            username: user.common_name.clone(),
+            last_name: new_surname.clone(),
+            first_name: new_given_name.clone(),
+            previous_last_name: surname.clone(),
+            previous_first_name: given_name.clone()
+        }).map_err(Stop::Journal)?;
+
+        if *new_surname != surname {
+            applied.push(Change::Surname { from: surname.clone(), to: new_surname.clone() });
+        }
+        if *new_given_name != given_name {
+            applied.push(Change::GivenName { from: given_name.clone(), to: new_given_name.clone() });
+        }
+    }
+
+    if options.passwords && !user.password.is_empty() {
+        backend.set_password(&user.common_name, &user.password).map_err(Stop::Refused)?;
+        applied.push(Change::Password);
+    }
+
+    let current = backend.user_groups(&user.common_name).map_err(Stop::Refused)?;
+    let wanted: Vec<String> = user.groups.iter().filter(|group| !group.is_empty()).cloned().collect();
+
+    for group in &wanted {
+        if !contains_name(&current, group) {
+            if !backend.group_exists(group).map_err(Stop::Refused)? {
+                backend.create_group(group).map_err(Stop::Refused)?;
+                journal.record(Step::CreateGroup { group: group.clone() }).map_err(Stop::Journal)?;
+                applied.push(Change::CreateGroup(group.clone()));
+            }
+
+            backend.add_member(group, &user.common_name).map_err(Stop::Refused)?;
+            journal.record(Step::AddMember { group: group.clone(), username: user.common_name.clone() })
+                .map_err(Stop::Journal)?;
+            applied.push(Change::JoinGroup(group.clone()));
+        }
+    }
+
+    for group in &current {
+        let removable = managed.contains(&group.to_lowercase())
+            && !names_equal(group, PRIMARY_GROUP)
+            && !contains_name(&wanted, group);
+
+        if removable {
+            backend.remove_member(group, &user.common_name).map_err(Stop::Refused)?;
+            journal.record(Step::RemoveMember { group: group.clone(), username: user.common_name.clone() })
+                .map_err(Stop::Journal)?;
+            applied.push(Change::LeaveGroup(group.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::fake::FakeDirectory;
+    use crate::batch::journal::tests::{TempJournal, unwritable};
+    use super::*;
+
+    fn user(name: &str, last_name: &str, first_name: &str, groups: &[&str]) -> DomainUser {
+        DomainUser {
+            common_name: name.to_string(),
+            last_name: last_name.to_string(),
+            first_name: first_name.to_string(),
+            groups: groups.iter().map(|group| group.to_string()).collect(),
+            password: "Secret#123".to_string()
+        }
+    }
+
+    fn domain() -> FakeDirectory {
+        FakeDirectory::new()
+            .with_user("mario.rossi", "Rossi", "Mario", &["3B", "staff"])
+            .with_user("anna.bianchi", "Bianchi", "Anna", &["4C"])
+    }
+
+    fn update(backend: &FakeDirectory, users: &[DomainUser], options: UpdateOptions, journal: &mut Journal) -> Vec<String> {
+        let mut reports = Vec::new();
+        update_users(backend, users, options, journal, |report| {
+            let outcome = match &report.outcome {
+                UpdateOutcome::Updated(changes) => changes.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "),
+                UpdateOutcome::Unchanged => "unchanged".to_string(),
+                UpdateOutcome::Missing => "missing".to_string(),
+                UpdateOutcome::Failed(err, changes) => format!("failed after {} changes: {}", changes.len(), err)
+            };
+            reports.push(format!("{}: {}", report.username, outcome));
+        }).unwrap();
+        reports
+    }
+
+    #[test]
+    fn accounts_follow_the_input() {
+        let backend = domain();
+        let users = [
+            user("mario.rossi", "Rossi", "Mario Luigi", &["5A"]),
+            user("Anna.Bianchi", "", "", &["4c", "3B"]),
+            user("luca.verdi", "Verdi", "Luca", &["3B"])
+        ];
+        let mut journal = Journal::in_memory(Vec::new());
+
+        let reports = update(&backend, &users, UpdateOptions::default(), &mut journal);
+
+        assert_eq!(reports, [
+            "mario.rossi: given name 'Mario' -> 'Mario Luigi', new group 5A, +5A, -3B",
+            "Anna.Bianchi: +3B",
+            "luca.verdi: missing"
+        ]);
+
+        // Unmanaged and primary groups are kept
+        assert_eq!(backend.user_groups("mario.rossi").unwrap(), ["Domain Users", "5A", "staff"]);
+        assert_eq!(backend.show_user("anna.bianchi").unwrap().surname.as_deref(), Some("Bianchi"));
+        assert_eq!(backend.password("mario.rossi").as_deref(), Some(""));
+
+        let steps: Vec<String> = journal.steps().iter().map(ToString::to_string).collect();
+        assert_eq!(steps, [
+            "rename mario.rossi: 'Mario Rossi' -> 'Mario Luigi Rossi'",
+            "create group 5A",
+            "add mario.rossi to group 5A",
+            "remove mario.rossi from group 3B",
+            "add Anna.Bianchi to group 3B"
+        ]);
+        assert!(journal.is_finished());
+
+        // A second run finds nothing to do
+        let mut journal = Journal::in_memory(Vec::new());
+        let reports = update(&backend, &users[..2], UpdateOptions::default(), &mut journal);
+        assert_eq!(reports, ["mario.rossi: unchanged", "Anna.Bianchi: unchanged"]);
+        assert!(journal.steps().is_empty());
+    }
+
+    #[test]
+    fn passwords_are_reset_on_request_only() {
+        let backend = domain();
+        let mut anna = user("anna.bianchi", "Bianchi", "Anna", &["4C"]);
+        anna.password = String::new();
+        let users = [user("mario.rossi", "Rossi", "Mario", &["3B"]), anna];
+        let mut journal = Journal::in_memory(Vec::new());
+
+        let reports = update(&backend, &users, UpdateOptions::default(), &mut journal);
+        assert_eq!(reports, ["mario.rossi: unchanged", "anna.bianchi: unchanged"]);
+
+        let reports = update(&backend, &users, UpdateOptions { passwords: true }, &mut journal);
+        assert_eq!(reports, ["mario.rossi: password reset", "anna.bianchi: unchanged"]);
+        assert_eq!(backend.password("mario.rossi").as_deref(), Some("Secret#123"));
+
+        // Previous passwords are unknown, so resets cannot be journaled
+        assert!(journal.steps().is_empty());
+    }
+
+    #[test]
+    fn refused_change_fails_its_user_only() {
+        let backend = domain().refuse("lab");
+        let users = [
+            user("mario.rossi", "Rossi", "Mario Luigi", &["3B", "lab"]),
+            user("anna.bianchi", "Bianchi", "Anna", &["4C", "3B"])
+        ];
+        let mut journal = Journal::in_memory(Vec::new());
+
+        let reports = update(&backend, &users, UpdateOptions::default(), &mut journal);
+
+        assert_eq!(reports, [
+            "mario.rossi: failed after 1 changes: constraint violation: change to lab refused",
+            "anna.bianchi: +3B"
+        ]);
+        assert_eq!(journal.steps().len(), 2);
+    }
+
+    #[test]
+    fn journal_failure_stops_the_run() {
+        let backend = domain();
+        let users = [
+            user("mario.rossi", "Rossi", "Mario Luigi", &["3B"]),
+            user("anna.bianchi", "Bianchi", "Anna", &["3B"])
+        ];
+        let path = TempJournal::new("update-unwritable");
+        let mut journal = unwritable(&path);
+        let mut reported = Vec::new();
+
+        let result = update_users(&backend, &users, UpdateOptions::default(), &mut journal, |report| {
+            reported.push(report.username.clone());
+        });
+
+        assert!(matches!(result, Err(Error::Io(_))), "{:?}", result);
+        assert!(reported.is_empty());
+
+        // The change that could not be journaled is the last one made
+        assert_eq!(backend.show_user("mario.rossi").unwrap().given_name.as_deref(), Some("Mario Luigi"));
+        assert_eq!(backend.user_groups("anna.bianchi").unwrap(), ["Domain Users", "4C"]);
+    }
+}
@@ -0,0 +1,183 @@
+use crate::config::tools_config::ToolsConfiguration;
+use crate::error::Result;
+use super::common::CommandRunner;
+use super::validate;
+
+///
+/// Argument list of a samba-tool invocation
+///
+/// Arguments are passed to the program as they are, without any shell in
+/// between: names must not be quoted. Names and paths are validated while
+/// the list is built, so that bad input is refused before samba-tool runs.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SambaArgs {
+    args: Vec<String>
+}
+
+impl SambaArgs {
+    ///
+    /// Start the arguments of a samba-tool subcommand
+    ///
+    /// Arguments:
+    /// * `object`: object type, e.g. `user`
+    /// * `action`: subcommand, e.g. `create`
+    ///
+    pub fn new(object: &str, action: &str) -> SambaArgs {
+        SambaArgs { args: vec![object.to_string(), action.to_string()] }
+    }
+
+    ///
+    /// Add a user name as positional argument
+    ///
+    /// Returns:
+    /// * `Error::InvalidInput` if the name is not a valid sAMAccountName
+    ///
+    pub fn user(mut self, username: &str) -> Result<SambaArgs> {
+        validate::account_name(username)?;
+        self.args.push(username.to_string());
+        Ok(self)
+    }
+
+    ///
+    /// Add a group name as positional argument
+    ///
+    /// Returns:
+    /// * `Error::InvalidInput` if the name is not a valid group name
+    ///
+    pub fn group(mut self, group: &str) -> Result<SambaArgs> {
+        validate::group_name(group)?;
+        self.args.push(group.to_string());
+        Ok(self)
+    }
+
+    ///
+    /// Add a plain literal argument, e.g. `show`
+    ///
+    pub fn arg(mut self, value: &str) -> SambaArgs {
+        self.args.push(value.to_string());
+        self
+    }
+
+    ///
+    /// Add an option without value, e.g. `--use-username-as-cn`
+    ///
+    pub fn flag(mut self, name: &str) -> SambaArgs {
+        self.args.push(format!("--{}", name));
+        self
+    }
+
+    ///
+    /// Add an option with a value, as `--name=value`: the value is kept in
+    /// the same argument, so it cannot be taken for an option
+    ///
+    pub fn option(mut self, name: &str, value: &str) -> SambaArgs {
+        self.args.push(format!("--{}={}", name, value));
+        self
+    }
+
+    ///
+    /// Add an option whose value is a UNC path
+    ///
+    /// Returns:
+    /// * `Error::InvalidInput` if the path is not a well formed UNC path
+    ///
+    pub fn path_option(self, name: &str, path: &str) -> Result<SambaArgs> {
+        validate::unc_path(path)?;
+        Ok(self.option(name, path))
+    }
+
+    ///
+    /// Get the arguments built so far
+    ///
+    pub fn args(&self) -> Vec<&str> {
+        self.args.iter().map(String::as_str).collect()
+    }
+
+    ///
+    /// Run samba-tool with these arguments
+    ///
+    /// Arguments:
+    /// * `runner`: command runner used to invoke samba-tool
+    /// * `config`: system configuration, `sambaPath` is the program to run
+    ///
+    /// Returns:
+    /// * the output lines, or the samba-tool error
+    ///
+    pub fn run(&self, runner: &dyn CommandRunner, config: &ToolsConfiguration) -> Result<Vec<String>> {
+        runner.run(&config.samba_path, &self.args())
+    }
+
+    ///
+    /// Run samba-tool with these arguments, writing `input` to its
+    /// standard input (password prompts)
+    ///
+    pub fn run_with_input(&self, runner: &dyn CommandRunner, config: &ToolsConfiguration, input: &str) -> Result<Vec<String>> {
+        runner.run_with_input(&config.samba_path, &self.args(), input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::fake::FakeRunner;
+    use crate::config::tools_config::tests::config;
+    use crate::error::Error;
+    use super::*;
+
+    #[test]
+    fn arguments_keep_their_order() {
+        let args = SambaArgs::new("user", "create")
+            .user("anna.deluca").unwrap()
+            .flag("random-password")
+            .option("surname", "De Luca")
+            .path_option("home-directory", "\\\\server\\homes\\anna.deluca").unwrap()
+            .arg("show");
+
+        assert_eq!(args.args(), [
+            "user",
+            "create",
+            "anna.deluca",
+            "--random-password",
+            "--surname=De Luca",
+            "--home-directory=\\\\server\\homes\\anna.deluca",
+            "show"
+        ]);
+    }
+
+    #[test]
+    fn invalid_names_and_paths_are_refused() {
+        let args = || SambaArgs::new("group", "addmembers");
+
+        assert!(matches!(args().user("-h"), Err(Error::InvalidInput(_))));
+        assert!(matches!(args().user("anna.deluca.studente01"), Err(Error::InvalidInput(_))));
+        assert!(matches!(args().user("anna;rm"), Err(Error::InvalidInput(_))));
+        assert!(matches!(args().group("--help"), Err(Error::InvalidInput(_))));
+        assert!(matches!(args().group("3B\\4C"), Err(Error::InvalidInput(_))));
+        assert!(matches!(args().path_option("profile-path", "/home/anna"), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn option_values_stay_in_their_argument() {
+        // A value starting with '-' cannot be taken for another option
+        let args = SambaArgs::new("user", "create").option("given-name", "--help");
+
+        assert_eq!(args.args(), ["user", "create", "--given-name=--help"]);
+    }
+
+    #[test]
+    fn samba_tool_is_run_with_the_arguments() {
+        let runner = FakeRunner::new();
+        runner.push_output(&["ok"]);
+
+        let args = SambaArgs::new("user", "setpassword").user("anna.deluca").unwrap();
+        assert_eq!(args.run(&runner, &config()).unwrap(), ["ok"]);
+        args.run_with_input(&runner, &config(), "Secret#123\nSecret#123\n").unwrap();
+
+        let invocations = runner.invocations();
+        assert_eq!(invocations.len(), 2);
+        assert!(invocations.iter().all(|invocation| invocation.command == config().samba_path));
+        assert!(invocations.iter().all(|invocation| invocation.args == ["user", "setpassword", "anna.deluca"]));
+        assert_eq!(invocations[0].input, None);
+        assert_eq!(invocations[1].input.as_deref(), Some("Secret#123\nSecret#123\n"));
+    }
+}
@@ -1,4 +1,53 @@
-use std::{io, process::Command};
+use std::io::{self, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Output, Stdio};
+use crate::error::{Error, Result};
+use super::parse;
+
+///
+/// Something able to run an external program and collect its output
+/// 
+/// The command functions in `commands::user` and `commands::group` only talk
+/// to this trait, so tests can replace the real process spawning with a
+/// `FakeRunner`.
+/// 
+pub trait CommandRunner: Send + Sync {
+    ///
+    /// Run a command getting its output split into lines
+    /// 
+    /// Arguments:
+    /// * `command`: command path string
+    /// * `args`: array of strings with command arguments
+    /// 
+    fn run(&self, command: &str, args: &[&str]) -> Result<Vec<String>>;
+
+    ///
+    /// Run a command writing `input` to its standard input, used to answer
+    /// password prompts so that secrets never appear in the argument list
+    ///
+    /// Arguments:
+    /// * `command`: command path string
+    /// * `args`: array of strings with command arguments
+    /// * `input`: text written to the standard input, then closed
+    ///
+    fn run_with_input(&self, command: &str, args: &[&str], input: &str) -> Result<Vec<String>>;
+}
+
+///
+/// Command runner spawning real processes
+/// 
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, command: &str, args: &[&str]) -> Result<Vec<String>> {
+        run_command_with_output(command, args)
+    }
+
+    fn run_with_input(&self, command: &str, args: &[&str], input: &str) -> Result<Vec<String>> {
+        run_command_with_input(command, args, input)
+    }
+}
 
 ///
 /// Run a command getting its output and splitting it into multiple lines
@@ -8,18 +57,81 @@ use std::{io, process::Command};
 /// * `args`: array of strings with command arguments
 /// 
 /// Returns:
-/// * `io::Result<Vec<String>>` containing the separated lines
+/// * `Result<Vec<String>>` containing the separated lines, or the reason
+///   why the command could not be run or failed
 /// 
-pub fn run_command_with_output(command: &str, args: &[&str]) -> io::Result<Vec<String>> {
+pub fn run_command_with_output(command: &str, args: &[&str]) -> Result<Vec<String>> {
     let output = Command::new(command)
         .args(args)
-        .output()?;
+        .output()
+        .map_err(|err| spawn_error(command, err))?;
+
+    collect_output(command, output)
+}
+
+///
+/// Run a command feeding its standard input, getting its output split
+/// into multiple lines
+///
+/// The command runs in a new session, without a controlling terminal:
+/// programs asking for passwords (samba-tool uses Python `getpass`) then
+/// read them from the standard input instead of `/dev/tty`.
+///
+/// Arguments:
+/// * `command`: command path string
+/// * `args`: array of strings with command arguments
+/// * `input`: text written to the standard input, then closed
+///
+/// Returns:
+/// * `Result<Vec<String>>` containing the separated lines, or the reason
+///   why the command could not be run or failed
+///
+pub fn run_command_with_input(command: &str, args: &[&str], input: &str) -> Result<Vec<String>> {
+    let mut cmd = Command::new(command);
+    cmd.args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    // SAFETY: setsid is async-signal-safe and touches no memory of the parent
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    let mut child = cmd.spawn().map_err(|err| spawn_error(command, err))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // A command failing before reading exits on its own, its status tells why
+        match stdin.write_all(input.as_bytes()) {
+            Err(err) if err.kind() != io::ErrorKind::BrokenPipe => return Err(Error::Io(err)),
+            _ => {}
+        }
+    }
+
+    let output = child.wait_with_output()?;
+    collect_output(command, output)
+}
+
+fn spawn_error(command: &str, err: io::Error) -> Error {
+    match err.kind() {
+        io::ErrorKind::NotFound => Error::CommandNotFound(command.to_string()),
+        _ => Error::Io(err)
+    }
+}
 
+///
+/// Split the standard output of a successful command into lines, or
+/// classify its failure
+///
+fn collect_output(command: &str, output: Output) -> Result<Vec<String>> {
     if !output.status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("Command failed with status: {}", output.status)
-        ));
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        return Err(classify_failure(command, output.status.code(), stderr));
     }
 
     let stdout = String::from_utf8_lossy(&output.stdout);
@@ -29,4 +141,96 @@ pub fn run_command_with_output(command: &str, args: &[&str]) -> io::Result<Vec<S
         .collect();
 
     Ok(lines)
-}
\ No newline at end of file
+}
+
+///
+/// Turn a failed samba-tool run into the most specific error available,
+/// looking at the LDB messages it prints on stderr
+/// 
+fn classify_failure(command: &str, status: Option<i32>, stderr: String) -> Error {
+    let lowered = stderr.to_lowercase();
+    let message = parse::error_message(&stderr);
+
+    if lowered.contains("already exists") || lowered.contains("already_exists") || lowered.contains("already in use") {
+        Error::AlreadyExists(message)
+    } else if lowered.contains("constraint violation") {
+        Error::ConstraintViolation(message)
+    } else if lowered.contains("unable to find") || lowered.contains("no such object") {
+        Error::NotFound(message)
+    } else {
+        Error::CommandFailed {
+            command: command.to_string(),
+            status,
+            stderr: message
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(err: &Error) -> &'static str {
+        match err {
+            Error::AlreadyExists(_) => "exists",
+            Error::ConstraintViolation(_) => "constraint",
+            Error::NotFound(_) => "not found",
+            Error::CommandFailed { .. } => "failed",
+            _ => "other"
+        }
+    }
+
+    #[test]
+    fn failures_are_classified_from_the_ldb_messages() {
+        let cases = [
+            (
+                "ERROR(ldb): Failed to add user 'mario.rossi':  - 00002071: samldb: Account name (sAMAccountName) 'mario.rossi' already in use!",
+                "exists"
+            ),
+            (
+                "ERROR(ldb): Failed to create group '3B': LDAP_ENTRY_ALREADY_EXISTS - Entry CN=3B,CN=Users,DC=samdom,DC=example,DC=com already exists",
+                "exists"
+            ),
+            (
+                "ERROR(ldb): Failed to add user 'mario.rossi':  - 0000052D: Constraint violation - check_password_restrictions: the password is too short. It should be equal or longer than 7 characters!",
+                "constraint"
+            ),
+            (
+                "ERROR: Unable to find user \"mario.rossi\"",
+                "not found"
+            ),
+            (
+                "Traceback (most recent call last):\n  File \"/usr/bin/samba-tool\", line 33, in <module>\nldb.LdbError: (32, 'No such object')",
+                "not found"
+            ),
+            (
+                "ERROR: Invalid command 'user getgroup'",
+                "failed"
+            )
+        ];
+
+        for (stderr, expected) in cases {
+            let err = classify_failure("samba-tool", Some(255), stderr.to_string());
+            assert_eq!(kind(&err), expected, "{} -> {:?}", stderr, err);
+        }
+    }
+
+    #[test]
+    fn classified_failures_keep_the_error_line_only() {
+        let stderr = "Traceback (most recent call last):\n  File \"/usr/lib/python3/dist-packages/samba/netcmd/user.py\", line 412, in run\nERROR: Unable to find user \"mario.rossi\"\n";
+
+        match classify_failure("samba-tool", Some(255), stderr.to_string()) {
+            Error::NotFound(message) => assert_eq!(message, "ERROR: Unable to find user \"mario.rossi\""),
+            err => panic!("unexpected error {:?}", err)
+        }
+
+        match classify_failure("samba-tool", None, "Killed\n".to_string()) {
+            Error::CommandFailed { command, status, stderr } => {
+                assert_eq!(command, "samba-tool");
+                assert_eq!(status, None);
+                assert_eq!(stderr, "Killed");
+            },
+            err => panic!("unexpected error {:?}", err)
+        }
+    }
+}
@@ -0,0 +1,24 @@
+use crate::config::tools_config::ToolsConfiguration;
+use crate::error::Result;
+use crate::password::policy::DomainPasswordPolicy;
+use super::args::SambaArgs;
+use super::common::CommandRunner;
+use super::parse;
+
+///
+/// Read the password policy of the Samba domain
+///
+/// Arguments:
+/// * `runner`: command runner used to invoke samba-tool
+/// * `config`: system configuration
+///
+/// Returns:
+/// * the `DomainPasswordPolicy` printed by samba-tool
+///
+pub fn password_settings(runner: &dyn CommandRunner, config: &ToolsConfiguration) -> Result<DomainPasswordPolicy> {
+    let result = SambaArgs::new("domain", "passwordsettings")
+        .arg("show")
+        .run(runner, config)?;
+
+    Ok(parse::parse_password_settings(&result))
+}
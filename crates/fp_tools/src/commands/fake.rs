@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use crate::error::Result;
+use super::common::CommandRunner;
+
+///
+/// Command invocation recorded by the `FakeRunner`
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    pub command: String,
+    pub args: Vec<String>,
+
+    /// Text written to the standard input, if any
+    pub input: Option<String>
+}
+
+///
+/// Command runner that never spawns a process: it records every invocation
+/// and replays the scripted results in order.
+///
+/// When the script runs out, every further command succeeds with no output.
+///
+#[derive(Debug, Default)]
+pub struct FakeRunner {
+    invocations: Mutex<Vec<Invocation>>,
+    script: Mutex<VecDeque<Result<Vec<String>>>>
+}
+
+impl FakeRunner {
+    ///
+    /// Create a fake runner with an empty script
+    ///
+    pub fn new() -> FakeRunner {
+        FakeRunner::default()
+    }
+
+    ///
+    /// Script the output of the next command
+    ///
+    /// Arguments:
+    /// * `lines`: lines printed on standard output
+    ///
+    pub fn push_output(&self, lines: &[&str]) -> &FakeRunner {
+        let lines = lines.iter().map(|line| line.to_string()).collect();
+        self.script.lock().unwrap().push_back(Ok(lines));
+        self
+    }
+
+    ///
+    /// Script a failure of the next command
+    ///
+    /// Arguments:
+    /// * `error`: error returned to the caller
+    ///
+    pub fn push_error(&self, error: crate::error::Error) -> &FakeRunner {
+        self.script.lock().unwrap().push_back(Err(error));
+        self
+    }
+
+    ///
+    /// Get all the invocations recorded so far
+    ///
+    pub fn invocations(&self) -> Vec<Invocation> {
+        self.invocations.lock().unwrap().clone()
+    }
+
+    ///
+    /// Get the argument vectors of the invocations recorded so far
+    ///
+    pub fn argv(&self) -> Vec<Vec<String>> {
+        self.invocations()
+            .into_iter()
+            .map(|invocation| invocation.args)
+            .collect()
+    }
+}
+
+impl FakeRunner {
+    fn record(&self, command: &str, args: &[&str], input: Option<&str>) -> Result<Vec<String>> {
+        self.invocations.lock().unwrap().push(Invocation {
+            command: command.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            input: input.map(str::to_string)
+        });
+
+        self.script.lock().unwrap().pop_front().unwrap_or(Ok(Vec::new()))
+    }
+}
+
+impl CommandRunner for FakeRunner {
+    fn run(&self, command: &str, args: &[&str]) -> Result<Vec<String>> {
+        self.record(command, args, None)
+    }
+
+    fn run_with_input(&self, command: &str, args: &[&str], input: &str) -> Result<Vec<String>> {
+        self.record(command, args, Some(input))
+    }
+}
@@ -1,115 +1,198 @@
-use std::process;
 use crate::config::tools_config::ToolsConfiguration;
-use super::common;
+use crate::error::Result;
+use super::args::SambaArgs;
+use super::common::CommandRunner;
+use super::parse;
 
 ///
 /// Check if the group exists in the Samba domain
-/// 
+///
 /// Arguments:
+/// * `runner`: command runner used to invoke samba-tool
 /// * `config`: system configuration
 /// * `group`: group to find
-/// 
+///
 /// Returns:
-/// * a boolean value - `true` if the group exists, `false` otherwise
-/// 
-pub fn is_existing_group(config: &ToolsConfiguration, group: &String) -> bool {
-    let result = common::run_command_with_output(
-        &config.samba_path, 
-        &[
-            "group",
-            "list"
-        ]);
+/// * `Ok(true)` if the group exists (case-insensitive match), `Ok(false)` otherwise
+///
+pub fn is_existing_group(runner: &dyn CommandRunner, config: &ToolsConfiguration, group: &str) -> Result<bool> {
+    Ok(parse::contains_name(&list_groups(runner, config)?, group))
+}
 
-    if let Ok(result) = result {
-        return result.contains(group);
-    }
+///
+/// List all the groups of the Samba domain
+///
+/// Arguments:
+/// * `runner`: command runner used to invoke samba-tool
+/// * `config`: system configuration
+///
+/// Returns:
+/// * a `Vec<String>` with the group names
+///
+pub fn list_groups(runner: &dyn CommandRunner, config: &ToolsConfiguration) -> Result<Vec<String>> {
+    let result = SambaArgs::new("group", "list").run(runner, config)?;
 
-    eprintln!("{:?}", result.err());
-    process::exit(1);
+    Ok(parse::parse_names(&result))
 }
 
 ///
 /// Add a new group to the Samba domain
-/// 
+///
 /// Arguments:
+/// * `runner`: command runner used to invoke samba-tool
 /// * `config`: system configuration
 /// * `group`: group name to add
-/// 
+///
 /// Returns:
-/// * boolean `true` on success, `false` otherwise
-/// 
-pub fn add_group(config: &ToolsConfiguration, group: &String) -> bool {
-    let result = common::run_command_with_output(
-        &config.samba_path,
-    &[
-        "group",
-        "add",
-        &format!("\"{}\"", group)
-    ]);
-
-    if let Ok(_) = result {
-        return true;
-    }
+/// * `Ok(())` on success, the samba-tool error otherwise
+///
+pub fn add_group(runner: &dyn CommandRunner, config: &ToolsConfiguration, group: &str) -> Result<()> {
+    SambaArgs::new("group", "add")
+        .group(group)?
+        .run(runner, config)?;
 
-    eprintln!("{:?}", result.err());
-    process::exit(1);
+    Ok(())
+}
+
+///
+/// Delete a group from the Samba domain
+///
+/// Arguments:
+/// * `runner`: command runner used to invoke samba-tool
+/// * `config`: system configuration
+/// * `group`: group name to delete
+///
+/// Returns:
+/// * `Ok(())` on success, the samba-tool error otherwise
+///
+pub fn delete_group(runner: &dyn CommandRunner, config: &ToolsConfiguration, group: &str) -> Result<()> {
+    SambaArgs::new("group", "delete")
+        .group(group)?
+        .run(runner, config)?;
+
+    Ok(())
 }
 
 ///
 /// Add member to group
-/// 
+///
 /// Arguments:
+/// * `runner`: command runner used to invoke samba-tool
 /// * `config`: system configuration
 /// * `group`: group common name
 /// * `username`: user common name
-/// 
+///
 /// Returns:
-/// * boolean `true` on success, `false` otherwise
-/// 
-pub fn add_member(config: &ToolsConfiguration, group: &String, username: &String) -> bool {
-    let result = common::run_command_with_output(
-        &config.samba_path,
-        &[
-            "group",
-            "addmembers",
-            &format!("\"{}\"", group),
-            &format!("\"{}\"", username)
-        ]
-    );
-
-    if let Ok(_) = result {
-        return true;
-    }
+/// * `Ok(())` on success, the samba-tool error otherwise
+///
+pub fn add_member(runner: &dyn CommandRunner, config: &ToolsConfiguration, group: &str, username: &str) -> Result<()> {
+    SambaArgs::new("group", "addmembers")
+        .group(group)?
+        .user(username)?
+        .run(runner, config)?;
+
+    Ok(())
+}
+
+///
+/// Remove member from group
+///
+/// Arguments:
+/// * `runner`: command runner used to invoke samba-tool
+/// * `config`: system configuration
+/// * `group`: group common name
+/// * `username`: user common name
+///
+/// Returns:
+/// * `Ok(())` on success, the samba-tool error otherwise
+///
+pub fn remove_member(runner: &dyn CommandRunner, config: &ToolsConfiguration, group: &str, username: &str) -> Result<()> {
+    SambaArgs::new("group", "removemembers")
+        .group(group)?
+        .user(username)?
+        .run(runner, config)?;
+
+    Ok(())
+}
+
+///
+/// List the members of a specific group
+///
+/// Arguments:
+/// * `runner`: command runner used to invoke samba-tool
+/// * `config`: system configuration
+/// * `group`: group name
+///
+/// Returns:
+/// * a `Vec<String>` with the member names
+///
+pub fn list_members(runner: &dyn CommandRunner, config: &ToolsConfiguration, group: &str) -> Result<Vec<String>> {
+    let result = SambaArgs::new("group", "listmembers")
+        .group(group)?
+        .run(runner, config)?;
 
-    eprintln!("{:?}", result.err());
-    process::exit(1);
+    Ok(parse::parse_names(&result))
 }
 
 ///
 /// Checks the membership of a user in a specific group
-/// 
+///
 /// Arguments:
+/// * `runner`: command runner used to invoke samba-tool
 /// * `config`: system configuration
 /// * `group`: group name
 /// * `username`: user common name
-/// 
+///
 /// Returns:
-/// * `true` when the user is member, `false` on non-membership or operational error
-/// 
-pub fn check_membership(config: &ToolsConfiguration, group: &String, username: &String) -> bool {
-    let result = common::run_command_with_output(
-        &config.samba_path,
-        &[
-            "group",
-            "listmembers",
-            &format!("\"{}\"", group)
-        ]
-    );
-
-    if let Ok(result) = result {
-        return result.contains(username);
+/// * `Ok(true)` when the user is member, `Ok(false)` otherwise
+///
+pub fn check_membership(runner: &dyn CommandRunner, config: &ToolsConfiguration, group: &str, username: &str) -> Result<bool> {
+    Ok(parse::contains_name(&list_members(runner, config, group)?, username))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::fake::FakeRunner;
+    use crate::config::tools_config::tests::config;
+    use crate::error::Error;
+    use super::*;
+
+    #[test]
+    fn group_arguments() {
+        let runner = FakeRunner::new();
+        add_group(&runner, &config(), "3B").unwrap();
+        add_member(&runner, &config(), "3B", "anna.deluca").unwrap();
+        remove_member(&runner, &config(), "3B", "anna.deluca").unwrap();
+        delete_group(&runner, &config(), "3B").unwrap();
+
+        assert_eq!(runner.argv(), [
+            vec!["group", "add", "3B"],
+            vec!["group", "addmembers", "3B", "anna.deluca"],
+            vec!["group", "removemembers", "3B", "anna.deluca"],
+            vec!["group", "delete", "3B"]
+        ]);
+        assert!(runner.invocations().iter().all(|invocation| invocation.command == "samba-tool" && invocation.input.is_none()));
+    }
+
+    #[test]
+    fn bad_names_are_refused_before_running() {
+        let runner = FakeRunner::new();
+
+        assert!(matches!(add_group(&runner, &config(), "--help"), Err(Error::InvalidInput(_))));
+        assert!(matches!(add_member(&runner, &config(), "3B", "anna,deluca"), Err(Error::InvalidInput(_))));
+        assert!(matches!(remove_member(&runner, &config(), "3B|4C", "anna.deluca"), Err(Error::InvalidInput(_))));
+        assert!(runner.invocations().is_empty());
     }
 
-    eprintln!("{:?}", result.err());
-    process::exit(1);
-}
\ No newline at end of file
+    #[test]
+    fn membership_is_case_insensitive() {
+        let runner = FakeRunner::new();
+        runner
+            .push_output(&["Anna.DeLuca", "mario.rossi"])
+            .push_output(&["Domain Users", "3b"]);
+
+        assert!(check_membership(&runner, &config(), "3B", "anna.deluca").unwrap());
+        assert!(is_existing_group(&runner, &config(), "3B").unwrap());
+        assert_eq!(runner.argv(), [vec!["group", "listmembers", "3B"], vec!["group", "list"]]);
+    }
+}
@@ -1,3 +1,8 @@
 pub mod user;
 pub mod group;
-pub mod common;
\ No newline at end of file
+pub mod domain;
+pub mod common;
+pub mod fake;
+pub mod parse;
+pub mod args;
+pub mod validate;
\ No newline at end of file
@@ -0,0 +1,389 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use crate::password::policy::DomainPasswordPolicy;
+
+///
+/// Single LDIF entry, as printed by `samba-tool user show`
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LdifEntry {
+    pub dn: String,
+    pub attributes: Vec<(String, String)>
+}
+
+impl LdifEntry {
+    ///
+    /// Get the first value of an attribute, attribute names are case-insensitive
+    ///
+    pub fn get(&self, attr: &str) -> Option<&str> {
+        self.get_all(attr).into_iter().next()
+    }
+
+    ///
+    /// Get all the values of a multi-valued attribute
+    ///
+    pub fn get_all(&self, attr: &str) -> Vec<&str> {
+        self.attributes
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case(attr))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+}
+
+///
+/// Domain user as described by `samba-tool user show`
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserRecord {
+    pub dn: String,
+    pub name: String,
+    pub given_name: Option<String>,
+    pub surname: Option<String>,
+    pub home_directory: Option<String>,
+    pub profile_path: Option<String>,
+    /// Common names of the groups the user is member of (primary group excluded)
+    pub member_of: Vec<String>,
+    /// Every attribute printed for the user
+    pub entry: LdifEntry
+}
+
+impl UserRecord {
+    ///
+    /// Build a user record out of an LDIF entry
+    ///
+    /// Returns:
+    /// * `None` if the entry has no `sAMAccountName`
+    ///
+    pub fn from_entry(entry: LdifEntry) -> Option<UserRecord> {
+        let owned = |attr: &str| entry.get(attr).map(str::to_string);
+
+        Some(UserRecord {
+            dn: entry.dn.clone(),
+            name: owned("sAMAccountName")?,
+            given_name: owned("givenName"),
+            surname: owned("sn"),
+            home_directory: owned("homeDirectory"),
+            profile_path: owned("profilePath"),
+            member_of: entry.get_all("memberOf")
+                .into_iter()
+                .map(|dn| rdn_value(dn).to_string())
+                .collect(),
+            entry
+        })
+    }
+}
+
+///
+/// Compare two account names the way Active Directory does (case-insensitive)
+///
+pub fn names_equal(a: &str, b: &str) -> bool {
+    a == b || a.to_lowercase() == b.to_lowercase()
+}
+
+///
+/// Check if a list of account names contains the given one
+///
+pub fn contains_name(names: &[String], name: &str) -> bool {
+    names.iter().any(|candidate| names_equal(candidate, name))
+}
+
+///
+/// Parse the output of `user list`, `group list`, `group listmembers` and
+/// `user getgroups`: one name per line, blank lines ignored
+///
+/// Arguments:
+/// * `lines`: command output lines
+///
+/// Returns:
+/// * a `Vec<String>` with the trimmed names
+///
+pub fn parse_names(lines: &[String]) -> Vec<String> {
+    lines
+        .iter()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+///
+/// Parse LDIF text into entries, handling folded lines, comments and
+/// base64 encoded (`attr:: value`) values
+///
+/// Arguments:
+/// * `lines`: command output lines
+///
+/// Returns:
+/// * a `Vec<LdifEntry>` with one element per `dn:` block
+///
+pub fn parse_ldif(lines: &[String]) -> Vec<LdifEntry> {
+    let mut entries = Vec::new();
+    let mut current: Option<LdifEntry> = None;
+
+    for line in unfold(lines) {
+        if line.is_empty() {
+            entries.extend(current.take());
+            continue;
+        }
+
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let Some((attr, value)) = parse_attribute(&line) else {
+            continue;
+        };
+
+        if attr.eq_ignore_ascii_case("dn") {
+            entries.extend(current.take());
+            current = Some(LdifEntry { dn: value, attributes: Vec::new() });
+        } else if let Some(entry) = current.as_mut() {
+            entry.attributes.push((attr, value));
+        }
+    }
+
+    entries.extend(current);
+    entries
+}
+
+///
+/// Extract the message samba-tool printed for a failure, skipping the
+/// Python traceback that sometimes precedes it
+///
+/// Arguments:
+/// * `stderr`: complete standard error of the command
+///
+/// Returns:
+/// * the `ERROR(...)` line if present, the last non-empty line otherwise
+///
+pub fn error_message(stderr: &str) -> String {
+    let lines: Vec<&str> = stderr
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    lines.iter()
+        .find(|line| line.starts_with("ERROR"))
+        .or(lines.last())
+        .map(|line| line.to_string())
+        .unwrap_or_default()
+}
+
+///
+/// Value of the first RDN of a distinguished name (`CN=3B,CN=Users,...` -> `3B`)
+///
+fn rdn_value(dn: &str) -> &str {
+    let mut escaped = false;
+    let end = dn.char_indices()
+        .find(|&(_, c)| {
+            let split = c == ',' && !escaped;
+            escaped = c == '\\' && !escaped;
+            split
+        })
+        .map(|(index, _)| index)
+        .unwrap_or(dn.len());
+
+    let rdn = &dn[..end];
+    rdn.split_once('=').map(|(_, value)| value).unwrap_or(rdn)
+}
+
+///
+/// Join LDIF continuation lines (starting with a single space) to the previous one
+///
+fn unfold(lines: &[String]) -> Vec<String> {
+    let mut unfolded: Vec<String> = Vec::new();
+
+    for line in lines {
+        match (line.strip_prefix(' '), unfolded.last_mut()) {
+            (Some(rest), Some(previous)) if !previous.is_empty() => previous.push_str(rest),
+            _ => unfolded.push(line.to_string())
+        }
+    }
+
+    unfolded
+}
+
+fn parse_attribute(line: &str) -> Option<(String, String)> {
+    let (attr, rest) = line.split_once(':')?;
+
+    let value = if let Some(encoded) = rest.strip_prefix(':') {
+        let bytes = STANDARD.decode(encoded.trim()).ok()?;
+        String::from_utf8_lossy(&bytes).into_owned()
+    } else {
+        rest.trim_start().to_string()
+    };
+
+    Some((attr.trim().to_string(), value))
+}
+
+///
+/// Parse the output of `samba-tool domain passwordsettings show`
+///
+/// Arguments:
+/// * `lines`: command output lines, `Label: value` pairs
+///
+/// Returns:
+/// * the `DomainPasswordPolicy`, settings missing from the output keep the
+///   Samba defaults
+///
+pub fn parse_password_settings(lines: &[String]) -> DomainPasswordPolicy {
+    let mut policy = DomainPasswordPolicy::default();
+
+    for line in lines {
+        let Some((label, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+
+        match label.trim().to_lowercase().as_str() {
+            "password complexity" => policy.complexity = value.eq_ignore_ascii_case("on"),
+            "password history length" => policy.history_length = value.parse().unwrap_or(policy.history_length),
+            "minimum password length" => policy.min_length = value.parse().unwrap_or(policy.min_length),
+            _ => {}
+        }
+    }
+
+    policy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    /// `samba-tool user show mario.rossi`, with a folded `memberOf` and a
+    /// base64 `givenName`
+    const USER_SHOW: &str = "\
+dn: CN=mario.rossi,CN=Users,DC=samdom,DC=example,DC=com
+objectClass: top
+objectClass: person
+objectClass: organizationalPerson
+objectClass: user
+cn: mario.rossi
+sn: Rossi
+givenName:: Tmljb2zDsg==
+memberOf: CN=3B,CN=Users,DC=samdom,DC=example,DC=com
+memberOf: CN=Rossi\\, Bianchi e Verdi - laboratorio di informatica,CN=Users,DC=s
+ amdom,DC=example,DC=com
+homeDirectory: \\\\dc1\\homes\\mario.rossi
+sAMAccountName: mario.rossi
+";
+
+    #[test]
+    fn parse_ldif_reads_a_user() {
+        let entries = parse_ldif(&lines(USER_SHOW));
+        assert_eq!(entries.len(), 1);
+
+        let user = UserRecord::from_entry(entries[0].clone()).unwrap();
+        assert_eq!(user.dn, "CN=mario.rossi,CN=Users,DC=samdom,DC=example,DC=com");
+        assert_eq!(user.name, "mario.rossi");
+        assert_eq!(user.given_name.as_deref(), Some("Nicolò"));
+        assert_eq!(user.surname.as_deref(), Some("Rossi"));
+        assert_eq!(user.home_directory.as_deref(), Some("\\\\dc1\\homes\\mario.rossi"));
+        assert_eq!(user.member_of, ["3B", "Rossi\\, Bianchi e Verdi - laboratorio di informatica"]);
+        assert_eq!(user.entry.get_all("OBJECTCLASS").len(), 4);
+    }
+
+    #[test]
+    fn parse_ldif_splits_entries_and_skips_comments() {
+        let text = "\
+# record 1
+dn: CN=3B,CN=Users,DC=samdom,DC=example,DC=com
+sAMAccountName: 3B
+
+# record 2
+dn: CN=4C,CN=Users,DC=samdom,DC=example,DC=com
+description:: IFJvc3Np
+sAMAccountName: 4C
+";
+        let entries = parse_ldif(&lines(text));
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].get("sAMAccountName"), Some("3B"));
+        assert_eq!(entries[1].get("samaccountname"), Some("4C"));
+        // Base64 keeps the leading space a plain value would lose
+        assert_eq!(entries[1].get("description"), Some(" Rossi"));
+    }
+
+    #[test]
+    fn parse_ldif_ignores_bad_lines() {
+        let text = "\
+memberOf: CN=orphan,CN=Users,DC=samdom,DC=example,DC=com
+dn: CN=3B,CN=Users,DC=samdom,DC=example,DC=com
+not an attribute
+description:: not base64!
+cn: 3B
+";
+        let entries = parse_ldif(&lines(text));
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].attributes, [("cn".to_string(), "3B".to_string())]);
+        assert!(parse_ldif(&[]).is_empty());
+    }
+
+    #[test]
+    fn rdn_value_keeps_escaped_commas() {
+        let cases = [
+            ("CN=3B,CN=Users,DC=samdom,DC=example,DC=com", "3B"),
+            ("CN=Rossi\\, Mario,CN=Users,DC=samdom", "Rossi\\, Mario"),
+            // An escaped backslash does not escape the comma after it
+            ("CN=dir\\\\,CN=Users,DC=samdom", "dir\\\\"),
+            ("CN=a=b,CN=Users", "a=b"),
+            ("CN=3B", "3B"),
+            ("3B", "3B")
+        ];
+
+        for (dn, value) in cases {
+            assert_eq!(rdn_value(dn), value, "{}", dn);
+        }
+    }
+
+    #[test]
+    fn error_message_picks_the_samba_tool_error() {
+        let cases = [
+            (
+                "ERROR(ldb): Failed to add user 'mario.rossi':  - 00002071: samldb: Account name (sAMAccountName) 'mario.rossi' already in use!\n",
+                "ERROR(ldb): Failed to add user 'mario.rossi':  - 00002071: samldb: Account name (sAMAccountName) 'mario.rossi' already in use!"
+            ),
+            (
+                "Traceback (most recent call last):\n  File \"/usr/lib/python3/dist-packages/samba/netcmd/user.py\", line 412, in run\n    samdb.deleteuser(username)\nERROR: Unable to find user \"mario.rossi\"\n\n",
+                "ERROR: Unable to find user \"mario.rossi\""
+            ),
+            (
+                "Traceback (most recent call last):\n  File \"/usr/bin/samba-tool\", line 33, in <module>\nldb.LdbError: (32, 'No such object')\n",
+                "ldb.LdbError: (32, 'No such object')"
+            ),
+            ("  \n", "")
+        ];
+
+        for (stderr, message) in cases {
+            assert_eq!(error_message(stderr), message);
+        }
+    }
+
+    #[test]
+    fn names_are_compared_like_active_directory() {
+        let cases = [
+            ("mario.rossi", "mario.rossi", true),
+            ("Mario.Rossi", "mario.ROSSI", true),
+            ("NICOLÒ.ROSSI", "nicolò.rossi", true),
+            ("mario.rossi", "mario.rossi2", false),
+            ("mario.rossi", "mario.rossi ", false)
+        ];
+
+        for (a, b, equal) in cases {
+            assert_eq!(names_equal(a, b), equal, "{} / {}", a, b);
+        }
+        assert!(contains_name(&["3B".to_string(), "Domain Users".to_string()], "domain users"));
+    }
+
+    #[test]
+    fn names_are_one_per_line() {
+        assert_eq!(parse_names(&lines("Administrator\n  mario.rossi  \n\nGuest\n")), ["Administrator", "mario.rossi", "Guest"]);
+    }
+}
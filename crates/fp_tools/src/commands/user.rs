@@ -1,14 +1,28 @@
-use std::process;
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, Zeroizing};
 use crate::config::tools_config::ToolsConfiguration;
-use crate::commands::common;
+use crate::commands::args::SambaArgs;
+use crate::commands::common::CommandRunner;
+use crate::commands::parse;
+use crate::commands::parse::UserRecord;
+use crate::error::{Error, Result};
 
-const SRV_DELIM: &'static str = "\\\\";
-const PATH_DELIM: &'static str = "\\";
+const SRV_DELIM: &str = "\\\\";
+const PATH_DELIM: &str = "\\";
+
+///
+/// Drive letter the home directory is mapped to
+///
+pub const HOME_DRIVE: &str = "H:";
 
 ///
 /// Temporary user data
 /// 
-#[derive(Clone, PartialEq, Eq)]
+/// The password is wiped from memory when the user is dropped and never
+/// shown by `Debug`.
+/// 
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DomainUser {
     pub common_name: String,
     pub last_name: String,
@@ -17,148 +31,353 @@ pub struct DomainUser {
     pub password: String
 }
 
+impl fmt::Debug for DomainUser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DomainUser")
+            .field("common_name", &self.common_name)
+            .field("last_name", &self.last_name)
+            .field("first_name", &self.first_name)
+            .field("groups", &self.groups)
+            .field("password", &format_args!("<redacted>"))
+            .finish()
+    }
+}
+
+impl Drop for DomainUser {
+    fn drop(&mut self) {
+        self.password.zeroize();
+    }
+}
+
 ///
 /// Check if a user exists in the samba domain
 /// 
 /// Arguments:
+/// * `runner`: command runner used to invoke samba-tool
 /// * `config`: system configuration
 /// * `username`: username to find
 /// 
 /// Returns:
-/// * a boolean value - `true` if the user exists, `false` otherwise
+/// * `Ok(true)` if the user exists (case-insensitive match), `Ok(false)` otherwise
 /// 
-pub fn is_existing_user(config: &ToolsConfiguration, username: &String) -> bool {
-    let result = common::run_command_with_output(
-        &config.samba_path,
-        &[
-            "user",
-            "list"
-        ]
-    );
+pub fn is_existing_user(runner: &dyn CommandRunner, config: &ToolsConfiguration, username: &str) -> Result<bool> {
+    Ok(parse::contains_name(&list_users(runner, config)?, username))
+}
 
-    if let Ok(result) = result {
-        return result.contains(username);
-    }
+///
+/// List all the users of the samba domain
+/// 
+/// Arguments:
+/// * `runner`: command runner used to invoke samba-tool
+/// * `config`: system configuration
+/// 
+/// Returns:
+/// * a `Vec<String>` with the user names
+/// 
+pub fn list_users(runner: &dyn CommandRunner, config: &ToolsConfiguration) -> Result<Vec<String>> {
+    let result = SambaArgs::new("user", "list").run(runner, config)?;
 
-    eprintln!("{:?}", result.err());
-    process::exit(1)
+    Ok(parse::parse_names(&result))
 }
 
 ///
-/// Add a domain user to the current Samba domain using 'samba-tool'
+/// Show the attributes of a domain user
 /// 
 /// Arguments:
+/// * `runner`: command runner used to invoke samba-tool
 /// * `config`: system configuration
-/// * `user`: domain user data
+/// * `username`: domain user common name
 /// 
 /// Returns:
-/// * `true` if successful, `false` otherwise
+/// * a `UserRecord` parsed from the LDIF output
 /// 
-pub fn add_user(config: &ToolsConfiguration, user: &DomainUser) -> bool {
+pub fn show_user(runner: &dyn CommandRunner, config: &ToolsConfiguration, username: &str) -> Result<UserRecord> {
+    let result = SambaArgs::new("user", "show")
+        .user(username)?
+        .run(runner, config)?;
 
-    let last_name = format!("--surname=\"{}\"", &user.last_name);
-    let first_name = format!("--given-name=\"{}\"", &user.first_name);
+    parse::parse_ldif(&result)
+        .into_iter()
+        .find_map(UserRecord::from_entry)
+        .ok_or_else(|| Error::NotFound(format!("user {}", username)))
+}
 
-    let share = format!(
-        "--home-directory=\"{}{}{}{}{}{}\"",
+///
+/// Build the UNC path of the home directory of a domain user
+/// 
+/// Arguments:
+/// * `config`: system configuration
+/// * `username`: domain user common name
+/// 
+/// Returns:
+/// * the `\\server\share\username` path as a `String`
+/// 
+pub fn home_directory(config: &ToolsConfiguration, username: &str) -> String {
+    format!(
+        "{}{}{}{}{}{}",
         SRV_DELIM,
         &config.srv_name,
         PATH_DELIM,
         &config.home_dirs_share,
         PATH_DELIM,
-        &user.common_name
-    );
+        username
+    )
+}
 
-    let profile = format!(
-        "--profile-path=\"{}{}{}{}{}{}{}.profiles{}{}",
-        SRV_DELIM,
-        &config.srv_name,
-        PATH_DELIM,
-        &config.home_dirs_share,
-        PATH_DELIM,
-        &user.common_name,
+///
+/// Build the UNC path of the roaming profile of a domain user
+/// 
+/// Arguments:
+/// * `config`: system configuration
+/// * `username`: domain user common name
+/// 
+/// Returns:
+/// * the `\\server\share\username\.profiles\username` path as a `String`
+/// 
+pub fn profile_path(config: &ToolsConfiguration, username: &str) -> String {
+    format!(
+        "{}{}.profiles{}{}",
+        home_directory(config, username),
         PATH_DELIM,
         PATH_DELIM,
-        &user.common_name
-    );
-
-    let result = common::run_command_with_output(
-        &config.samba_path,
-    &[
-        "user",
-        "create",
-        &format!("\"{}\"", &user.common_name),
-        &user.password,
-        "--use-username-as-cn",
-        &last_name,
-        &first_name,
-        "--home-drive:H:",
-        &share,
-        &profile
-    ]);
-
-    if let Ok(_) = result {
-        return true;
-    }
+        username
+    )
+}
+
+///
+/// Answers to the two password prompts of samba-tool (new password and
+/// confirmation), wiped from memory once written
+///
+fn password_prompts(password: &str) -> Zeroizing<String> {
+    Zeroizing::new(format!("{}\n{}\n", password, password))
+}
+
+///
+/// Add a domain user to the current Samba domain using 'samba-tool'
+/// 
+/// The password is typed at the samba-tool prompt through the standard
+/// input, so it does not show up in the process list. User name, home
+/// directory and profile path are validated before samba-tool is run.
+/// 
+/// Arguments:
+/// * `runner`: command runner used to invoke samba-tool
+/// * `config`: system configuration
+/// * `user`: domain user data
+/// 
+/// Returns:
+/// * `Ok(())` on success, `Error::InvalidInput` for a bad name or path,
+///   the samba-tool error otherwise
+/// 
+pub fn add_user(runner: &dyn CommandRunner, config: &ToolsConfiguration, user: &DomainUser) -> Result<()> {
+    SambaArgs::new("user", "create")
+        .user(&user.common_name)?
+        .flag("use-username-as-cn")
+        .option("surname", &user.last_name)
+        .option("given-name", &user.first_name)
+        .option("home-drive", HOME_DRIVE)
+        .path_option("home-directory", &home_directory(config, &user.common_name))?
+        .path_option("profile-path", &profile_path(config, &user.common_name))?
+        .run_with_input(runner, config, &password_prompts(&user.password))?;
+
+    Ok(())
+}
+
+///
+/// Delete a domain user from the current Samba domain
+/// 
+/// Arguments:
+/// * `runner`: command runner used to invoke samba-tool
+/// * `config`: system configuration
+/// * `username`: domain user common name
+/// 
+/// Returns:
+/// * `Ok(())` on success, the samba-tool error otherwise
+/// 
+pub fn delete_user(runner: &dyn CommandRunner, config: &ToolsConfiguration, username: &str) -> Result<()> {
+    SambaArgs::new("user", "delete")
+        .user(username)?
+        .run(runner, config)?;
+
+    Ok(())
+}
+
+///
+/// Changes surname and given name of a user
+/// 
+/// Arguments:
+/// * `runner`: command runner used to invoke samba-tool
+/// * `config`: system configuration
+/// * `username`: domain user common name
+/// * `surname`: new surname
+/// * `given_name`: new given name
+/// 
+/// Returns:
+/// * `Ok(())` on success, the samba-tool error otherwise
+/// 
+pub fn rename_user(runner: &dyn CommandRunner, config: &ToolsConfiguration, username: &str, surname: &str, given_name: &str) -> Result<()> {
+    SambaArgs::new("user", "rename")
+        .user(username)?
+        .option("surname", surname)
+        .option("given-name", given_name)
+        .run(runner, config)?;
 
-    eprintln!("domain-adduser: {:?}", result.err());
-    process::exit(1)
+    Ok(())
 }
 
 ///
 /// Changes a user password
 /// 
+/// As for `add_user`, the password goes through the standard input.
+/// 
 /// Arguments:
+/// * `runner`: command runner used to invoke samba-tool
 /// * `config`: system configuration
 /// * `username`: domain user common name
 /// * `password`: new password
 /// 
 /// Returns:
-/// * `true` if successful, exit on error
+/// * `Ok(())` on success, the samba-tool error otherwise
 /// 
-pub fn change_password(config: &ToolsConfiguration, username: &String, password: &String) -> bool {
-    let result = common::run_command_with_output(
-        &config.samba_path,
-        &[
-            "user",
-            "setpassword",
-            &format!("--newpassword=\"{}\"", password),
-            &format!("\"{}\"", username)
-        ]
-    );
-
-    if let Ok(_) = result {
-        return true;
-    }
+pub fn change_password(runner: &dyn CommandRunner, config: &ToolsConfiguration, username: &str, password: &str) -> Result<()> {
+    SambaArgs::new("user", "setpassword")
+        .user(username)?
+        .run_with_input(runner, config, &password_prompts(password))?;
 
-    eprintln!("{:?}", result.err());
-    process::exit(1)
+    Ok(())
 }
 
 ///
 /// Gets the list of groups associated with this user
 /// 
 /// Arguments:
+/// * `runner`: command runner used to invoke samba-tool
 /// * `config`: system configuration
 /// * `username`: domain user common name
 /// 
 /// Returns:
-/// * `true` if successful, exit on error
+/// * a `Vec<String>` with the group names
 /// 
-pub fn get_groups(config:& ToolsConfiguration, username: &String) -> Vec<String> {
-    let result = common::run_command_with_output(
-        &config.samba_path,
-        &[
+pub fn get_groups(runner: &dyn CommandRunner, config: &ToolsConfiguration, username: &str) -> Result<Vec<String>> {
+    let result = SambaArgs::new("user", "getgroups")
+        .user(username)?
+        .run(runner, config)?;
+
+    Ok(parse::parse_names(&result))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::fake::FakeRunner;
+    use crate::config::tools_config::tests::config;
+    use super::*;
+
+    const PASSWORD: &str = "Secret#123";
+
+    fn user(name: &str) -> DomainUser {
+        DomainUser {
+            common_name: name.to_string(),
+            last_name: "De Luca".to_string(),
+            first_name: "Anna Maria".to_string(),
+            groups: Vec::new(),
+            password: PASSWORD.to_string()
+        }
+    }
+
+    fn assert_no_password(runner: &FakeRunner) {
+        for args in runner.argv() {
+            assert!(args.iter().all(|arg| !arg.contains(PASSWORD)), "password in {:?}", args);
+        }
+    }
+
+    #[test]
+    fn add_user_types_the_password_at_the_prompt() {
+        let runner = FakeRunner::new();
+        add_user(&runner, &config(), &user("anna.deluca")).unwrap();
+
+        let invocations = runner.invocations();
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].command, "samba-tool");
+        assert_eq!(invocations[0].args, [
             "user",
-            "getgroups",
-            &format!("\"{}\"", username)
-        ]
-    );
+            "create",
+            "anna.deluca",
+            "--use-username-as-cn",
+            "--surname=De Luca",
+            "--given-name=Anna Maria",
+            "--home-drive=H:",
+            "--home-directory=\\\\dc1\\homes\\anna.deluca",
+            "--profile-path=\\\\dc1\\homes\\anna.deluca\\.profiles\\anna.deluca"
+        ]);
+        assert_eq!(invocations[0].input.as_deref(), Some("Secret#123\nSecret#123\n"));
+        assert_no_password(&runner);
+    }
+
+    #[test]
+    fn change_password_types_the_password_at_the_prompt() {
+        let runner = FakeRunner::new();
+        change_password(&runner, &config(), "anna.deluca", PASSWORD).unwrap();
+
+        let invocations = runner.invocations();
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].args, ["user", "setpassword", "anna.deluca"]);
+        assert_eq!(invocations[0].input.as_deref(), Some("Secret#123\nSecret#123\n"));
+        assert_no_password(&runner);
+    }
+
+    #[test]
+    fn rename_and_delete_arguments() {
+        let runner = FakeRunner::new();
+        rename_user(&runner, &config(), "anna.deluca", "D'Angelo", "Anna").unwrap();
+        delete_user(&runner, &config(), "anna.deluca").unwrap();
+
+        assert_eq!(runner.argv(), [
+            vec!["user", "rename", "anna.deluca", "--surname=D'Angelo", "--given-name=Anna"],
+            vec!["user", "delete", "anna.deluca"]
+        ]);
+        assert!(runner.invocations().iter().all(|invocation| invocation.input.is_none()));
+    }
 
-    if let Ok(result) = result {
-        return result;
+    #[test]
+    fn bad_names_are_refused_before_running() {
+        let runner = FakeRunner::new();
+
+        for name in ["-H", "anna;deluca", "un.nome.davvero.troppo.lungo", ""] {
+            assert!(matches!(add_user(&runner, &config(), &user(name)), Err(Error::InvalidInput(_))), "{:?}", name);
+            assert!(matches!(delete_user(&runner, &config(), name), Err(Error::InvalidInput(_))), "{:?}", name);
+        }
+        assert!(runner.invocations().is_empty());
+    }
+
+    #[test]
+    fn samba_tool_errors_are_returned() {
+        let runner = FakeRunner::new();
+        runner.push_error(Error::AlreadyExists("ERROR(ldb): Failed to add user 'anna.deluca'".to_string()));
+
+        assert!(matches!(add_user(&runner, &config(), &user("anna.deluca")), Err(Error::AlreadyExists(_))));
     }
 
-    eprintln!("{:?}", result.err());
-    process::exit(1)
+    #[test]
+    fn queries_parse_the_output() {
+        let runner = FakeRunner::new();
+        runner
+            .push_output(&["Administrator", "Anna.DeLuca", ""])
+            .push_output(&["Domain Users", "3B"])
+            .push_output(&[
+                "dn: CN=anna.deluca,CN=Users,DC=samdom,DC=example,DC=com",
+                "sn: De Luca",
+                "sAMAccountName: anna.deluca"
+            ]);
+
+        assert!(is_existing_user(&runner, &config(), "anna.deluca").unwrap());
+        assert_eq!(get_groups(&runner, &config(), "anna.deluca").unwrap(), ["Domain Users", "3B"]);
+        assert_eq!(show_user(&runner, &config(), "anna.deluca").unwrap().surname.as_deref(), Some("De Luca"));
+        assert_eq!(runner.argv(), [
+            vec!["user", "list"],
+            vec!["user", "getgroups", "anna.deluca"],
+            vec!["user", "show", "anna.deluca"]
+        ]);
+
+        // No output: the user is gone
+        assert!(matches!(show_user(&runner, &config(), "anna.deluca"), Err(Error::NotFound(_))));
+    }
 }
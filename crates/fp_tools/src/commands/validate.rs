@@ -0,0 +1,202 @@
+use crate::error::{Error, Result};
+
+///
+/// Maximum length of a user sAMAccountName (pre-Windows 2000 logon name)
+///
+pub const MAX_ACCOUNT_NAME_LENGTH: usize = 20;
+
+///
+/// Maximum length of a group name, bound by the `cn` attribute
+///
+pub const MAX_GROUP_NAME_LENGTH: usize = 64;
+
+///
+/// Maximum length of a UNC path (Windows `MAX_PATH`)
+///
+pub const MAX_UNC_PATH_LENGTH: usize = 260;
+
+///
+/// Characters Active Directory refuses in account names
+///
+const ACCOUNT_FORBIDDEN: &str = "\"/\\[]:;|=,+*?<>@";
+
+///
+/// Characters Windows refuses in the components of a path
+///
+const PATH_FORBIDDEN: &str = "\"/\\:|*?<>";
+
+///
+/// Check that a user name is a valid sAMAccountName
+///
+/// Arguments:
+/// * `name`: user name
+///
+/// Returns:
+/// * `Ok(())` if the name can be used, `Error::InvalidInput` otherwise
+///
+pub fn account_name(name: &str) -> Result<()> {
+    check_name("user name", name, MAX_ACCOUNT_NAME_LENGTH)
+}
+
+///
+/// Check that a group name can be used as sAMAccountName and common name
+///
+/// Arguments:
+/// * `name`: group name
+///
+/// Returns:
+/// * `Ok(())` if the name can be used, `Error::InvalidInput` otherwise
+///
+pub fn group_name(name: &str) -> Result<()> {
+    check_name("group name", name, MAX_GROUP_NAME_LENGTH)
+}
+
+///
+/// Check that a path is a well formed `\\server\share[\dir...]` UNC path
+///
+/// Arguments:
+/// * `path`: UNC path
+///
+/// Returns:
+/// * `Ok(())` if the path can be used, `Error::InvalidInput` otherwise
+///
+pub fn unc_path(path: &str) -> Result<()> {
+    let invalid = |what: String| Err(Error::InvalidInput(format!("UNC path {:?}: {}", path, what)));
+
+    let length = path.chars().count();
+    if length > MAX_UNC_PATH_LENGTH {
+        return invalid(format!("{} characters long, at most {} allowed", length, MAX_UNC_PATH_LENGTH));
+    }
+
+    let Some(rest) = path.strip_prefix("\\\\") else {
+        return invalid("does not start with \\\\".to_string());
+    };
+
+    let components: Vec<&str> = rest.split('\\').collect();
+    if components.len() < 2 {
+        return invalid("no share name".to_string());
+    }
+
+    for component in components {
+        if component.is_empty() {
+            return invalid("empty path component".to_string());
+        }
+        if component == "." || component == ".." {
+            return invalid(format!("relative component {:?}", component));
+        }
+        if let Some(c) = component.chars().find(|c| c.is_control() || PATH_FORBIDDEN.contains(*c)) {
+            return invalid(format!("character {:?} is not allowed", c));
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Common rules of user and group names
+///
+fn check_name(what: &str, name: &str, max_length: usize) -> Result<()> {
+    let invalid = |reason: String| Err(Error::InvalidInput(format!("{} {:?}: {}", what, name, reason)));
+
+    if name.trim().is_empty() {
+        return invalid("empty name".to_string());
+    }
+
+    let length = name.chars().count();
+    if length > max_length {
+        return invalid(format!("{} characters long, at most {} allowed", length, max_length));
+    }
+
+    if let Some(c) = name.chars().find(|c| c.is_control() || ACCOUNT_FORBIDDEN.contains(*c)) {
+        return invalid(format!("character {:?} is not allowed", c));
+    }
+
+    // samba-tool would take it for an option
+    if name.starts_with('-') {
+        return invalid("starts with '-'".to_string());
+    }
+
+    if name.starts_with(' ') || name.ends_with(' ') || name.ends_with('.') {
+        return invalid("starts or ends with a space, or ends with '.'".to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refused(result: Result<()>) -> bool {
+        matches!(result, Err(Error::InvalidInput(_)))
+    }
+
+    #[test]
+    fn account_names() {
+        for name in ["anna.deluca", "Mario Rossi", "studente-01", "o'brien", "àèìòù", "a"] {
+            assert!(account_name(name).is_ok(), "{}", name);
+        }
+
+        for c in ACCOUNT_FORBIDDEN.chars().chain(['\t', '\n', '\0']) {
+            let name = format!("anna{}deluca", c);
+            assert!(refused(account_name(&name)), "{:?}", name);
+        }
+
+        for name in ["", "   ", "-anna", "--help", " anna", "anna ", "anna."] {
+            assert!(refused(account_name(name)), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn account_names_are_at_most_20_characters() {
+        assert!(account_name(&"a".repeat(MAX_ACCOUNT_NAME_LENGTH)).is_ok());
+        assert!(refused(account_name(&"a".repeat(MAX_ACCOUNT_NAME_LENGTH + 1))));
+
+        // Characters are counted, not bytes
+        assert!(account_name(&"è".repeat(MAX_ACCOUNT_NAME_LENGTH)).is_ok());
+    }
+
+    #[test]
+    fn group_names() {
+        assert!(group_name("Domain Users").is_ok());
+        assert!(group_name("3B").is_ok());
+        assert!(group_name(&"g".repeat(MAX_GROUP_NAME_LENGTH)).is_ok());
+        assert!(refused(group_name(&"g".repeat(MAX_GROUP_NAME_LENGTH + 1))));
+
+        for name in ["", "-3B", "3B|4C", "3B,4C", "docenti@scuola", "3B."] {
+            assert!(refused(group_name(name)), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn unc_paths() {
+        for path in ["\\\\server\\share", "\\\\server\\homes\\anna.deluca", "\\\\10.0.0.1\\profili$\\anna"] {
+            assert!(unc_path(path).is_ok(), "{}", path);
+        }
+
+        for path in [
+            "",
+            "server\\share",
+            "\\server\\share",
+            "//server/share",
+            "\\\\server",
+            "\\\\server\\",
+            "\\\\server\\\\share",
+            "\\\\server\\share\\..\\etc",
+            "\\\\server\\share\\.",
+            "\\\\server\\sha:re",
+            "\\\\server\\share\\a*b",
+            "\\\\server\\share\\a\nb"
+        ] {
+            assert!(refused(unc_path(path)), "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn unc_paths_are_at_most_260_characters() {
+        let path = |length: usize| format!("\\\\server\\share\\{}", "d".repeat(length - "\\\\server\\share\\".len()));
+
+        assert!(unc_path(&path(MAX_UNC_PATH_LENGTH)).is_ok());
+        assert!(refused(unc_path(&path(MAX_UNC_PATH_LENGTH + 1))));
+    }
+}
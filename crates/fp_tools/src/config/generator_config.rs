@@ -1,5 +1,8 @@
+use std::fmt;
 use serde::Deserialize;
-use serde_json::Result;
+use zeroize::Zeroize;
+use crate::config::password_config::PasswordSettings;
+use crate::error::{Error, Result};
 
 ///
 /// Default MySQL port
@@ -9,7 +12,10 @@ pub const DEFAULT_SQL_PORT: u32 = 3306;
 ///
 /// Generator configuration data
 /// 
-#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+/// The database password is wiped from memory on drop and never shown
+/// by `Debug`.
+/// 
+#[derive(Deserialize, PartialEq, Eq, Clone)]
 pub struct GeneratorConfig {
     #[serde(rename = "databaseHost")]
     db_host: String,
@@ -21,7 +27,28 @@ pub struct GeneratorConfig {
     db_user: String,
 
     #[serde(rename = "databasePass")]
-    db_pass: String
+    db_pass: String,
+
+    #[serde(rename = "passwords", default)]
+    passwords: PasswordSettings
+}
+
+impl fmt::Debug for GeneratorConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GeneratorConfig")
+            .field("db_host", &self.db_host)
+            .field("db_name", &self.db_name)
+            .field("db_user", &self.db_user)
+            .field("db_pass", &format_args!("<redacted>"))
+            .field("passwords", &self.passwords)
+            .finish()
+    }
+}
+
+impl Drop for GeneratorConfig {
+    fn drop(&mut self) {
+        self.db_pass.zeroize();
+    }
 }
 
 impl GeneratorConfig {
@@ -34,12 +61,13 @@ impl GeneratorConfig {
     /// * `db_user`: database user for connection
     /// * `db_pass`: database user password for connection
     /// 
-    pub fn new(db_host: &String, db_name: &String, db_user: &String, db_pass: &String) -> GeneratorConfig {
+    pub fn new(db_host: &str, db_name: &str, db_user: &str, db_pass: &str) -> GeneratorConfig {
         GeneratorConfig {
-            db_host: db_host.clone(),
-            db_name: db_name.clone(),
-            db_user: db_user.clone(),
-            db_pass: db_pass.clone()
+            db_host: db_host.to_string(),
+            db_name: db_name.to_string(),
+            db_user: db_user.to_string(),
+            db_pass: db_pass.to_string(),
+            passwords: PasswordSettings::default()
         }
     }
 
@@ -67,6 +95,13 @@ impl GeneratorConfig {
     pub fn get_db_pass(&self) -> &String {
         &self.db_pass
     }
+
+    ///
+    /// Get the policies of the passwords generated for blank ones
+    /// 
+    pub fn get_passwords(&self) -> &PasswordSettings {
+        &self.passwords
+    }
 }
 
 ///
@@ -75,34 +110,21 @@ impl GeneratorConfig {
 /// Arguments:
 /// * file_path: file path to configuration
 /// 
-pub fn read_config_from_file(file_path: &str) -> Option<GeneratorConfig> {
-    let content = read_file(&file_path.to_string());
-
-    if let Some(content) = content {
-        let config = read_config(&content);
-        return config;
-    }
-
-    None
+/// Returns:
+/// * the parsed `GeneratorConfig`, or an `Error::Config` describing the problem
+/// 
+pub fn read_config_from_file(file_path: &str) -> Result<GeneratorConfig> {
+    let content = read_file(file_path)?;
+    read_config(file_path, &content)
 }
 
-fn read_config(config_text: &String) -> Option<GeneratorConfig> {
-    let config: Result<GeneratorConfig> = serde_json::from_str(&config_text);
-
-    if config.is_ok() {
-        Some(config.unwrap())
-    } else {
-        None
-    }
+fn read_config(file_path: &str, config_text: &str) -> Result<GeneratorConfig> {
+    serde_json::from_str(config_text)
+        .map_err(|err| Error::Config(format!("{}: {}", file_path, err)))
 }
 
-fn read_file(file_path: &String) -> Option<String> {
+fn read_file(file_path: &str) -> Result<String> {
     // Read the content
-    let contents = std::fs::read_to_string(&file_path);
-
-    if contents.is_ok() {
-        Some(contents.unwrap())
-    } else {
-        None
-    }
-}
\ No newline at end of file
+    std::fs::read_to_string(file_path)
+        .map_err(|err| Error::Config(format!("{}: {}", file_path, err)))
+}
@@ -1,2 +1,3 @@
 pub mod generator_config;
+pub mod password_config;
 pub mod tools_config;
\ No newline at end of file
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use serde::Deserialize;
+
+///
+/// Password generation settings, shared by every tool that creates accounts
+/// 
+#[derive(Deserialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct PasswordSettings {
+    /// Policy used for users matching no entry of `groups`; when unset the
+    /// built-in random policy is used
+    #[serde(rename = "defaultPolicy", default)]
+    pub default_policy: Option<String>,
+
+    /// Named password policies
+    #[serde(rename = "policies", default)]
+    pub policies: HashMap<String, PasswordPolicy>,
+
+    /// Policy name by group or class, e.g. `{ "1A": "primaria" }`
+    #[serde(rename = "groups", default)]
+    pub groups: HashMap<String, String>
+}
+
+///
+/// How passwords of a category of users are built
+/// 
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(tag = "kind")]
+pub enum PasswordPolicy {
+    /// Random characters from the selected classes, at least one of each
+    #[serde(rename = "random")]
+    Random {
+        #[serde(rename = "length", default = "default_random_length")]
+        length: usize,
+
+        #[serde(rename = "lowercase", default = "default_true")]
+        lowercase: bool,
+
+        #[serde(rename = "uppercase", default = "default_true")]
+        uppercase: bool,
+
+        #[serde(rename = "digits", default = "default_true")]
+        digits: bool,
+
+        #[serde(rename = "symbols", default)]
+        symbols: bool,
+
+        /// Leave out characters easily confused when read aloud or
+        /// copied from paper (`l`, `1`, `I`, `O`, `0`...)
+        #[serde(rename = "excludeAmbiguous", default = "default_true")]
+        exclude_ambiguous: bool
+    },
+
+    /// Alternating consonants and vowels, e.g. `Bamezita47`, without
+    /// ambiguous characters
+    #[serde(rename = "pronounceable")]
+    Pronounceable {
+        /// Number of letters
+        #[serde(rename = "length", default = "default_pronounceable_length")]
+        length: usize,
+
+        /// Number of digits appended
+        #[serde(rename = "digits", default = "default_digits")]
+        digits: usize
+    },
+
+    /// Simple Italian words joined by a separator, e.g. `Barca-Neve-42`,
+    /// without ambiguous characters
+    #[serde(rename = "passphrase")]
+    Passphrase {
+        #[serde(rename = "words", default = "default_words")]
+        words: usize,
+
+        #[serde(rename = "separator", default = "default_separator")]
+        separator: String,
+
+        /// Number of digits appended
+        #[serde(rename = "digits", default = "default_digits")]
+        digits: usize
+    }
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> PasswordPolicy {
+        PasswordPolicy::Random {
+            length: default_random_length(),
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: false,
+            exclude_ambiguous: true
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_random_length() -> usize {
+    12
+}
+
+fn default_pronounceable_length() -> usize {
+    8
+}
+
+fn default_digits() -> usize {
+    2
+}
+
+fn default_words() -> usize {
+    2
+}
+
+fn default_separator() -> String {
+    "-".to_string()
+}
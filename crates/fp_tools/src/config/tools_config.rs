@@ -1,4 +1,8 @@
+use std::collections::HashMap;
+use std::fmt;
 use serde::Deserialize;
+use crate::config::password_config::PasswordSettings;
+use crate::error::{Error, Result};
 
 ///
 /// Tools configuration data
@@ -33,7 +37,177 @@ pub struct ToolsConfiguration {
     pub pool_owner: String,
 
     #[serde(rename = "winbindSeparator")]
-    pub winbind_separator: String
+    pub winbind_separator: String,
+
+    #[serde(rename = "backend", default)]
+    pub backend: BackendKind,
+
+    #[serde(rename = "ldap", default)]
+    pub ldap: Option<LdapSettings>,
+
+    /// Named column mappings for input files, see `ImportProfile`
+    #[serde(rename = "importProfiles", default)]
+    pub import_profiles: HashMap<String, ImportProfile>,
+
+    /// Pattern of the generated logins, see `login::LoginPattern`
+    #[serde(rename = "loginPattern", default = "default_login_pattern")]
+    pub login_pattern: String,
+
+    /// Policies of the generated passwords
+    #[serde(rename = "passwords", default)]
+    pub passwords: PasswordSettings
+}
+
+///
+/// Directory backend used to manage the domain
+/// 
+#[derive(Deserialize, Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum BackendKind {
+    /// Run the `samba-tool` program found at `sambaPath`
+    #[default]
+    #[serde(rename = "samba-tool")]
+    SambaTool,
+
+    /// Talk to the domain controller directly over LDAP(S)
+    #[serde(rename = "ldap")]
+    Ldap
+}
+
+///
+/// LDAP connection settings, required by the `ldap` backend
+/// 
+/// The bind password is never shown by `Debug`.
+/// 
+#[derive(Deserialize, PartialEq, Eq, Clone)]
+pub struct LdapSettings {
+    /// Server URL, e.g. `ldaps://dc1.example.lan`
+    #[serde(rename = "url")]
+    pub url: String,
+
+    #[serde(rename = "bindDn")]
+    pub bind_dn: String,
+
+    #[serde(rename = "bindPass")]
+    pub bind_pass: String,
+
+    /// Naming context of the domain, e.g. `DC=example,DC=lan`
+    #[serde(rename = "baseDn")]
+    pub base_dn: String,
+
+    /// Container for new users and groups, relative to `baseDn`
+    #[serde(rename = "usersContainer", default = "default_users_container")]
+    pub users_container: String,
+
+    /// Upgrade a plain `ldap://` connection with StartTLS
+    #[serde(rename = "startTls", default)]
+    pub start_tls: bool,
+
+    /// Skip certificate verification (self-signed Samba certificates)
+    #[serde(rename = "noTlsVerify", default)]
+    pub no_tls_verify: bool
+}
+
+impl fmt::Debug for LdapSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LdapSettings")
+            .field("url", &self.url)
+            .field("bind_dn", &self.bind_dn)
+            .field("bind_pass", &format_args!("<redacted>"))
+            .field("base_dn", &self.base_dn)
+            .field("users_container", &self.users_container)
+            .field("start_tls", &self.start_tls)
+            .field("no_tls_verify", &self.no_tls_verify)
+            .finish()
+    }
+}
+
+fn default_login_pattern() -> String {
+    crate::login::DEFAULT_PATTERN.to_string()
+}
+
+fn default_users_container() -> String {
+    "CN=Users".to_string()
+}
+
+///
+/// Layout of an input file with users to import
+/// 
+/// Every setting is optional: the default profile reads the login CSV
+/// files written by `get-login`.
+/// 
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct ImportProfile {
+    /// Field delimiter, a single ASCII character
+    #[serde(rename = "delimiter", default = "default_delimiter")]
+    pub delimiter: char,
+
+    /// Character encoding label, e.g. `utf-8` or `windows-1252`
+    #[serde(rename = "encoding", default = "default_encoding")]
+    pub encoding: String,
+
+    /// Header names of the columns, when different from the standard ones
+    #[serde(rename = "columns", default)]
+    pub columns: ColumnNames,
+
+    /// Separator between the groups listed in the groups column
+    #[serde(rename = "groupSeparator", default = "default_group_separator")]
+    pub group_separator: String,
+
+    /// Group added to every imported user
+    #[serde(rename = "defaultGroup", default)]
+    pub default_group: Option<String>
+}
+
+impl Default for ImportProfile {
+    fn default() -> ImportProfile {
+        ImportProfile {
+            delimiter: default_delimiter(),
+            encoding: default_encoding(),
+            columns: ColumnNames::default(),
+            group_separator: default_group_separator(),
+            default_group: None
+        }
+    }
+}
+
+///
+/// Header names of the columns of an input file. Columns left unset are
+/// looked up by their standard English or Italian legacy name.
+/// 
+#[derive(Deserialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct ColumnNames {
+    #[serde(rename = "login", default)]
+    pub login: Option<String>,
+
+    #[serde(rename = "lastName", default)]
+    pub last_name: Option<String>,
+
+    #[serde(rename = "firstName", default)]
+    pub first_name: Option<String>,
+
+    #[serde(rename = "groups", default)]
+    pub groups: Option<String>,
+
+    #[serde(rename = "class", default)]
+    pub class: Option<String>,
+
+    #[serde(rename = "cf", default)]
+    pub cf: Option<String>,
+
+    #[serde(rename = "password", default)]
+    pub password: Option<String>
+}
+
+fn default_delimiter() -> char {
+    ';'
+}
+
+fn default_encoding() -> String {
+    "utf-8".to_string()
+}
+
+fn default_group_separator() -> String {
+    ",".to_string()
 }
 
 ///
@@ -43,35 +217,67 @@ pub struct ToolsConfiguration {
 /// * `file_path`: file path string
 /// 
 /// Returns:
-/// * `Option<ToolsConfiguration>` with all configurations
+/// * `Result<ToolsConfiguration>` with all configurations, or an
+///   `Error::Config` describing why the file could not be loaded
 /// 
-pub fn read_config_from_file(file_path: &str) -> Option<ToolsConfiguration> {
-    let content = read_file(&file_path.to_string());
+pub fn read_config_from_file(file_path: &str) -> Result<ToolsConfiguration> {
+    let content = read_file(file_path)?;
+    read_config(file_path, &content)
+}
 
-    if let Some(content) = content {
-        let config = read_config(&content);
-        return config;
-    }
+fn read_config(file_path: &str, config_text: &str) -> Result<ToolsConfiguration> {
+    serde_json::from_str(config_text)
+        .map_err(|err| Error::Config(format!("{}: {}", file_path, err)))
+}
 
-    None
+fn read_file(file_path: &str) -> Result<String> {
+    std::fs::read_to_string(file_path)
+        .map_err(|err| Error::Config(format!("{}: {}", file_path, err)))
 }
 
-fn read_config(config_text: &String) -> Option<ToolsConfiguration> {
-    let config: Result<ToolsConfiguration, serde_json::Error> = serde_json::from_str(&config_text);
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
 
-    if config.is_ok() {
-        return Some(config.unwrap());
+    ///
+    /// Configuration with the required settings only, for a `dc1` domain
+    /// controller of `samdom.example.com`
+    ///
+    pub(crate) fn config() -> ToolsConfiguration {
+        read_config("settings.json", r#"{
+            "sambaPath": "samba-tool",
+            "srvName": "dc1",
+            "homeDirsPath": "/home",
+            "homeDirsShare": "homes",
+            "domainFqdn": "samdom.example.com",
+            "ntDomainName": "SAMDOM",
+            "poolPath": "/pool",
+            "poolShare": "pool",
+            "poolOwner": "root",
+            "winbindSeparator": "\\"
+        }"#).unwrap()
     }
 
-    None
-}
-
-fn read_file(file_path: &String) -> Option<String> {
-    let contents = std::fs::read_to_string(&file_path);
+    #[test]
+    fn optional_settings_have_defaults() {
+        let config = config();
 
-    if contents.is_ok() {
-        return Some(contents.unwrap());
+        assert_eq!(config.backend, BackendKind::SambaTool);
+        assert!(config.ldap.is_none());
+        assert!(config.import_profiles.is_empty());
+        assert_eq!(config.login_pattern, crate::login::DEFAULT_PATTERN);
     }
 
-    None
-}
\ No newline at end of file
+    #[test]
+    fn errors_name_the_file() {
+        let Err(Error::Config(message)) = read_config("/etc/ad/settings.json", r#"{"sambaPath": "samba-tool"}"#) else {
+            panic!("incomplete configuration accepted");
+        };
+        assert!(message.starts_with("/etc/ad/settings.json: missing field"), "{}", message);
+
+        let Err(Error::Config(message)) = read_config_from_file("/nonexistent/settings.json") else {
+            panic!("missing file accepted");
+        };
+        assert!(message.starts_with("/nonexistent/settings.json: "), "{}", message);
+    }
+}
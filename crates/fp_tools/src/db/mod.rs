@@ -1 +1,2 @@
-pub mod rget_login;
\ No newline at end of file
+pub mod rget_login;
+pub mod watermark;
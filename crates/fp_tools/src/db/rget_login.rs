@@ -1,6 +1,8 @@
+use chrono::{NaiveDate, NaiveDateTime};
 use crate::config::generator_config::{GeneratorConfig, DEFAULT_SQL_PORT};
 use mysql::prelude::*;
 use mysql::*;
+use crate::error::{Error, Result};
 
 ///
 /// MySQL database domain user
@@ -15,7 +17,22 @@ pub struct MySQLDomainUser {
     pub tax_code: String,
     pub group: String,
     pub birth_date: String,
-    pub date_modified: Option<String>
+    pub date_modified: Option<NaiveDateTime>
+}
+
+impl MySQLDomainUser {
+    ///
+    /// Domain groups of the student: the class, then the groups listed in
+    /// the comma separated `gruppo` column
+    ///
+    pub fn groups(&self) -> Vec<String> {
+        std::iter::once(self.class.as_str())
+            .chain(self.group.split(','))
+            .map(str::trim)
+            .filter(|group| !group.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
 }
 
 ///
@@ -40,79 +57,153 @@ pub fn generate_connection_string(config: &GeneratorConfig) -> String {
 /// Arguments:
 /// * `conn_string`: connection URL string generated with the proper function
 /// 
-pub fn get_db_connection(conn_string: &String) -> Option<PooledConn> {
+/// Returns:
+/// * a `PooledConn` to the database, or an `Error::Database`
+/// 
+pub fn get_db_connection(conn_string: &str) -> Result<PooledConn> {
     // Get connection pool from SQL API
-    let url: &str = &conn_string;
-    let pool = Pool::new(url);
+    let pool = Pool::new(conn_string)?;
 
-    // Check for pool creation
-    if pool.is_err() {
-        return None
-    }
+    // Get connection from pool
+    Ok(pool.get_conn()?)
+}
 
-    // Get connection from pool and check errors
-    let conn = pool.unwrap().get_conn();
+///
+/// Columns read from the ALUNNO table, in the order expected by `to_domain_user`
+///
+const LOGIN_COLUMNS: &str = "login, cognome, nome, classe, password, CF, gruppo, data_nascita, data_modifica";
 
-    if conn.is_err() {
-        eprintln!("{}", conn.err().unwrap());
-        return None
-    }
+///
+/// Row of the ALUNNO table, as read by the login queries
+///
+type LoginRow = (String, String, String, String, String, String, String, Value, Value);
+
+///
+/// Performt the query to recover login data from database
+/// 
+/// Arguments:
+/// * `conn`: PooledConn to MySQL / MariaDB database
+/// 
+/// Returns:
+/// * a `Vec<MySQLDomainUser>` with all user data, or an `Error::Database`
+/// 
+pub fn get_login_data(conn: &mut PooledConn) -> Result<Vec<MySQLDomainUser>> {
+    let query = format!("SELECT {} FROM ALUNNO", LOGIN_COLUMNS);
 
-    Some(conn.unwrap())
+    // Query and get each row as a Vec<Value>
+    Ok(conn.query_map(query, to_domain_user)?)
 }
 
 ///
-/// Performt the query to recover login data from database
+/// Recover login data of the students modified from a given time on
+/// 
+/// Rows modified exactly at `since` are returned too: `data_modifica` may
+/// only hold the day, so later changes of the same day would be lost
+/// otherwise. Rows without `data_modifica` are never returned.
 /// 
 /// Arguments:
 /// * `conn`: PooledConn to MySQL / MariaDB database
+/// * `since`: first modification time to load, usually a previous `latest_modification`
 /// 
 /// Returns:
-/// * a `Vec<MySQLDomainUser> with all user data`
+/// * a `Vec<MySQLDomainUser>` with the changed users, or an `Error::Database`
 /// 
-pub fn get_login_data(conn: &mut PooledConn) -> Vec<MySQLDomainUser> {
-    let users: Vec<MySQLDomainUser>;
+pub fn get_login_data_since(conn: &mut PooledConn, since: &NaiveDateTime) -> Result<Vec<MySQLDomainUser>> {
+    let query = format!("SELECT {} FROM ALUNNO WHERE data_modifica >= ?", LOGIN_COLUMNS);
 
-    const QUERY: &str = "SELECT login, cognome, nome, classe, password, CF, gruppo, data_nascita, data_modifica FROM ALUNNO";
+    Ok(conn.exec_map(query, (since,), to_domain_user)?)
+}
 
-    // Query and get each row as a Vec<Value>
-    let result = conn.query_map(
-        QUERY,
-        |(login, cognome, nome, classe, password, cf, gruppo, nascita, modifica): (
-            String, String, String, String, String, String, String, Value, Value
-        )| {
-            let birth_date = match nascita {
-                Value::Date(y, m, d, _, _, _, _) => format!("{:04}-{:02}-{:02}", y, m, d),
-                Value::Bytes(v) => String::from_utf8(v).expect("Valid date data"),
-                _ => "NULL".to_string()
-            };
-
-            let date_modified = match modifica {
-                Value::Date(y, m, d, _, _, _, _) => Some(format!("{:04}-{:02}-{:02}", y, m, d)),
-                Value::Bytes(vx) => Some(String::from_utf8(vx).expect("Valid date data")),
-                _ => None
-            };
-
-            MySQLDomainUser {
-                login,
-                last_name: cognome,
-                first_name: nome,
-                class: classe,
-                password,
-                tax_code: cf,
-                group: gruppo,
-                birth_date,
-                date_modified,
-            }
-        },
-    );
+///
+/// Recover the groups of all the students (classes and `gruppo` values),
+/// without loading the students themselves
+/// 
+/// Arguments:
+/// * `conn`: PooledConn to MySQL / MariaDB database
+/// 
+/// Returns:
+/// * the group names without duplicates, or an `Error::Database`
+/// 
+pub fn get_student_groups(conn: &mut PooledConn) -> Result<Vec<String>> {
+    const QUERY: &str = "SELECT DISTINCT classe, gruppo FROM ALUNNO";
 
-    if result.is_err() {
-        return Vec::new();
+    let rows: Vec<(String, String)> = conn.query(QUERY)?;
+    let mut groups: Vec<String> = Vec::new();
+
+    for (class, group) in rows {
+        for name in std::iter::once(class.as_str()).chain(group.split(',')).map(str::trim) {
+            if !name.is_empty() && !groups.iter().any(|known| known.eq_ignore_ascii_case(name)) {
+                groups.push(name.to_string());
+            }
+        }
     }
 
-    users = result.unwrap();
+    Ok(groups)
+}
+
+///
+/// Get the most recent modification time of a set of users
+/// 
+/// Arguments:
+/// * `users`: vector of users
+/// 
+/// Returns:
+/// * the highest `date_modified`, `None` if no user has one
+/// 
+pub fn latest_modification(users: &[MySQLDomainUser]) -> Option<NaiveDateTime> {
     users
+        .iter()
+        .filter_map(|user| user.date_modified)
+        .max()
+}
+
+///
+/// Parse a `data_modifica` value, as MySQL prints it or as given on the
+/// command line
+/// 
+/// Arguments:
+/// * `text`: `YYYY-MM-DD[ HH:MM:SS[.ffffff]]` timestamp, a day alone being its midnight
+/// 
+/// Returns:
+/// * the `NaiveDateTime`, or an `Error::InvalidInput`
+/// 
+pub fn parse_timestamp(text: &str) -> Result<NaiveDateTime> {
+    let text = text.trim();
+
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f")
+        .or_else(|_| NaiveDate::parse_from_str(text, "%Y-%m-%d").map(|day| day.and_time(Default::default())))
+        .map_err(|_| Error::InvalidInput(format!("{:?} is not a YYYY-MM-DD[ HH:MM:SS] time", text)))
+}
+
+///
+/// Build a user from a row of the login queries
+/// 
+fn to_domain_user((login, cognome, nome, classe, password, cf, gruppo, nascita, modifica): LoginRow) -> MySQLDomainUser {
+    let birth_date = match nascita {
+        Value::Date(y, m, d, _, _, _, _) => format!("{:04}-{:02}-{:02}", y, m, d),
+        Value::Bytes(v) => String::from_utf8(v).expect("Valid date data"),
+        _ => "NULL".to_string()
+    };
+
+    // Keep the time, if any: it is the high-water mark of incremental runs
+    let date_modified = match modifica {
+        Value::Date(y, m, d, h, i, s, us) => NaiveDate::from_ymd_opt(y.into(), m.into(), d.into())
+            .and_then(|day| day.and_hms_micro_opt(h.into(), i.into(), s.into(), us)),
+        Value::Bytes(vx) => Some(parse_timestamp(&String::from_utf8(vx).expect("Valid date data")).expect("Valid date data")),
+        _ => None
+    };
+
+    MySQLDomainUser {
+        login,
+        last_name: cognome,
+        first_name: nome,
+        class: classe,
+        password,
+        tax_code: cf,
+        group: gruppo,
+        birth_date,
+        date_modified,
+    }
 }
 
 ///
@@ -125,7 +216,7 @@ pub fn get_login_data(conn: &mut PooledConn) -> Vec<MySQLDomainUser> {
 /// Returns:
 /// * `Vec<MySQLDomainUser> filtered by class`
 /// 
-pub fn filter_by_class(users: &Vec<MySQLDomainUser>, class: &String) -> Vec<MySQLDomainUser> {
+pub fn filter_by_class(users: &[MySQLDomainUser], class: &str) -> Vec<MySQLDomainUser> {
     users
         .iter()
         .filter(|user| user.class == *class)
@@ -143,10 +234,28 @@ pub fn filter_by_class(users: &Vec<MySQLDomainUser>, class: &String) -> Vec<MySQ
 /// Returns:
 /// * `Vec<MySQLDomainUser>` filtered by group
 /// 
-pub fn filter_by_group(users: &Vec<MySQLDomainUser>, group: &String) -> Vec<MySQLDomainUser> {
+pub fn filter_by_group(users: &[MySQLDomainUser], group: &str) -> Vec<MySQLDomainUser> {
     users
         .iter()
         .filter(|user| user.group == *group)
         .cloned()
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_are_parsed_as_times() {
+        let midnight = parse_timestamp("2024-05-02").unwrap();
+
+        assert_eq!(parse_timestamp(" 2024-05-02 00:00:00 ").unwrap(), midnight);
+        assert!(parse_timestamp("2024-05-02 00:00:00.000001").unwrap() > midnight);
+        assert!(parse_timestamp("2024-05-02 08:30:00").unwrap() > midnight);
+
+        for text in ["", "02/05/2024", "2024-05-02T08:30:00", "2024-02-30", "2024-05-02 25:00:00"] {
+            assert!(matches!(parse_timestamp(text), Err(Error::InvalidInput(_))), "{:?}", text);
+        }
+    }
+}
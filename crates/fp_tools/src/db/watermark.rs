@@ -0,0 +1,240 @@
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use crate::commands::parse::contains_name;
+use crate::error::{Error, Result};
+use super::rget_login::MySQLDomainUser;
+
+///
+/// High-water mark of incremental runs: the most recent `data_modifica`
+/// handled by the last successful run of a tool
+///
+/// The next run loads the students changed from that time on, included, as
+/// `data_modifica` may only hold the day. Tools that must not handle a
+/// student twice keep the logins already handled at that time.
+///
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Watermark {
+    #[serde(rename = "lastModified", default, with = "timestamp")]
+    pub last_modified: Option<NaiveDateTime>,
+
+    /// Logins changed at `last_modified` that have been handled
+    #[serde(rename = "handledAtMark", default, skip_serializing_if = "Vec::is_empty")]
+    pub handled: Vec<String>
+}
+
+impl Watermark {
+    ///
+    /// Move the mark forward, never back
+    ///
+    /// Arguments:
+    /// * `latest`: most recent modification handled by the run, see `latest_modification`
+    ///
+    pub fn advance(&mut self, latest: Option<NaiveDateTime>) {
+        if latest > self.last_modified {
+            self.last_modified = latest;
+            self.handled.clear();
+        }
+    }
+
+    ///
+    /// Remember the users changed at the time of the mark, so that the
+    /// next run can leave them out with `is_handled`. Call it after `advance`.
+    ///
+    /// Arguments:
+    /// * `users`: users handled by the run
+    ///
+    pub fn record_handled(&mut self, users: &[MySQLDomainUser]) {
+        for user in users {
+            if self.is_at_mark(user) && !contains_name(&self.handled, &user.login) {
+                self.handled.push(user.login.clone());
+            }
+        }
+    }
+
+    ///
+    /// Returns:
+    /// * `true` if `user` has been handled by a previous run, at the time
+    ///   of the mark
+    ///
+    pub fn is_handled(&self, user: &MySQLDomainUser) -> bool {
+        self.is_at_mark(user) && contains_name(&self.handled, &user.login)
+    }
+
+    fn is_at_mark(&self, user: &MySQLDomainUser) -> bool {
+        user.date_modified.is_some() && user.date_modified == self.last_modified
+    }
+}
+
+///
+/// `lastModified` as `YYYY-MM-DD HH:MM:SS`; files written with the day
+/// alone for midnight are read too
+///
+mod timestamp {
+    use chrono::NaiveDateTime;
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use crate::db::rget_login::parse_timestamp;
+
+    pub fn serialize<S: Serializer>(value: &Option<NaiveDateTime>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.serialize_some(&value.format("%Y-%m-%d %H:%M:%S%.f").to_string()),
+            None => serializer.serialize_none()
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<NaiveDateTime>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|text| parse_timestamp(&text).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+///
+/// Load the high-water mark saved by a previous run
+///
+/// Arguments:
+/// * `file_path`: state file of the tool
+///
+/// Returns:
+/// * the saved `Watermark`, an empty one if the file does not exist yet, or
+///   an `Error::Config` if the file cannot be read
+///
+pub fn read_watermark(file_path: &str) -> Result<Watermark> {
+    let content = match fs::read_to_string(file_path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Watermark::default()),
+        Err(err) => return Err(Error::Config(format!("{}: {}", file_path, err)))
+    };
+
+    serde_json::from_str(&content)
+        .map_err(|err| Error::Config(format!("{}: {}", file_path, err)))
+}
+
+///
+/// Save the high-water mark of a successful run
+///
+/// The file is replaced atomically, so an interrupted run leaves the
+/// previous mark in place. Missing parent directories are created.
+///
+/// Arguments:
+/// * `file_path`: state file of the tool
+/// * `watermark`: mark to save
+///
+pub fn write_watermark(file_path: &str, watermark: &Watermark) -> Result<()> {
+    let path = Path::new(file_path);
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+
+    let content = serde_json::to_string_pretty(watermark)
+        .map_err(|err| Error::Config(format!("{}: {}", file_path, err)))?;
+
+    let temp_path = format!("{}.tmp", file_path);
+    let mut file = fs::File::create(&temp_path)?;
+    file.write_all(content.as_bytes())?;
+    file.write_all(b"\n")?;
+    file.sync_all()?;
+
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::batch::journal::tests::TempJournal;
+    use crate::db::rget_login::parse_timestamp;
+    use super::*;
+
+    fn at(text: &str) -> Option<NaiveDateTime> {
+        Some(parse_timestamp(text).unwrap())
+    }
+
+    fn student(login: &str, modified: &str) -> MySQLDomainUser {
+        MySQLDomainUser {
+            login: login.to_string(),
+            last_name: String::new(),
+            first_name: String::new(),
+            class: "3B".to_string(),
+            password: String::new(),
+            tax_code: String::new(),
+            group: String::new(),
+            birth_date: String::new(),
+            date_modified: at(modified)
+        }
+    }
+
+    #[test]
+    fn mark_only_moves_forward() {
+        let mut mark = Watermark::default();
+
+        mark.advance(None);
+        assert_eq!(mark.last_modified, None);
+
+        mark.advance(at("2024-05-01 08:30:00"));
+        mark.handled.push("mario.rossi".to_string());
+
+        // Times are compared, not their text: a day alone is its midnight
+        mark.advance(at("2024-05-01"));
+        mark.advance(None);
+        assert_eq!(mark.last_modified, at("2024-05-01 08:30:00"));
+        assert_eq!(mark.handled, ["mario.rossi"]);
+
+        mark.advance(at("2024-05-01 08:30:00.5"));
+        assert_eq!(mark.last_modified, at("2024-05-01 08:30:00.500"));
+        assert!(mark.handled.is_empty());
+    }
+
+    #[test]
+    fn students_at_the_mark_are_handled_once() {
+        let students = [
+            student("mario.rossi", "2024-05-02"),
+            student("anna.bianchi", "2024-05-02 00:00:00"),
+            student("luca.verdi", "2024-05-01 23:59:59")
+        ];
+
+        let mark_time = students.iter().filter_map(|student| student.date_modified).max();
+        let mut mark = Watermark::default();
+        mark.advance(mark_time);
+        mark.record_handled(&students);
+        mark.record_handled(&students);
+
+        assert_eq!(mark.handled, ["mario.rossi", "anna.bianchi"]);
+        assert!(mark.is_handled(&student("Mario.Rossi", "2024-05-02 00:00:00")));
+        assert!(!mark.is_handled(&student("luca.verdi", "2024-05-01 23:59:59")));
+
+        // Changed again after the run
+        assert!(!mark.is_handled(&student("mario.rossi", "2024-05-02 10:00:00")));
+
+        let mut unknown = student("mario.rossi", "2024-05-02");
+        unknown.date_modified = None;
+        assert!(!mark.is_handled(&unknown));
+    }
+
+    #[test]
+    fn marks_are_saved_and_read_back() {
+        let file = TempJournal::new("watermark");
+        let mut mark = Watermark::default();
+        mark.advance(at("2024-05-02"));
+        mark.handled.push("mario.rossi".to_string());
+
+        write_watermark(&file.0, &mark).unwrap();
+        assert!(fs::read_to_string(&file.0).unwrap().contains("\"2024-05-02 00:00:00\""));
+        assert_eq!(read_watermark(&file.0).unwrap(), mark);
+
+        // Files with the day alone, or without any mark
+        fs::write(&file.0, r#"{ "lastModified": "2024-05-02", "handledAtMark": ["mario.rossi"] }"#).unwrap();
+        assert_eq!(read_watermark(&file.0).unwrap(), mark);
+
+        fs::write(&file.0, "{}").unwrap();
+        assert_eq!(read_watermark(&file.0).unwrap(), Watermark::default());
+
+        fs::write(&file.0, r#"{ "lastModified": "2 maggio" }"#).unwrap();
+        assert!(matches!(read_watermark(&file.0), Err(Error::Config(_))));
+
+        fs::remove_file(&file.0).unwrap();
+        assert_eq!(read_watermark(&file.0).unwrap(), Watermark::default());
+    }
+}
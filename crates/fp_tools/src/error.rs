@@ -0,0 +1,117 @@
+use std::fmt;
+use std::io;
+
+///
+/// Errors returned by the `fp_tools` library
+///
+#[derive(Debug)]
+pub enum Error {
+    /// The external program could not be found
+    CommandNotFound(String),
+
+    /// The external program exited with a non-zero status
+    CommandFailed {
+        command: String,
+        status: Option<i32>,
+        stderr: String
+    },
+
+    /// The object to create already exists in the domain
+    AlreadyExists(String),
+
+    /// The object does not exist in the domain
+    NotFound(String),
+
+    /// The directory refused a value (password complexity, bad attribute...)
+    ConstraintViolation(String),
+
+    /// MySQL / MariaDB error
+    Database(String),
+
+    /// Missing or invalid configuration
+    Config(String),
+
+    /// LDAP protocol or connection error
+    Ldap(String),
+
+    /// Input file that cannot be processed (unknown header, missing columns...)
+    InvalidInput(String),
+
+    /// Any other I/O error
+    Io(io::Error)
+}
+
+///
+/// Result type used throughout the library
+///
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::CommandNotFound(command) => write!(f, "command not found: {}", command),
+            Error::CommandFailed { command, status, stderr } => {
+                match status {
+                    Some(code) => write!(f, "{} exited with status {}", command, code)?,
+                    None => write!(f, "{} was terminated by a signal", command)?
+                }
+
+                if !stderr.is_empty() {
+                    write!(f, ": {}", stderr)?;
+                }
+
+                Ok(())
+            },
+            Error::AlreadyExists(what) => write!(f, "already exists: {}", what),
+            Error::NotFound(what) => write!(f, "not found: {}", what),
+            Error::ConstraintViolation(what) => write!(f, "constraint violation: {}", what),
+            Error::Database(what) => write!(f, "database error: {}", what),
+            Error::Config(what) => write!(f, "configuration error: {}", what),
+            Error::Ldap(what) => write!(f, "LDAP error: {}", what),
+            Error::InvalidInput(what) => write!(f, "invalid input: {}", what),
+            Error::Io(err) => write!(f, "I/O error: {}", err)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<mysql::Error> for Error {
+    fn from(err: mysql::Error) -> Error {
+        Error::Database(err.to_string())
+    }
+}
+
+impl From<ldap3::LdapError> for Error {
+    fn from(err: ldap3::LdapError) -> Error {
+        match err {
+            ldap3::LdapError::LdapResult { result } => Error::from(result),
+            err => Error::Ldap(err.to_string())
+        }
+    }
+}
+
+impl From<ldap3::LdapResult> for Error {
+    fn from(result: ldap3::LdapResult) -> Error {
+        // RFC 4511 result codes
+        match result.rc {
+            19 => Error::ConstraintViolation(result.text),
+            32 => Error::NotFound(result.text),
+            68 => Error::AlreadyExists(result.text),
+            _ => Error::Ldap(result.to_string())
+        }
+    }
+}
@@ -1,4 +1,10 @@
+pub mod backend;
+pub mod batch;
 pub mod config;
 pub mod db;
+pub mod error;
+pub mod login;
+pub mod password;
+pub mod sync;
 pub mod tools;
-pub mod commands;
\ No newline at end of file
+pub mod commands;
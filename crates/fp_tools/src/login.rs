@@ -0,0 +1,380 @@
+use std::collections::HashSet;
+use crate::error::{Error, Result};
+
+///
+/// Maximum length of a login (sAMAccountName limit)
+///
+pub const MAX_LOGIN_LENGTH: usize = 20;
+
+///
+/// Pattern used when none is configured
+///
+pub const DEFAULT_PATTERN: &str = "{nome}.{cognome}";
+
+///
+/// Piece of a login pattern
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(String),
+    FirstName,
+    LastName,
+    FirstInitial,
+    LastInitial,
+    Year,
+    ShortYear
+}
+
+///
+/// Template of the generated logins
+///
+/// Placeholders, English or Italian:
+/// * `{first}` / `{nome}`: first name
+/// * `{last}` / `{cognome}`: last name
+/// * `{f}` / `{n}`: initial of the first name
+/// * `{l}` / `{c}`: initial of the last name
+/// * `{year}` / `{anno}`: four digit year
+/// * `{yy}` / `{aa}`: two digit year
+///
+/// Any other character is copied as it is, so `{nome}.{cognome}`,
+/// `{n}{cognome}` and `{cognome}{yy}` are all valid patterns.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginPattern {
+    tokens: Vec<Token>
+}
+
+impl LoginPattern {
+    ///
+    /// Parse a pattern
+    ///
+    /// Arguments:
+    /// * `pattern`: pattern text, e.g. `{nome}.{cognome}`
+    ///
+    /// Returns:
+    /// * the `LoginPattern`, `Error::Config` if it has unknown placeholders,
+    ///   characters not allowed in a login, no name at all, no room for
+    ///   the names, or would give logins starting with `-` or ending with `.`
+    ///
+    pub fn parse(pattern: &str) -> Result<LoginPattern> {
+        let invalid = |what: String| Error::Config(format!("login pattern '{}': {}", pattern, what));
+
+        let mut tokens = Vec::new();
+        let mut rest = pattern;
+
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('{') {
+                let end = after.find('}').ok_or_else(|| invalid("unterminated placeholder".to_string()))?;
+
+                let token = match after[..end].to_lowercase().as_str() {
+                    "first" | "nome" => Token::FirstName,
+                    "last" | "cognome" => Token::LastName,
+                    "f" | "n" => Token::FirstInitial,
+                    "l" | "c" => Token::LastInitial,
+                    "year" | "anno" => Token::Year,
+                    "yy" | "aa" => Token::ShortYear,
+                    other => return Err(invalid(format!("unknown placeholder {{{}}}", other)))
+                };
+
+                tokens.push(token);
+                rest = &after[end + 1..];
+            } else {
+                let end = rest.find('{').unwrap_or(rest.len());
+                let literal = &rest[..end];
+
+                if let Some(c) = literal.chars().find(|c| !is_login_char(*c)) {
+                    return Err(invalid(format!("character {:?} is not allowed in a login", c)));
+                }
+
+                tokens.push(Token::Literal(literal.to_string()));
+                rest = &rest[end..];
+            }
+        }
+
+        let has_name = tokens.iter().any(|token| matches!(token, Token::FirstName | Token::LastName));
+        if !has_name {
+            return Err(invalid("no {nome} or {cognome} placeholder".to_string()));
+        }
+
+        // At least a letter of every name must fit
+        let shortest: usize = tokens
+            .iter()
+            .map(|token| match token {
+                Token::Literal(text) => text.len(),
+                Token::Year => 4,
+                Token::ShortYear => 2,
+                _ => 1
+            })
+            .sum();
+        if shortest > MAX_LOGIN_LENGTH {
+            return Err(invalid(format!("leaves no room for the names in {} characters", MAX_LOGIN_LENGTH)));
+        }
+
+        // Account names cannot start with '-' nor end with '.'
+        if let Some(Token::Literal(text)) = tokens.first() && text.starts_with('-') {
+            return Err(invalid("a login cannot start with '-'".to_string()));
+        }
+        if let Some(Token::Literal(text)) = tokens.last() && text.ends_with('.') {
+            return Err(invalid("a login cannot end with '.'".to_string()));
+        }
+
+        Ok(LoginPattern { tokens })
+    }
+}
+
+impl Default for LoginPattern {
+    fn default() -> LoginPattern {
+        LoginPattern::parse(DEFAULT_PATTERN).unwrap()
+    }
+}
+
+///
+/// Builds unique logins from first and last names
+///
+/// A generated login never matches a reserved one (existing domain users,
+/// logins given explicitly in the input) nor one generated before: on a
+/// collision a counter is appended, `mario.rossi`, `mario.rossi2`... Names
+/// are shortened, last name first, so that the result always fits in
+/// `MAX_LOGIN_LENGTH` characters.
+///
+#[derive(Debug, Clone)]
+pub struct LoginGenerator {
+    pattern: LoginPattern,
+    year: i32,
+    taken: HashSet<String>
+}
+
+impl LoginGenerator {
+    ///
+    /// Arguments:
+    /// * `pattern`: template of the logins
+    /// * `year`: value of the year placeholders (usually the school year start)
+    ///
+    pub fn new(pattern: LoginPattern, year: i32) -> LoginGenerator {
+        LoginGenerator { pattern, year, taken: HashSet::new() }
+    }
+
+    ///
+    /// Mark logins as already in use (case-insensitive)
+    ///
+    pub fn reserve<I, S>(&mut self, logins: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>
+    {
+        self.taken.extend(logins.into_iter().map(|login| login.as_ref().to_lowercase()));
+    }
+
+    ///
+    /// Generate and reserve a new login
+    ///
+    /// Arguments:
+    /// * `first_name`: first name, as written in the input
+    /// * `last_name`: last name, as written in the input
+    ///
+    /// Returns:
+    /// * the login, `None` if a name used by the pattern has no usable characters
+    ///
+    pub fn generate(&mut self, first_name: &str, last_name: &str) -> Option<String> {
+        let first = normalize(first_name);
+        let last = normalize(last_name);
+
+        let uses = |tokens: [Token; 2]| self.pattern.tokens.iter().any(|t| tokens.contains(t));
+
+        if (first.is_empty() && uses([Token::FirstName, Token::FirstInitial]))
+            || (last.is_empty() && uses([Token::LastName, Token::LastInitial])) {
+            return None;
+        }
+
+        for counter in 1.. {
+            let suffix = if counter == 1 { String::new() } else { counter.to_string() };
+            let login = self.render(&first, &last, &suffix);
+
+            if self.taken.insert(login.clone()) {
+                return Some(login);
+            }
+        }
+
+        unreachable!()
+    }
+
+    ///
+    /// Render the pattern, shortening the names to make room for the suffix
+    ///
+    fn render(&self, first: &str, last: &str, suffix: &str) -> String {
+        let fixed: usize = self.pattern.tokens
+            .iter()
+            .map(|token| match token {
+                Token::FirstName | Token::LastName => 0,
+                token => self.piece(token, first, last).len()
+            })
+            .sum::<usize>() + suffix.len();
+
+        let budget = MAX_LOGIN_LENGTH.saturating_sub(fixed);
+        let first_uses = self.pattern.tokens.iter().filter(|t| **t == Token::FirstName).count();
+        let last_uses = self.pattern.tokens.iter().filter(|t| **t == Token::LastName).count();
+
+        // Cut the last name first, then the first name, never below one letter
+        let mut first_len = first.len();
+        let mut last_len = last.len();
+
+        while first_len * first_uses + last_len * last_uses > budget {
+            if last_uses > 0 && last_len > 1 {
+                last_len -= 1;
+            } else if first_uses > 0 && first_len > 1 {
+                first_len -= 1;
+            } else {
+                break;
+            }
+        }
+
+        let (first, last) = (&first[..first_len], &last[..last_len]);
+        let mut login: String = self.pattern.tokens
+            .iter()
+            .map(|token| self.piece(token, first, last))
+            .collect();
+
+        // Patterns with long literals may still not fit
+        login.truncate(MAX_LOGIN_LENGTH.saturating_sub(suffix.len()));
+        login.push_str(suffix);
+        login
+    }
+
+    fn piece(&self, token: &Token, first: &str, last: &str) -> String {
+        match token {
+            Token::Literal(text) => text.to_lowercase(),
+            Token::FirstName => first.to_string(),
+            Token::LastName => last.to_string(),
+            Token::FirstInitial => first.chars().take(1).collect(),
+            Token::LastInitial => last.chars().take(1).collect(),
+            Token::Year => format!("{:04}", self.year),
+            Token::ShortYear => format!("{:02}", self.year.rem_euclid(100))
+        }
+    }
+}
+
+///
+/// Turn a name into login characters: lowercase ASCII letters and digits
+/// only, accents transliterated, apostrophes, spaces and hyphens dropped
+/// (`D'Angelo` becomes `dangelo`, `De Luca` becomes `deluca`)
+///
+pub fn normalize(name: &str) -> String {
+    name.chars()
+        .flat_map(char::to_lowercase)
+        .map(strip_accent)
+        .filter(char::is_ascii_alphanumeric)
+        .collect()
+}
+
+///
+/// Characters accepted in a login: ASCII letters, digits, `.`, `-` and `_`
+///
+pub fn is_login_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_')
+}
+
+fn strip_accent(c: char) -> char {
+    match c {
+        'à' | 'á' | 'â' | 'ä' | 'ã' | 'å' => 'a',
+        'è' | 'é' | 'ê' | 'ë' => 'e',
+        'ì' | 'í' | 'î' | 'ï' => 'i',
+        'ò' | 'ó' | 'ô' | 'ö' | 'õ' => 'o',
+        'ù' | 'ú' | 'û' | 'ü' => 'u',
+        'ç' => 'c',
+        'ñ' => 'n',
+        c => c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generator(pattern: &str) -> LoginGenerator {
+        LoginGenerator::new(LoginPattern::parse(pattern).unwrap(), 2024)
+    }
+
+    #[test]
+    fn patterns_render_every_placeholder() {
+        let cases = [
+            ("{nome}.{cognome}", "mario.rossi"),
+            ("{first}.{last}", "mario.rossi"),
+            ("{n}{cognome}", "mrossi"),
+            ("{cognome}{f}", "rossim"),
+            ("{nome}.{c}", "mario.r"),
+            ("{l}{nome}", "rmario"),
+            ("{cognome}{yy}", "rossi24"),
+            ("{nome}.{cognome}.{anno}", "mario.rossi.2024"),
+            ("S_{NOME}-{cognome}", "s_mario-rossi")
+        ];
+
+        for (pattern, login) in cases {
+            assert_eq!(generator(pattern).generate("Mario", "Rossi").as_deref(), Some(login), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn invalid_patterns_are_refused() {
+        for pattern in ["{nome", "{nome}.{surname}", "{nome} {cognome}", "{anno}{n}", "-{nome}", "{cognome}.", ""] {
+            assert!(matches!(LoginPattern::parse(pattern), Err(Error::Config(_))), "{:?}", pattern);
+        }
+
+        assert!(LoginPattern::parse("{nome}-{cognome}.{aa}").is_ok());
+    }
+
+    #[test]
+    fn names_are_normalised() {
+        let cases = [
+            ("Nicolò", "D'Angelo", "nicolo.dangelo"),
+            ("Anna", "De Luca", "anna.deluca"),
+            ("José", "Çelik", "jose.celik"),
+            ("Maria-Grazia", "Dell'Orto", "mariagrazia.dellorto"),
+            ("ÈLIA", "O’NEIL", "elia.oneil"),
+            ("Luca 2", "Rossi", "luca2.rossi")
+        ];
+
+        let mut logins = generator(DEFAULT_PATTERN);
+        for (first_name, last_name, login) in cases {
+            assert_eq!(logins.generate(first_name, last_name).as_deref(), Some(login), "{} {}", first_name, last_name);
+        }
+
+        // A name with nothing usable left
+        assert_eq!(logins.generate("Mario", "'’ -"), None);
+        assert_eq!(generator("{cognome}{yy}").generate("", "Rossi").as_deref(), Some("rossi24"));
+    }
+
+    #[test]
+    fn long_names_are_cut_to_the_limit() {
+        let mut logins = generator(DEFAULT_PATTERN);
+
+        // Last name first, never below one letter
+        assert_eq!(logins.generate("Anna Maria", "Della Valle Bianchi").as_deref(), Some("annamaria.dellavalle"));
+        assert_eq!(logins.generate("Maria Francesca Antonietta", "Rossi").as_deref(), Some("mariafrancescaanto.r"));
+
+        // The counter takes the room of the last name
+        assert_eq!(logins.generate("Anna Maria", "Della Valle Bianchi").as_deref(), Some("annamaria.dellavall2"));
+
+        for login in ["annamaria.dellavalle", "mariafrancescaanto.r", "annamaria.dellavall2"] {
+            assert_eq!(login.len(), MAX_LOGIN_LENGTH);
+        }
+
+        // Long literals leave little room to the names
+        let mut logins = generator("studente.{anno}.{nome}.{cognome}");
+        assert_eq!(logins.generate("Mario", "Rossi").as_deref(), Some("studente.2024.mari.r"));
+        assert_eq!(logins.generate("Mario", "Rossi").as_deref(), Some("studente.2024.mar.r2"));
+        assert!(LoginPattern::parse("alunno.scuola.primaria.{cognome}").is_err());
+    }
+
+    #[test]
+    fn collisions_get_a_counter() {
+        let mut logins = generator(DEFAULT_PATTERN);
+        logins.reserve(["Mario.Rossi", "mario.rossi3"]);
+
+        let generated: Vec<String> = (0..3).filter_map(|_| logins.generate("Mario", "Rossi")).collect();
+        assert_eq!(generated, ["mario.rossi2", "mario.rossi4", "mario.rossi5"]);
+
+        // Different names giving the same login collide too
+        assert_eq!(logins.generate("Anna", "D'Amico").as_deref(), Some("anna.damico"));
+        assert_eq!(logins.generate("Anna", "Da Mico").as_deref(), Some("anna.damico2"));
+    }
+}
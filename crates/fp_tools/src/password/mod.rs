@@ -0,0 +1,408 @@
+pub mod policy;
+mod words;
+
+use std::collections::HashMap;
+use rand::Rng;
+use rand::rngs::OsRng;
+use rand::seq::SliceRandom;
+use crate::config::password_config::{PasswordPolicy, PasswordSettings};
+use crate::error::{Error, Result};
+use policy::DomainPasswordPolicy;
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+const SYMBOLS: &str = "!#$%&*+-=?@_";
+
+///
+/// Characters left out by policies excluding ambiguous characters
+///
+const AMBIGUOUS: &str = "lI1O0o";
+
+///
+/// Passwords generated looking for one accepted by the domain policy
+///
+const COMPLIANT_ATTEMPTS: usize = 20;
+
+// Pronounceable passwords and passphrases are meant for young students:
+// they never contain ambiguous characters
+const VOWELS: &str = "aeiu";
+const CONSONANTS: &str = "bcdfgmnprstvz";
+const UNAMBIGUOUS_DIGITS: &str = "23456789";
+
+///
+/// Builds passwords following the policy of each user's groups
+///
+#[derive(Debug, Clone, Default)]
+pub struct PasswordGenerator {
+    default_policy: PasswordPolicy,
+
+    /// Policy by lowercase group name
+    by_group: HashMap<String, PasswordPolicy>
+}
+
+impl PasswordGenerator {
+    ///
+    /// Build a generator from the configured settings
+    ///
+    /// Arguments:
+    /// * `settings`: policies and their assignment to groups
+    ///
+    /// Returns:
+    /// * the `PasswordGenerator`, `Error::Config` if a policy cannot produce
+    ///   passwords or a group refers to an unknown policy
+    ///
+    pub fn new(settings: &PasswordSettings) -> Result<PasswordGenerator> {
+        for (name, policy) in &settings.policies {
+            check_policy(policy).map_err(|what| Error::Config(format!("password policy '{}': {}", name, what)))?;
+        }
+
+        let find = |name: &str| {
+            settings.policies
+                .get(name)
+                .cloned()
+                .ok_or_else(|| Error::Config(format!("unknown password policy '{}'", name)))
+        };
+
+        let default_policy = match &settings.default_policy {
+            Some(name) => find(name)?,
+            None => PasswordPolicy::default()
+        };
+
+        let mut by_group = HashMap::new();
+        for (group, name) in &settings.groups {
+            by_group.insert(group.to_lowercase(), find(name)?);
+        }
+
+        Ok(PasswordGenerator { default_policy, by_group })
+    }
+
+    ///
+    /// Policy of a user: the one of the first listed group that has a
+    /// policy, the default one otherwise
+    ///
+    pub fn policy_for(&self, groups: &[String]) -> &PasswordPolicy {
+        groups
+            .iter()
+            .find_map(|group| self.by_group.get(&group.to_lowercase()))
+            .unwrap_or(&self.default_policy)
+    }
+
+    ///
+    /// Generate a password for a user belonging to the given groups
+    ///
+    pub fn generate(&self, groups: &[String]) -> String {
+        generate(self.policy_for(groups))
+    }
+
+    ///
+    /// Generate a password that the domain controller will accept
+    ///
+    /// Arguments:
+    /// * `groups`: groups of the user, choosing the generation policy
+    /// * `domain`: password policy of the domain
+    /// * `login`: account name of the user
+    /// * `full_name`: first and last name of the user
+    ///
+    /// Returns:
+    /// * the password, `None` if the generation policy never satisfies the
+    ///   domain one (e.g. it is shorter than the minimum length)
+    ///
+    pub fn generate_compliant(
+        &self,
+        groups: &[String],
+        domain: &DomainPasswordPolicy,
+        login: &str,
+        full_name: &[&str]
+    ) -> Option<String> {
+        (0..COMPLIANT_ATTEMPTS)
+            .map(|_| self.generate(groups))
+            .find(|password| domain.violations(password, login, full_name).is_empty())
+    }
+}
+
+///
+/// Generate a password with the given policy, using the operating system
+/// random number generator
+///
+pub fn generate(policy: &PasswordPolicy) -> String {
+    let mut rng = OsRng;
+
+    match policy {
+        PasswordPolicy::Random { length, exclude_ambiguous, .. } => {
+            let classes: Vec<Vec<char>> = classes(policy)
+                .into_iter()
+                .map(|class| class.chars().filter(|c| !*exclude_ambiguous || !AMBIGUOUS.contains(*c)).collect())
+                .collect();
+
+            // One character of every class, then any class, then shuffle
+            let mut password: Vec<char> = classes.iter().map(|class| pick(&mut rng, class)).collect();
+            let all: Vec<char> = classes.concat();
+
+            while password.len() < *length {
+                password.push(pick(&mut rng, &all));
+            }
+
+            password.shuffle(&mut rng);
+            password.into_iter().collect()
+        },
+        PasswordPolicy::Pronounceable { length, digits } => {
+            let vowels: Vec<char> = VOWELS.chars().collect();
+            let consonants: Vec<char> = CONSONANTS.chars().collect();
+
+            let mut password: String = (0..*length)
+                .map(|i| if i % 2 == 0 { pick(&mut rng, &consonants) } else { pick(&mut rng, &vowels) })
+                .collect();
+
+            password = capitalize(&password);
+            password.push_str(&random_digits(&mut rng, *digits));
+            password
+        },
+        PasswordPolicy::Passphrase { words, separator, digits } => {
+            let mut parts: Vec<String> = words::WORDS
+                .choose_multiple(&mut rng, *words)
+                .map(|word| capitalize(word))
+                .collect();
+
+            if *digits > 0 {
+                parts.push(random_digits(&mut rng, *digits));
+            }
+
+            parts.join(separator)
+        }
+    }
+}
+
+///
+/// Character classes enabled by a random policy
+///
+fn classes(policy: &PasswordPolicy) -> Vec<&'static str> {
+    match policy {
+        PasswordPolicy::Random { lowercase, uppercase, digits, symbols, .. } => {
+            [(*lowercase, LOWERCASE), (*uppercase, UPPERCASE), (*digits, DIGITS), (*symbols, SYMBOLS)]
+                .into_iter()
+                .filter(|(enabled, _)| *enabled)
+                .map(|(_, class)| class)
+                .collect()
+        },
+        _ => Vec::new()
+    }
+}
+
+///
+/// Reasons a policy cannot produce usable passwords
+///
+fn check_policy(policy: &PasswordPolicy) -> std::result::Result<(), String> {
+    match policy {
+        PasswordPolicy::Random { length, .. } => {
+            let classes = classes(policy).len();
+
+            if classes == 0 {
+                return Err("no character class enabled".to_string());
+            }
+            if *length < classes {
+                return Err(format!("length {} is shorter than the {} enabled classes", length, classes));
+            }
+        },
+        PasswordPolicy::Pronounceable { length, .. } if *length == 0 => {
+            return Err("length must be at least 1".to_string());
+        },
+        PasswordPolicy::Passphrase { words, .. } if *words == 0 || *words > words::WORDS.len() => {
+            return Err(format!("words must be between 1 and {}", words::WORDS.len()));
+        },
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn pick<R: Rng>(rng: &mut R, chars: &[char]) -> char {
+    chars[rng.gen_range(0..chars.len())]
+}
+
+fn random_digits<R: Rng>(rng: &mut R, count: usize) -> String {
+    let digits: Vec<char> = UNAMBIGUOUS_DIGITS.chars().collect();
+    (0..count).map(|_| pick(rng, &digits)).collect()
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLES: usize = 200;
+
+    fn random(length: usize, symbols: bool, exclude_ambiguous: bool) -> PasswordPolicy {
+        PasswordPolicy::Random { length, lowercase: true, uppercase: true, digits: true, symbols, exclude_ambiguous }
+    }
+
+    fn passphrase(words: usize, digits: usize) -> PasswordPolicy {
+        PasswordPolicy::Passphrase { words, separator: "-".to_string(), digits }
+    }
+
+    fn has_ambiguous(password: &str) -> bool {
+        password.chars().any(|c| AMBIGUOUS.contains(c))
+    }
+
+    fn settings(policies: &[(&str, PasswordPolicy)], groups: &[(&str, &str)], default: Option<&str>) -> PasswordSettings {
+        PasswordSettings {
+            default_policy: default.map(str::to_string),
+            policies: policies.iter().map(|(name, policy)| (name.to_string(), policy.clone())).collect(),
+            groups: groups.iter().map(|(group, name)| (group.to_string(), name.to_string())).collect()
+        }
+    }
+
+    #[test]
+    fn random_passwords_have_every_class() {
+        for _ in 0..SAMPLES {
+            let password = generate(&random(12, true, true));
+
+            assert_eq!(password.chars().count(), 12);
+            assert!(password.chars().any(|c| c.is_ascii_lowercase()), "{}", password);
+            assert!(password.chars().any(|c| c.is_ascii_uppercase()), "{}", password);
+            assert!(password.chars().any(|c| c.is_ascii_digit()), "{}", password);
+            assert!(password.chars().any(|c| SYMBOLS.contains(c)), "{}", password);
+            assert!(!has_ambiguous(&password), "{}", password);
+        }
+
+        // As short as the enabled classes
+        let password = generate(&random(4, true, true));
+        assert_eq!(password.chars().count(), 4);
+    }
+
+    #[test]
+    fn random_passwords_use_only_enabled_classes() {
+        let digits_only = PasswordPolicy::Random {
+            length: 30,
+            lowercase: false,
+            uppercase: false,
+            digits: true,
+            symbols: false,
+            exclude_ambiguous: false
+        };
+
+        let password = generate(&digits_only);
+        assert_eq!(password.len(), 30);
+        assert!(password.chars().all(|c| c.is_ascii_digit()), "{}", password);
+
+        // Without the exclusion, ambiguous characters show up sooner or later
+        let all: String = (0..SAMPLES).map(|_| generate(&random(12, false, false))).collect();
+        assert!(has_ambiguous(&all));
+        assert!(!all.chars().any(|c| SYMBOLS.contains(c)));
+    }
+
+    #[test]
+    fn pronounceable_passwords_alternate_letters() {
+        let policy = PasswordPolicy::Pronounceable { length: 8, digits: 2 };
+
+        for _ in 0..SAMPLES {
+            let password = generate(&policy);
+            let (letters, digits) = password.split_at(8);
+
+            assert_eq!(password.len(), 10);
+            assert!(letters.chars().next().unwrap().is_ascii_uppercase(), "{}", password);
+            for (i, c) in letters.to_lowercase().chars().enumerate() {
+                let class = if i % 2 == 0 { CONSONANTS } else { VOWELS };
+                assert!(class.contains(c), "{}", password);
+            }
+            assert!(digits.chars().all(|c| UNAMBIGUOUS_DIGITS.contains(c)), "{}", password);
+            assert!(!has_ambiguous(&password), "{}", password);
+        }
+    }
+
+    #[test]
+    fn passphrases_join_distinct_words() {
+        for _ in 0..SAMPLES {
+            let password = generate(&passphrase(3, 2));
+            let parts: Vec<&str> = password.split('-').collect();
+
+            assert_eq!(parts.len(), 4, "{}", password);
+            for word in &parts[..3] {
+                assert!(words::WORDS.contains(&word.to_lowercase().as_str()), "{}", password);
+                assert!(word.chars().next().unwrap().is_uppercase(), "{}", password);
+            }
+            assert_ne!(parts[0], parts[1]);
+            assert_ne!(parts[1], parts[2]);
+            assert_eq!(parts[3].len(), 2);
+            assert!(!has_ambiguous(&password), "{}", password);
+        }
+
+        // No trailing separator without digits
+        assert_eq!(generate(&passphrase(2, 0)).split('-').count(), 2);
+    }
+
+    #[test]
+    fn no_word_shows_an_ambiguous_character() {
+        for word in words::WORDS {
+            assert!(!has_ambiguous(word), "{}", word);
+            assert!(!has_ambiguous(&capitalize(word)), "{}", word);
+        }
+    }
+
+    #[test]
+    fn policies_follow_the_groups() {
+        let generator = PasswordGenerator::new(&settings(
+            &[("primaria", passphrase(2, 2)), ("docenti", random(16, true, true))],
+            &[("1A", "primaria")],
+            Some("docenti")
+        )).unwrap();
+
+        assert_eq!(generator.policy_for(&["Domain Users".to_string(), "1a".to_string()]), &passphrase(2, 2));
+        assert_eq!(generator.policy_for(&["Docenti".to_string()]), &random(16, true, true));
+        assert_eq!(generator.generate(&[]).len(), 16);
+
+        let built_in = PasswordGenerator::new(&PasswordSettings::default()).unwrap();
+        assert_eq!(built_in.policy_for(&[]), &PasswordPolicy::default());
+    }
+
+    #[test]
+    fn unusable_settings_are_refused() {
+        let no_class = PasswordPolicy::Random {
+            length: 12,
+            lowercase: false,
+            uppercase: false,
+            digits: false,
+            symbols: false,
+            exclude_ambiguous: true
+        };
+        let cases = [
+            settings(&[("p", no_class)], &[], None),
+            settings(&[("p", random(3, true, true))], &[], None),
+            settings(&[("p", PasswordPolicy::Pronounceable { length: 0, digits: 2 })], &[], None),
+            settings(&[("p", passphrase(0, 2))], &[], None),
+            settings(&[("p", passphrase(words::WORDS.len() + 1, 2))], &[], None),
+            settings(&[], &[], Some("missing")),
+            settings(&[("p", passphrase(2, 2))], &[("1A", "missing")], None)
+        ];
+
+        for case in cases {
+            assert!(matches!(PasswordGenerator::new(&case), Err(Error::Config(_))), "{:?}", case);
+        }
+    }
+
+    #[test]
+    fn compliant_passwords_satisfy_the_domain() {
+        let domain = DomainPasswordPolicy::default();
+        let generator = PasswordGenerator::new(&settings(
+            &[("corta", PasswordPolicy::Pronounceable { length: 2, digits: 0 })],
+            &[("1A", "corta")],
+            None
+        )).unwrap();
+
+        for _ in 0..SAMPLES {
+            let password = generator.generate_compliant(&[], &domain, "mario.rossi", &["Mario", "Rossi"]).unwrap();
+            assert!(domain.violations(&password, "mario.rossi", &["Mario", "Rossi"]).is_empty(), "{}", password);
+        }
+
+        // Two letters never reach the minimum length
+        assert_eq!(generator.generate_compliant(&["1A".to_string()], &domain, "mario.rossi", &[]), None);
+    }
+}
@@ -0,0 +1,110 @@
+use std::fmt;
+
+///
+/// Password settings enforced by the domain controller
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DomainPasswordPolicy {
+    /// Passwords must mix character categories and avoid the account name
+    pub complexity: bool,
+
+    pub min_length: usize,
+
+    /// Number of previous passwords that cannot be reused
+    pub history_length: usize
+}
+
+impl Default for DomainPasswordPolicy {
+    ///
+    /// Defaults of a freshly provisioned Samba domain
+    ///
+    fn default() -> DomainPasswordPolicy {
+        DomainPasswordPolicy { complexity: true, min_length: 7, history_length: 24 }
+    }
+}
+
+///
+/// Reason a password would be refused by the domain controller
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// Shorter than the minimum length
+    TooShort(usize),
+
+    /// Fewer than three character categories
+    NotComplex,
+
+    /// Contains the account name or a part of the full name
+    ContainsName(String)
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::TooShort(min) => write!(f, "shorter than {} characters", min),
+            Violation::NotComplex => write!(f, "needs three of uppercase, lowercase, digits and symbols"),
+            Violation::ContainsName(name) => write!(f, "contains the name {:?}", name)
+        }
+    }
+}
+
+impl DomainPasswordPolicy {
+    ///
+    /// Check a password the way Active Directory does when it is set
+    ///
+    /// Password history cannot be checked in advance: the directory only
+    /// keeps hashes of the previous passwords.
+    ///
+    /// Arguments:
+    /// * `password`: password to check
+    /// * `login`: account name of the user
+    /// * `full_name`: first and last name of the user
+    ///
+    /// Returns:
+    /// * every rule the password breaks, empty if it is acceptable
+    ///
+    pub fn violations(&self, password: &str, login: &str, full_name: &[&str]) -> Vec<Violation> {
+        let mut violations = Vec::new();
+
+        if password.chars().count() < self.min_length {
+            violations.push(Violation::TooShort(self.min_length));
+        }
+
+        if !self.complexity {
+            return violations;
+        }
+
+        let categories = [
+            password.chars().any(char::is_uppercase),
+            password.chars().any(char::is_lowercase),
+            password.chars().any(|c| c.is_ascii_digit()),
+            password.chars().any(|c| !c.is_alphanumeric()),
+            // Letters without case, e.g. ideographs
+            password.chars().any(|c| c.is_alphabetic() && !c.is_uppercase() && !c.is_lowercase())
+        ];
+        if categories.iter().filter(|&&present| present).count() < 3 {
+            violations.push(Violation::NotComplex);
+        }
+
+        let lowercase = password.to_lowercase();
+
+        // The account name is checked whole, the full name token by token;
+        // pieces shorter than three characters are ignored
+        if login.chars().count() >= 3 && lowercase.contains(&login.to_lowercase()) {
+            violations.push(Violation::ContainsName(login.to_string()));
+        }
+
+        let tokens = full_name
+            .iter()
+            .flat_map(|name| name.split(|c: char| matches!(c, ',' | '.' | '-' | '_' | '#' | '\'') || c.is_whitespace()))
+            .filter(|token| token.chars().count() >= 3);
+
+        for token in tokens {
+            if lowercase.contains(&token.to_lowercase()) {
+                violations.push(Violation::ContainsName(token.to_string()));
+            }
+        }
+
+        violations
+    }
+}
@@ -0,0 +1,20 @@
+///
+/// Short, common Italian words without accents, used for passphrases.
+/// None contains `l` or `o`, nor starts with `i`: capitalized or not,
+/// they never show an ambiguous character
+///
+pub const WORDS: [&str; 125] = [
+    "acqua", "ananas", "ape", "arancia", "banana", "barca", "cane", "capra", "casa", "dente",
+    "duna", "erba", "fata", "fiume", "giraffa", "mappa", "pettine", "gamba", "pigna", "mare",
+    "mucca", "nave", "neve", "pane", "panda", "pappa", "papera", "pera", "pesce", "pizza",
+    "rana", "sedia", "seme", "serra", "spiga", "strada", "tazza", "tenda", "terra", "tigre",
+    "uva", "verde", "zebra", "zucca", "anatra", "chiave", "gabbia", "vasca", "matita", "pianeta",
+    "arpa", "barba", "biscia", "cassetta", "cesta", "cena", "chitarra", "ciabatta", "cuccia", "danza",
+    "dita", "festa", "fetta", "fiaba", "fiamma", "frutta", "gemma", "ghianda", "giacca", "gru",
+    "mamma", "maschera", "matassa", "medusa", "menta", "merenda", "musica", "nebbia", "nube", "pagina",
+    "panca", "panna", "pasta", "patata", "penna", "pesca", "piazza", "pietra", "pinza", "piuma",
+    "prugna", "radice", "renna", "rete", "ricetta", "rima", "rupe", "sabbia", "sacca", "sarta",
+    "scarpa", "scimmia", "sciarpa", "sete", "sirena", "spada", "spezie", "squadra", "stampa", "stanza",
+    "strega", "tasca", "tastiera", "tuta", "unghia", "vacanza", "vernice", "vespa", "vigna", "zampa",
+    "zanzara", "zappa", "zattera", "zecca", "zuppa"
+];
@@ -0,0 +1,613 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::backend::DirectoryBackend;
+use crate::batch::journal::Step;
+use crate::commands::parse::{contains_name, names_equal};
+use crate::commands::user::DomainUser;
+use crate::commands::validate;
+use crate::db::rget_login::MySQLDomainUser;
+use crate::error::Result;
+use crate::password::PasswordGenerator;
+
+///
+/// What to do with domain accounts that belong to a managed group but are
+/// no longer listed in the school database
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OrphanPolicy {
+    /// List them in the plan, change nothing
+    #[default]
+    Report,
+
+    /// Remove them from the managed groups, keeping the account
+    Detach,
+
+    /// Delete the account. Any member of a managed group is a candidate,
+    /// staff accounts placed in a shared group included: review the
+    /// orphans listed by a `Report` run first
+    Delete
+}
+
+///
+/// Options of a synchronisation
+///
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    pub orphans: OrphanPolicy,
+
+    /// Builds the passwords of new students with a blank one in the database
+    pub passwords: PasswordGenerator
+}
+
+///
+/// Student of the database that cannot be mirrored
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncProblem {
+    pub login: String,
+    pub reason: String
+}
+
+impl fmt::Display for SyncProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.login.is_empty() {
+            write!(f, "{}", self.reason)
+        } else {
+            write!(f, "{}: {}", self.login, self.reason)
+        }
+    }
+}
+
+///
+/// Difference between the school database and the domain
+///
+/// The steps are laid out like the ones of a batch import, so that they run
+/// with `batch::run_import` and its journal: account after account, each
+/// one preceded by the creation of the groups it is the first to join.
+///
+#[derive(Debug, Clone, Default)]
+pub struct SyncPlan {
+    /// Changes to make, in order
+    pub steps: Vec<Step>,
+
+    /// Accounts of managed groups missing from the database
+    pub orphans: Vec<String>,
+
+    /// Students left out of the plan
+    pub problems: Vec<SyncProblem>,
+
+    /// Students already matching the domain
+    pub unchanged: usize
+}
+
+impl SyncPlan {
+    ///
+    /// Returns:
+    /// * `true` if the domain already matches the database
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+///
+/// Compare the students of the school database with the domain
+///
+/// The groups of the students (classes and `gruppo` values) are the managed
+/// groups: only their memberships are compared, and their members missing
+/// from the database are the orphans. Empty database names never overwrite
+/// the domain ones.
+///
+/// Arguments:
+/// * `backend`: directory backend, only queried
+/// * `students`: rows of the ALUNNO table
+/// * `options`: orphan policy and password generator
+///
+/// Returns:
+/// * the `SyncPlan`, or the error of the first failed query
+///
+pub fn compute(backend: &dyn DirectoryBackend, students: &[MySQLDomainUser], options: &SyncOptions) -> Result<SyncPlan> {
+    let mut plan = SyncPlan::default();
+    let desired = desired_users(students, &mut plan.problems);
+    let managed = groups_of(&desired);
+
+    let mut groups = known_groups(backend)?;
+
+    // Current members of the managed groups: lowercase user -> groups
+    let mut memberships: HashMap<String, Vec<String>> = HashMap::new();
+    let mut members: Vec<String> = Vec::new();
+
+    for group in managed.iter().filter(|group| groups.contains(&group.to_lowercase())) {
+        for member in backend.list_members(group)? {
+            let groups = memberships.entry(member.to_lowercase()).or_default();
+            if groups.is_empty() {
+                members.push(member);
+            }
+            groups.push(group.clone());
+        }
+    }
+
+    plan_users(backend, desired, options, &mut groups, &mut plan, |username| {
+        Ok(memberships.get(&username.to_lowercase()).cloned().unwrap_or_default())
+    })?;
+
+    // Members of managed groups that the database does not know
+    let listed: HashSet<String> = students.iter().map(|student| student.login.trim().to_lowercase()).collect();
+
+    for member in members.into_iter().filter(|member| !listed.contains(&member.to_lowercase())) {
+        match options.orphans {
+            OrphanPolicy::Report => {},
+            OrphanPolicy::Detach => {
+                for group in &memberships[&member.to_lowercase()] {
+                    plan.steps.push(Step::RemoveMember { group: group.clone(), username: member.clone() });
+                }
+            },
+            OrphanPolicy::Delete => plan.steps.push(Step::DeleteUser { username: member.clone() })
+        }
+        plan.orphans.push(member);
+    }
+
+    Ok(plan)
+}
+
+///
+/// Compare only the students changed since a previous run with the domain
+///
+/// Unlike `compute`, the members of the managed groups are not listed: the
+/// memberships of each changed student are read on their own, so the cost
+/// follows the number of changes, not the size of the school. Students
+/// removed from the database leave no row behind, so orphans are never
+/// found: a full `compute` is still needed from time to time.
+///
+/// Arguments:
+/// * `backend`: directory backend, only queried
+/// * `changed`: rows of the ALUNNO table modified since the previous run
+/// * `managed_groups`: groups of all the students, see `get_student_groups`
+/// * `options`: password generator, the orphan policy is not used
+///
+/// Returns:
+/// * the `SyncPlan`, or the error of the first failed query
+///
+pub fn compute_changes(
+    backend: &dyn DirectoryBackend,
+    changed: &[MySQLDomainUser],
+    managed_groups: &[String],
+    options: &SyncOptions
+) -> Result<SyncPlan> {
+    let mut plan = SyncPlan::default();
+    let desired = desired_users(changed, &mut plan.problems);
+
+    let mut managed = groups_of(&desired);
+    let mut groups = known_groups(backend)?;
+
+    for group in managed_groups {
+        if !contains_name(&managed, group) {
+            managed.push(group.clone());
+        }
+    }
+
+    plan_users(backend, desired, options, &mut groups, &mut plan, |username| {
+        Ok(backend
+            .user_groups(username)?
+            .into_iter()
+            .filter(|group| contains_name(&managed, group))
+            .collect())
+    })?;
+
+    Ok(plan)
+}
+
+///
+/// Accounts described by the database rows, without duplicates and
+/// without the rows that cannot become an account
+///
+fn desired_users(students: &[MySQLDomainUser], problems: &mut Vec<SyncProblem>) -> Vec<DomainUser> {
+    let mut users: Vec<DomainUser> = Vec::new();
+
+    for student in students {
+        let login = student.login.trim();
+
+        let problem = if login.is_empty() {
+            Some(format!("{} {} has no login", student.first_name, student.last_name))
+        } else if let Err(err) = validate::account_name(login) {
+            Some(err.to_string())
+        } else if users.iter().any(|user| names_equal(&user.common_name, login)) {
+            Some("listed more than once".to_string())
+        } else {
+            student.groups().iter().find_map(|group| validate::group_name(group).err()).map(|err| err.to_string())
+        };
+
+        if let Some(reason) = problem {
+            problems.push(SyncProblem { login: login.to_string(), reason });
+            continue;
+        }
+
+        users.push(DomainUser {
+            common_name: login.to_string(),
+            last_name: student.last_name.trim().to_string(),
+            first_name: student.first_name.trim().to_string(),
+            groups: student.groups(),
+            password: student.password.trim().to_string()
+        });
+    }
+
+    users
+}
+
+///
+/// Groups of a set of users, in order of first appearance
+///
+fn groups_of(users: &[DomainUser]) -> Vec<String> {
+    let mut groups: Vec<String> = Vec::new();
+    for user in users {
+        for group in &user.groups {
+            if !contains_name(&groups, group) {
+                groups.push(group.clone());
+            }
+        }
+    }
+
+    groups
+}
+
+///
+/// Lowercase names of the groups in the domain
+///
+fn known_groups(backend: &dyn DirectoryBackend) -> Result<HashSet<String>> {
+    Ok(backend.list_groups()?.iter().map(|group| group.to_lowercase()).collect())
+}
+
+///
+/// Add the creation of the groups that are neither in the domain nor
+/// planned yet: the groups must exist before an account joins them
+///
+fn plan_groups<'a>(wanted: impl IntoIterator<Item = &'a String>, groups: &mut HashSet<String>, plan: &mut SyncPlan) {
+    for group in wanted {
+        if groups.insert(group.to_lowercase()) {
+            plan.steps.push(Step::CreateGroup { group: group.clone() });
+        }
+    }
+}
+
+///
+/// Add the changes of every account to the plan
+///
+/// Arguments:
+/// * `backend`: directory backend, only queried
+/// * `desired`: accounts described by the database
+/// * `options`: password generator
+/// * `groups`: lowercase names of the groups in the domain or planned
+/// * `plan`: plan receiving the steps, problems and unchanged count
+/// * `current_groups`: managed groups an existing account is member of
+///
+fn plan_users<F>(
+    backend: &dyn DirectoryBackend,
+    desired: Vec<DomainUser>,
+    options: &SyncOptions,
+    groups: &mut HashSet<String>,
+    plan: &mut SyncPlan,
+    mut current_groups: F
+) -> Result<()>
+where
+    F: FnMut(&str) -> Result<Vec<String>>
+{
+    let domain_users = backend.list_users()?;
+    let policy = backend.password_policy().ok();
+
+    for mut user in desired {
+        if !contains_name(&domain_users, &user.common_name) {
+            let generated_password = user.password.is_empty();
+            if generated_password {
+                let full_name = [user.first_name.as_str(), user.last_name.as_str()];
+                user.password = match &policy {
+                    Some(policy) => match options.passwords.generate_compliant(&user.groups, policy, &user.common_name, &full_name) {
+                        Some(password) => password,
+                        None => {
+                            plan.problems.push(SyncProblem {
+                                login: user.common_name.clone(),
+                                reason: "cannot generate a password accepted by the domain policy".to_string()
+                            });
+                            continue;
+                        }
+                    },
+                    None => options.passwords.generate(&user.groups)
+                };
+            } else if let Some(policy) = &policy {
+                let full_name = [user.first_name.as_str(), user.last_name.as_str()];
+                let violations = policy.violations(&user.password, &user.common_name, &full_name);
+
+                if !violations.is_empty() {
+                    let reasons: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+                    plan.problems.push(SyncProblem {
+                        login: user.common_name.clone(),
+                        reason: format!("password refused by the domain policy: {}", reasons.join(", "))
+                    });
+                    continue;
+                }
+            }
+
+            plan_groups(&user.groups, groups, plan);
+            let memberships: Vec<Step> = user.groups
+                .iter()
+                .map(|group| Step::AddMember { group: group.clone(), username: user.common_name.clone() })
+                .collect();
+
+            plan.steps.push(Step::CreateUser { user, generated_password });
+            plan.steps.extend(memberships);
+            continue;
+        }
+
+        let before = plan.steps.len();
+        let record = backend.show_user(&user.common_name)?;
+        let surname = record.surname.unwrap_or_default();
+        let given_name = record.given_name.unwrap_or_default();
+
+        let last_name = if user.last_name.is_empty() { &surname } else { &user.last_name };
+        let first_name = if user.first_name.is_empty() { &given_name } else { &user.first_name };
+
+        let current = current_groups(&user.common_name)?;
+        let joined: Vec<&String> = user.groups.iter().filter(|group| !contains_name(&current, group)).collect();
+        plan_groups(joined.iter().copied(), groups, plan);
+
+        if *last_name != surname || *first_name != given_name {
+            plan.steps.push(Step::SetNames {
+                username: user.common_name.clone(),
+                last_name: last_name.clone(),
+                first_name: first_name.clone(),
+                previous_last_name: surname.clone(),
+                previous_first_name: given_name.clone()
+            });
+        }
+
+        for group in joined {
+            plan.steps.push(Step::AddMember { group: group.clone(), username: user.common_name.clone() });
+        }
+        for group in &current {
+            if !contains_name(&user.groups, group) {
+                plan.steps.push(Step::RemoveMember { group: group.clone(), username: user.common_name.clone() });
+            }
+        }
+
+        if plan.steps.len() == before {
+            plan.unchanged += 1;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::fake::FakeDirectory;
+    use crate::batch::{self, BatchSummary, RowOutcome};
+    use crate::batch::journal::{Journal, StepState};
+    use crate::batch::rollback::{self, RollbackOptions};
+    use super::*;
+
+    fn student(login: &str, last_name: &str, first_name: &str, class: &str, group: &str) -> MySQLDomainUser {
+        MySQLDomainUser {
+            login: login.to_string(),
+            last_name: last_name.to_string(),
+            first_name: first_name.to_string(),
+            class: class.to_string(),
+            password: "Secret#123".to_string(),
+            tax_code: String::new(),
+            group: group.to_string(),
+            birth_date: String::new(),
+            date_modified: None
+        }
+    }
+
+    fn steps(plan: &SyncPlan) -> Vec<String> {
+        plan.steps.iter().map(ToString::to_string).collect()
+    }
+
+    fn plan(backend: &FakeDirectory, students: &[MySQLDomainUser], orphans: OrphanPolicy) -> SyncPlan {
+        compute(backend, students, &SyncOptions { orphans, ..SyncOptions::default() }).unwrap()
+    }
+
+    /// 3B with two students, a teacher in a group the database does not know
+    fn school() -> FakeDirectory {
+        FakeDirectory::new()
+            .with_user("mario.rossi", "Rossi", "Mario", &["3B", "coro"])
+            .with_user("anna.bianchi", "Bianchi", "Anna", &["3B"])
+            .with_user("paolo.neri", "Neri", "Paolo", &["Docenti"])
+    }
+
+    #[test]
+    fn new_students_are_created_with_their_groups() {
+        let backend = school();
+        let mut blank = student("luca.verdi", "Verdi", "Luca", "4C", "coro");
+        blank.password.clear();
+        let students = [
+            student("mario.rossi", "Rossi", "Mario", "3B", "coro"),
+            student("anna.bianchi", "Bianchi", "Anna", "3B", ""),
+            student("giulia.russo", "Russo", "Giulia", "3B", "scacchi"),
+            blank
+        ];
+
+        let plan = plan(&backend, &students, OrphanPolicy::Report);
+
+        assert_eq!(steps(&plan), [
+            "create group scacchi",
+            "create user giulia.russo",
+            "add giulia.russo to group 3B",
+            "add giulia.russo to group scacchi",
+            "create group 4C",
+            "create user luca.verdi",
+            "add luca.verdi to group 4C",
+            "add luca.verdi to group coro"
+        ]);
+        assert_eq!(plan.unchanged, 2);
+        assert!(plan.orphans.is_empty() && plan.problems.is_empty());
+
+        let passwords: Vec<(&str, bool)> = plan.steps
+            .iter()
+            .filter_map(|step| match step {
+                Step::CreateUser { user, generated_password } => Some((user.password.as_str(), *generated_password)),
+                _ => None
+            })
+            .collect();
+        assert_eq!(passwords[0], ("Secret#123", false));
+        assert!(passwords[1].1 && !passwords[1].0.is_empty());
+    }
+
+    #[test]
+    fn renamed_students_keep_the_names_the_database_lacks() {
+        let backend = school();
+        let students = [
+            student("mario.rossi", "Rossi Verdi", "", "3B", "coro"),
+            student("anna.bianchi", "Bianchi", "Anna", "3B", "")
+        ];
+
+        let plan = plan(&backend, &students, OrphanPolicy::Report);
+
+        assert_eq!(steps(&plan), ["rename mario.rossi: 'Mario Rossi' -> 'Mario Rossi Verdi'"]);
+        assert_eq!(plan.unchanged, 1);
+    }
+
+    #[test]
+    fn class_changes_move_the_memberships() {
+        let backend = school();
+        let students = [
+            student("mario.rossi", "Rossi", "Mario", "4B", ""),
+            student("anna.bianchi", "Bianchi", "Anna", "3B", "coro")
+        ];
+
+        let plan = plan(&backend, &students, OrphanPolicy::Report);
+
+        // coro is still managed through anna.bianchi, Docenti never is
+        assert_eq!(steps(&plan), [
+            "create group 4B",
+            "add mario.rossi to group 4B",
+            "remove mario.rossi from group 3B",
+            "remove mario.rossi from group coro",
+            "add anna.bianchi to group coro"
+        ]);
+        assert_eq!(plan.unchanged, 0);
+        assert!(plan.orphans.is_empty());
+    }
+
+    #[test]
+    fn orphans_are_reported_detached_or_deleted() {
+        let backend = school().with_user("luca.verdi", "Verdi", "Luca", &["coro", "3B"]);
+        let students = [student("mario.rossi", "Rossi", "Mario", "3B", "coro")];
+
+        let report = plan(&backend, &students, OrphanPolicy::Report);
+        assert_eq!(report.orphans, ["anna.bianchi", "luca.verdi"]);
+        assert!(report.is_empty());
+
+        let detach = plan(&backend, &students, OrphanPolicy::Detach);
+        assert_eq!(detach.orphans, report.orphans);
+        assert_eq!(steps(&detach), [
+            "remove anna.bianchi from group 3B",
+            "remove luca.verdi from group 3B",
+            "remove luca.verdi from group coro"
+        ]);
+
+        let delete = plan(&backend, &students, OrphanPolicy::Delete);
+        assert_eq!(steps(&delete), ["delete user anna.bianchi", "delete user luca.verdi"]);
+    }
+
+    #[test]
+    fn students_that_cannot_be_mirrored_are_left_out() {
+        let backend = school();
+        let mut weak = student("luca.verdi", "Verdi", "Luca", "3B", "");
+        weak.password = "luca".to_string();
+        let students = [
+            student("mario.rossi", "Rossi", "Mario", "3B", "coro"),
+            student("Mario.Rossi", "Rossi", "Mario", "3B", ""),
+            student("", "Bianchi", "Anna", "3B", ""),
+            student("giulia|russo", "Russo", "Giulia", "3B", ""),
+            student("sara.conti", "Conti", "Sara", "3B", "a/b"),
+            weak
+        ];
+
+        let plan = plan(&backend, &students, OrphanPolicy::Report);
+
+        let problems: Vec<&str> = plan.problems.iter().map(|problem| problem.login.as_str()).collect();
+        assert_eq!(problems, ["Mario.Rossi", "", "giulia|russo", "sara.conti", "luca.verdi"]);
+        assert!(plan.problems[1].reason.contains("has no login"));
+        assert!(plan.problems[4].reason.contains("password refused"));
+        assert!(plan.is_empty());
+
+        // Students left out are no orphans, but the missing ones are
+        assert_eq!(plan.orphans, ["anna.bianchi"]);
+    }
+
+    #[test]
+    fn changes_only_read_the_changed_students() {
+        let backend = school().with_user("luca.verdi", "Verdi", "Luca", &["4C"]);
+        let managed = ["3B".to_string(), "4C".to_string(), "coro".to_string()];
+        let changed = [student("mario.rossi", "Rossi", "Mario", "4C", "")];
+
+        let plan = compute_changes(&backend, &changed, &managed, &SyncOptions::default()).unwrap();
+
+        assert_eq!(steps(&plan), [
+            "add mario.rossi to group 4C",
+            "remove mario.rossi from group 3B",
+            "remove mario.rossi from group coro"
+        ]);
+        assert!(plan.orphans.is_empty());
+    }
+
+    #[test]
+    fn applied_plans_are_journaled_and_leave_nothing_to_do() {
+        let backend = school();
+        let students = [
+            student("mario.rossi", "Rossi", "Mario", "4B", ""),
+            student("anna.bianchi", "Bianchi", "Anna Maria", "3B", "coro"),
+            student("luca.verdi", "Verdi", "Luca", "4B", "")
+        ];
+
+        let plan = plan(&backend, &students, OrphanPolicy::Detach);
+        let journal = Journal::in_memory(plan.steps);
+        let summary = batch::run_import(&backend, &journal, 2, |_, _| {}).unwrap();
+
+        assert_eq!(summary, BatchSummary { created: 3, previously_created: 0, failed: 0 });
+        assert!(journal.is_finished());
+        assert_eq!(backend.user_groups("mario.rossi").unwrap(), ["Domain Users", "4B"]);
+        assert_eq!(backend.user_groups("luca.verdi").unwrap(), ["Domain Users", "4B"]);
+        assert!(self::plan(&backend, &students, OrphanPolicy::Detach).is_empty());
+
+        // The journal undoes the run
+        let undo = rollback::plan_rollback(&backend, &journal, RollbackOptions { restore_groups: true }).unwrap();
+        let undo = Journal::in_memory(undo.steps);
+        rollback::run_rollback(&backend, &undo, |_| {}).unwrap();
+
+        assert!(undo.is_finished());
+        assert!(!backend.user_exists("luca.verdi").unwrap());
+        assert_eq!(backend.user_groups("mario.rossi").unwrap(), ["Domain Users", "3B", "coro"]);
+        assert_eq!(backend.show_user("anna.bianchi").unwrap().given_name.as_deref(), Some("Anna"));
+    }
+
+    #[test]
+    fn refused_account_stays_pending_for_resume() {
+        let backend = school().refuse("luca.verdi");
+        let students = [
+            student("mario.rossi", "Rossi", "Mario", "3B", ""),
+            student("anna.bianchi", "Bianchi", "Anna", "3B", "coro"),
+            student("luca.verdi", "Verdi", "Luca", "3B", "coro")
+        ];
+
+        let plan = plan(&backend, &students, OrphanPolicy::Report);
+        let journal = Journal::in_memory(plan.steps);
+        let mut failed = Vec::new();
+        let summary = batch::run_import(&backend, &journal, 1, |report, _| {
+            if let RowOutcome::Failed(_) = report.outcome {
+                failed.push(report.username.clone());
+            }
+        }).unwrap();
+
+        assert_eq!(summary, BatchSummary { created: 2, previously_created: 0, failed: 1 });
+        assert_eq!(failed, ["luca.verdi"]);
+
+        // Nothing of the refused account is left half made
+        let pending: Vec<String> = (0..journal.steps().len())
+            .filter(|index| journal.state(*index) == StepState::Pending)
+            .map(|index| journal.steps()[index].to_string())
+            .collect();
+        assert_eq!(pending, ["create user luca.verdi", "add luca.verdi to group 3B", "add luca.verdi to group coro"]);
+        assert!(!backend.user_exists("luca.verdi").unwrap());
+    }
+}
@@ -0,0 +1,936 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use csv::{ReaderBuilder, StringRecord};
+use encoding_rs::Encoding;
+use crate::commands::user::DomainUser;
+use crate::commands::validate;
+use crate::config::tools_config::ImportProfile;
+use crate::error::{Error, Result};
+use crate::login::{self, LoginGenerator, MAX_LOGIN_LENGTH};
+use crate::password::PasswordGenerator;
+use crate::password::policy::DomainPasswordPolicy;
+use super::schema::Field;
+
+///
+/// Reasons a row of a login file is rejected
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// A required field is empty or missing
+    MissingField,
+
+    /// The login contains characters not allowed in an account name
+    BadLoginCharacters(String),
+
+    /// The login is longer than `MAX_LOGIN_LENGTH`
+    LoginTooLong(usize),
+
+    /// The login is made of allowed characters, but the domain would
+    /// still refuse it (e.g. it starts with `-` or ends with `.`)
+    InvalidLogin(String),
+
+    /// The password is empty
+    EmptyPassword,
+
+    /// The login already appeared on the given line
+    DuplicateLogin(u64),
+
+    /// The row could not be parsed at all
+    Unreadable(String),
+
+    /// The domain controller would refuse the password
+    WeakPassword(String)
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::MissingField => write!(f, "missing value"),
+            Problem::BadLoginCharacters(chars) => write!(f, "characters not allowed in a login: {:?}", chars),
+            Problem::LoginTooLong(len) => write!(f, "login is {} characters long, at most {} allowed", len, MAX_LOGIN_LENGTH),
+            Problem::InvalidLogin(reason) => write!(f, "{}", reason),
+            Problem::EmptyPassword => write!(f, "empty password"),
+            Problem::DuplicateLogin(line) => write!(f, "duplicate login, first seen on line {}", line),
+            Problem::Unreadable(reason) => write!(f, "unreadable row: {}", reason),
+            Problem::WeakPassword(reason) => write!(f, "password refused by the domain policy: {}", reason)
+        }
+    }
+}
+
+///
+/// Single problem found on a row
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowError {
+    /// Line of the file, starting from 1 (the header)
+    pub line: u64,
+
+    /// Column the problem refers to, `None` for the whole row
+    pub column: Option<Field>,
+
+    pub problem: Problem
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.column {
+            Some(column) => write!(f, "line {}, column {}: {}", self.line, column.names()[0], self.problem),
+            None => write!(f, "line {}: {}", self.line, self.problem)
+        }
+    }
+}
+
+///
+/// Row of a login file, either parsed or rejected
+///
+pub struct ImportRow {
+    pub line: u64,
+    pub result: std::result::Result<DomainUser, Vec<RowError>>,
+
+    /// The password has been generated, the input had none
+    pub generated_password: bool
+}
+
+///
+/// Outcome of reading a whole login file
+///
+#[derive(Default)]
+pub struct ImportReport {
+    pub rows: Vec<ImportRow>
+}
+
+impl ImportReport {
+    ///
+    /// Users parsed from the valid rows, in file order
+    ///
+    pub fn valid_users(&self) -> Vec<DomainUser> {
+        self.rows
+            .iter()
+            .filter_map(|row| row.result.as_ref().ok().cloned())
+            .collect()
+    }
+
+    ///
+    /// Every error found in the file, in file order
+    ///
+    pub fn errors(&self) -> impl Iterator<Item = &RowError> {
+        self.rows
+            .iter()
+            .filter_map(|row| row.result.as_ref().err())
+            .flatten()
+    }
+
+    ///
+    /// Returns:
+    /// * `true` if at least one row has been rejected
+    ///
+    pub fn has_errors(&self) -> bool {
+        self.rows.iter().any(|row| row.result.is_err())
+    }
+
+    ///
+    /// Returns:
+    /// * the number of rejected rows
+    ///
+    pub fn invalid_count(&self) -> usize {
+        self.rows.iter().filter(|row| row.result.is_err()).count()
+    }
+
+    ///
+    /// Check the passwords of the valid rows against the domain policy,
+    /// before anything is sent to the domain controller
+    ///
+    /// Offending passwords are replaced by compliant generated ones when a
+    /// generator is given, and the row is marked as having a generated
+    /// password. Otherwise the row is rejected. Empty passwords are left
+    /// alone: they are never sent.
+    ///
+    /// Arguments:
+    /// * `policy`: password policy of the domain
+    /// * `regenerate`: generator for the replacements, `None` to reject the rows
+    ///
+    /// Returns:
+    /// * the passwords that have been replaced
+    ///
+    pub fn enforce_password_policy(
+        &mut self,
+        policy: &DomainPasswordPolicy,
+        regenerate: Option<&PasswordGenerator>
+    ) -> Vec<ReplacedPassword> {
+        let mut replaced = Vec::new();
+
+        for row in &mut self.rows {
+            let Ok(user) = &mut row.result else {
+                continue;
+            };
+            if user.password.is_empty() {
+                continue;
+            }
+
+            let full_name = [user.first_name.as_str(), user.last_name.as_str()];
+            let violations = policy.violations(&user.password, &user.common_name, &full_name);
+            if violations.is_empty() {
+                continue;
+            }
+
+            let reason = violations.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ");
+            let compliant = regenerate
+                .and_then(|generator| generator.generate_compliant(&user.groups, policy, &user.common_name, &full_name));
+
+            match compliant {
+                Some(password) => {
+                    user.password = password;
+                    row.generated_password = true;
+                    replaced.push(ReplacedPassword { line: row.line, login: user.common_name.clone(), reason });
+                },
+                None => {
+                    row.result = Err(vec![RowError {
+                        line: row.line,
+                        column: Some(Field::Password),
+                        problem: Problem::WeakPassword(reason)
+                    }]);
+                }
+            }
+        }
+
+        replaced
+    }
+}
+
+///
+/// Password of the input replaced because the domain would refuse it
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplacedPassword {
+    pub line: u64,
+    pub login: String,
+
+    /// Rules the original password broke
+    pub reason: String
+}
+
+///
+/// Table of cells read from an input file, before any validation
+///
+pub struct Table {
+    pub header: Vec<String>,
+    pub rows: Vec<TableRow>
+}
+
+///
+/// Row of a `Table`: its cells, or the reason it could not be read
+///
+pub struct TableRow {
+    /// Line (or spreadsheet row) of the file, starting from 1 (the header)
+    pub line: u64,
+    pub cells: std::result::Result<Vec<String>, String>
+}
+
+impl TableRow {
+    ///
+    /// Trimmed content of a cell, empty when the row is too short
+    ///
+    pub fn cell(&self, index: Option<usize>) -> &str {
+        match (&self.cells, index) {
+            (Ok(cells), Some(index)) => cells.get(index).map(|cell| cell.trim()).unwrap_or_default(),
+            _ => ""
+        }
+    }
+}
+
+impl Table {
+    ///
+    /// Index of the first column whose header matches one of `names`,
+    /// ignoring case and surrounding spaces
+    ///
+    pub fn column(&self, names: &[&str]) -> Option<usize> {
+        self.header
+            .iter()
+            .position(|header| names.iter().any(|name| header.trim().eq_ignore_ascii_case(name.trim())))
+    }
+}
+
+///
+/// Values of a user taken from a row, before validation
+///
+pub struct Candidate {
+    /// Empty when the input has no login for the user
+    pub login: String,
+    pub last_name: String,
+    pub first_name: String,
+    pub groups: Vec<String>,
+
+    /// `None` when the input has no password at all (nothing to check)
+    pub password: Option<String>
+}
+
+///
+/// How the values missing from the input are filled in
+///
+#[derive(Default)]
+pub struct ImportOptions {
+    /// Builds the missing logins, `None` to report them as missing
+    pub logins: Option<LoginGenerator>,
+
+    /// Builds the missing passwords, `None` to report them as missing
+    pub passwords: Option<PasswordGenerator>
+}
+
+///
+/// Validate the candidates read from the rows of a file
+///
+/// Logins given in the input are reserved before any login is generated,
+/// so a generated login never clashes with one found further down.
+///
+/// Arguments:
+/// * `rows`: line and candidate of every row, or why the row is unreadable
+/// * `options`: how missing logins and passwords are filled in
+///
+/// Returns:
+/// * the `ImportReport` with a result for every row
+///
+pub fn validate(rows: Vec<(u64, std::result::Result<Candidate, String>)>, options: &mut ImportOptions) -> ImportReport {
+    if let Some(logins) = &mut options.logins {
+        logins.reserve(
+            rows.iter()
+                .filter_map(|(_, row)| row.as_ref().ok())
+                .map(|candidate| candidate.login.as_str())
+                .filter(|login| !login.is_empty())
+        );
+    }
+
+    let mut seen = HashMap::new();
+    let mut report = ImportReport::default();
+
+    for (line, row) in rows {
+        let row = match row {
+            Ok(mut candidate) => {
+                let generated_password = fill_in(&mut candidate, options);
+                ImportRow { line, result: check(line, candidate, &mut seen), generated_password }
+            },
+            Err(reason) => unreadable(line, &reason)
+        };
+
+        report.rows.push(row);
+    }
+
+    report
+}
+
+///
+/// Generate the login and the password missing from a candidate
+///
+/// Returns:
+/// * `true` if a password has been generated
+///
+fn fill_in(candidate: &mut Candidate, options: &mut ImportOptions) -> bool {
+    if candidate.login.is_empty()
+        && let Some(logins) = &mut options.logins {
+        candidate.login = logins
+            .generate(&candidate.first_name, &candidate.last_name)
+            .unwrap_or_default();
+    }
+
+    if candidate.password.as_deref().is_none_or(str::is_empty)
+        && let Some(passwords) = &options.passwords {
+        candidate.password = Some(passwords.generate(&candidate.groups));
+        return true;
+    }
+
+    false
+}
+
+fn check(line: u64, candidate: Candidate, seen: &mut HashMap<String, u64>) -> std::result::Result<DomainUser, Vec<RowError>> {
+
+    let mut errors = Vec::new();
+    let mut error = |column: Field, problem: Problem| errors.push(RowError { line, column: Some(column), problem });
+
+    let login = candidate.login.as_str();
+    if login.is_empty() {
+        error(Field::Login, Problem::MissingField);
+    } else {
+        let bad: String = login.chars().filter(|c| !login::is_login_char(*c)).collect();
+        let length = login.chars().count();
+        let checked = bad.is_empty() && length <= MAX_LOGIN_LENGTH;
+
+        if !bad.is_empty() {
+            error(Field::Login, Problem::BadLoginCharacters(bad));
+        }
+        if length > MAX_LOGIN_LENGTH {
+            error(Field::Login, Problem::LoginTooLong(length));
+        }
+
+        // The rest of the rules of the domain on account names
+        if checked && let Err(Error::InvalidInput(reason)) = validate::account_name(login) {
+            error(Field::Login, Problem::InvalidLogin(reason));
+        }
+
+        match seen.get(&login.to_lowercase()) {
+            Some(first) => error(Field::Login, Problem::DuplicateLogin(*first)),
+            None => { seen.insert(login.to_lowercase(), line); }
+        }
+    }
+
+    if candidate.last_name.is_empty() {
+        error(Field::LastName, Problem::MissingField);
+    }
+    if candidate.first_name.is_empty() {
+        error(Field::FirstName, Problem::MissingField);
+    }
+
+    if candidate.password.as_deref() == Some("") {
+        error(Field::Password, Problem::EmptyPassword);
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(DomainUser {
+        common_name: candidate.login,
+        last_name: candidate.last_name,
+        first_name: candidate.first_name,
+        groups: candidate.groups,
+        password: candidate.password.unwrap_or_default()
+    })
+}
+
+///
+/// Read and validate a login CSV file
+///
+/// Arguments:
+/// * `file_path`: complete path of the file
+/// * `profile`: layout of the file (delimiter, encoding, column names...)
+/// * `options`: how missing logins and passwords are filled in
+///
+/// Returns:
+/// * the `ImportReport` with a result for every row, `Error::Io` if the
+///   file cannot be opened, `Error::Config` if the profile is not usable,
+///   `Error::InvalidInput` if the file does not match the profile
+///
+pub fn read_login_file(file_path: &str, profile: &ImportProfile, options: &mut ImportOptions) -> Result<ImportReport> {
+    let text = read_text(file_path, &profile.encoding)?;
+    read_login_records(text.as_bytes(), profile, options)
+}
+
+///
+/// Read and validate login CSV text
+///
+/// Arguments:
+/// * `input`: source of the UTF-8 CSV text, header included
+/// * `profile`: layout of the text
+/// * `options`: how missing logins and passwords are filled in
+///
+/// Returns:
+/// * the `ImportReport` with a result for every row, `Error::InvalidInput`
+///   if a required column is missing from the header
+///
+pub fn read_login_records<R: Read>(input: R, profile: &ImportProfile, options: &mut ImportOptions) -> Result<ImportReport> {
+    if !profile.delimiter.is_ascii() {
+        return Err(Error::Config(format!("delimiter '{}' is not an ASCII character", profile.delimiter)));
+    }
+
+    let table = read_csv_table(input, profile.delimiter as u8)?;
+    import_login_table(&table, profile, options)
+}
+
+///
+/// Validate the rows of a table holding login data
+///
+/// Columns are matched by header name, so their order does not matter:
+/// the names set in the profile first, the standard English or Italian
+/// legacy ones otherwise. Rows with problems are reported, never dropped.
+///
+/// Arguments:
+/// * `table`: header and rows read from the input
+/// * `profile`: column names, group separator and default group
+/// * `options`: how missing logins and passwords are filled in
+///
+/// Returns:
+/// * the `ImportReport` with a result for every row, `Error::InvalidInput`
+///   if a required column is missing from the header
+///
+pub fn import_login_table(table: &Table, profile: &ImportProfile, options: &mut ImportOptions) -> Result<ImportReport> {
+    let columns = map_columns(table, profile)?;
+    let column = |field: Field| columns.get(&field).copied();
+
+    let rows = table.rows
+        .iter()
+        .map(|row| {
+            let cells = match &row.cells {
+                Ok(cells) => cells,
+                Err(reason) => return (row.line, Err(reason.clone()))
+            };
+
+            // Retrieve groups from raw string, then add the class and the default
+            // group of the profile if there are any
+            let mut groups: Vec<String> = row.cell(column(Field::Groups))
+                .split(profile.group_separator.as_str())
+                .map(|s| s.trim().to_owned())
+                .filter(|s| !s.is_empty())
+                .collect();
+
+            let class = row.cell(column(Field::Class));
+            if !class.is_empty() {
+                groups.push(class.to_owned());
+            }
+
+            if let Some(group) = &profile.default_group {
+                add_group(&mut groups, group);
+            }
+
+            // Passwords are taken as they are, surrounding spaces included
+            let password = column(Field::Password)
+                .and_then(|index| cells.get(index))
+                .cloned()
+                .unwrap_or_default();
+
+            let candidate = Candidate {
+                login: row.cell(column(Field::Login)).to_owned(),
+                last_name: row.cell(column(Field::LastName)).to_owned(),
+                first_name: row.cell(column(Field::FirstName)).to_owned(),
+                groups,
+                password: Some(password)
+            };
+
+            (row.line, Ok(candidate))
+        })
+        .collect();
+
+    Ok(validate(rows, options))
+}
+
+///
+/// Add a group to a list, unless it is empty or already there
+///
+pub fn add_group(groups: &mut Vec<String>, group: &str) {
+    let group = group.trim();
+
+    if !group.is_empty() && !groups.iter().any(|g| g.eq_ignore_ascii_case(group)) {
+        groups.push(group.to_owned());
+    }
+}
+
+///
+/// Report row of a line that could not be read
+///
+fn unreadable(line: u64, reason: &str) -> ImportRow {
+    let problem = Problem::Unreadable(reason.to_owned());
+    ImportRow { line, result: Err(vec![RowError { line, column: None, problem }]), generated_password: false }
+}
+
+///
+/// Read a whole file and decode it to text
+///
+/// Arguments:
+/// * `file_path`: complete path of the file
+/// * `encoding`: encoding label, e.g. `utf-8` or `windows-1252`
+///
+/// Returns:
+/// * the text of the file, `Error::Io` if it cannot be read, `Error::Config`
+///   for an unknown encoding, `Error::InvalidInput` if the content does not
+///   match the encoding
+///
+pub fn read_text(file_path: &str, encoding: &str) -> Result<String> {
+    let bytes = std::fs::read(file_path)
+        .map_err(|err| Error::Io(std::io::Error::new(err.kind(), format!("{}: {}", file_path, err))))?;
+
+    decode(&bytes, encoding)
+        .map_err(|err| match err {
+            Error::InvalidInput(what) => Error::InvalidInput(format!("{}: {}", file_path, what)),
+            err => err
+        })
+}
+
+///
+/// Decode the content of a file to text
+///
+/// A byte order mark, when present, takes precedence over `label`.
+///
+fn decode(bytes: &[u8], label: &str) -> Result<String> {
+    let encoding = Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| Error::Config(format!("unknown encoding '{}'", label)))?;
+
+    let (text, used, malformed) = encoding.decode(bytes);
+
+    if malformed {
+        return Err(Error::InvalidInput(format!(
+            "the file is not valid {} text, check the encoding of the import profile",
+            used.name()
+        )));
+    }
+
+    Ok(text.into_owned())
+}
+
+///
+/// Read CSV text into a `Table`, skipping blank lines
+///
+/// Arguments:
+/// * `input`: source of the UTF-8 CSV text, header included
+/// * `delimiter`: field delimiter
+///
+/// Returns:
+/// * the `Table`, `Error::Io` if the input cannot be read,
+///   `Error::InvalidInput` if the header cannot be read
+///
+pub fn read_csv_table<R: Read>(mut input: R, delimiter: u8) -> Result<Table> {
+    // The reader counts lines its own way, off after blank lines and with
+    // CRLF line ends: lines are counted on the text from the record offsets
+    let mut text = Vec::new();
+    input.read_to_end(&mut text)?;
+    let line_at = |byte: u64| line_at(&text, byte);
+
+    let mut reader = ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(true)
+        .flexible(true)
+        .from_reader(text.as_slice());
+
+    let header = reader.headers()
+        .map_err(|err| Error::InvalidInput(format!("cannot read header: {}", err)))?
+        .iter()
+        .map(str::to_owned)
+        .collect();
+
+    let mut table = Table { header, rows: Vec::new() };
+    let mut record = StringRecord::new();
+
+    loop {
+        let byte = reader.position().byte();
+
+        match reader.read_record(&mut record) {
+            Ok(false) => break,
+            Ok(true) => {},
+            Err(err) => {
+                let line = line_at(err.position().map(|pos| pos.byte()).unwrap_or(byte));
+                table.rows.push(TableRow { line, cells: Err(err.to_string()) });
+
+                // An I/O error would repeat forever, parse errors skip the row
+                if matches!(err.kind(), csv::ErrorKind::Io(_)) {
+                    break;
+                }
+                continue;
+            }
+        }
+
+        // Blank lines between records are not worth an error
+        if record.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+
+        let line = line_at(record.position().map(|pos| pos.byte()).unwrap_or(byte));
+        table.rows.push(TableRow { line, cells: Ok(record.iter().map(str::to_owned).collect()) });
+    }
+
+    Ok(table)
+}
+
+///
+/// Line, starting from 1, of the first non-blank character at or after
+/// `byte`: a record offset may point to the line ends before it
+///
+fn line_at(text: &[u8], byte: u64) -> u64 {
+    let start = (byte as usize).min(text.len());
+    let start = text[start..]
+        .iter()
+        .position(|c| !matches!(c, b'\r' | b'\n'))
+        .map_or(text.len(), |offset| start + offset);
+
+    1 + text[..start].iter().filter(|&&c| c == b'\n').count() as u64
+}
+
+///
+/// Header names accepted for a column with the given profile
+///
+fn header_names(field: Field, profile: &ImportProfile) -> Vec<&str> {
+    let columns = &profile.columns;
+    let configured = match field {
+        Field::Login => &columns.login,
+        Field::LastName => &columns.last_name,
+        Field::FirstName => &columns.first_name,
+        Field::Groups => &columns.groups,
+        Field::Class => &columns.class,
+        Field::Cf => &columns.cf,
+        Field::Password => &columns.password
+    };
+
+    match configured {
+        Some(name) => vec![name.as_str()],
+        None => field.names().to_vec()
+    }
+}
+
+///
+/// Find the index of every known column in the header
+///
+fn map_columns(table: &Table, profile: &ImportProfile) -> Result<HashMap<Field, usize>> {
+    let mut columns = HashMap::new();
+
+    for field in Field::ALL {
+        let names = header_names(field, profile);
+
+        match table.column(&names) {
+            Some(index) => { columns.insert(field, index); },
+            None if field.is_required() => {
+                return Err(Error::InvalidInput(format!("missing column '{}' in header", names.join("' or '"))));
+            },
+            None => {}
+        }
+    }
+
+    Ok(columns)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+    use crate::config::password_config::{PasswordPolicy, PasswordSettings};
+    use super::*;
+
+    /// Path of a file of `tests/fixtures`
+    pub(crate) fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    /// Profile of the rosters sent by the secretary's office, as configured
+    fn secretary_profile() -> ImportProfile {
+        serde_json::from_str(r#"{
+            "delimiter": ",",
+            "encoding": "windows-1252",
+            "columns": {
+                "login": "Utente",
+                "lastName": "Cognome Alunno",
+                "firstName": "Nome Alunno",
+                "groups": "Gruppi",
+                "password": "Parola chiave"
+            },
+            "groupSeparator": "|",
+            "defaultGroup": "studenti"
+        }"#).unwrap()
+    }
+
+    fn read(csv: &str, options: &mut ImportOptions) -> ImportReport {
+        read_login_records(csv.as_bytes(), &ImportProfile::default(), options).unwrap()
+    }
+
+    fn problems(report: &ImportReport) -> Vec<(u64, Option<Field>, Problem)> {
+        report.errors().map(|error| (error.line, error.column, error.problem.clone())).collect()
+    }
+
+    fn generator() -> PasswordGenerator {
+        PasswordGenerator::new(&PasswordSettings {
+            default_policy: None,
+            policies: HashMap::from([
+                ("primaria".to_string(), PasswordPolicy::Pronounceable { length: 6, digits: 2 })
+            ]),
+            groups: HashMap::from([("1A".to_string(), "primaria".to_string())])
+        }).unwrap()
+    }
+
+    #[test]
+    fn missing_required_columns_are_reported() {
+        let profile = ImportProfile::default();
+
+        let Err(Error::InvalidInput(message)) = read_login_records(
+            "login;nome;password\nmario.rossi;Mario;Secret#123\n".as_bytes(), &profile, &mut ImportOptions::default()
+        ) else {
+            panic!("file without last names accepted");
+        };
+        assert_eq!(message, "missing column 'last_name' or 'cognome' in header");
+
+        // Logins and passwords may be generated
+        let report = read("cognome;nome\nRossi;Mario\n", &mut ImportOptions::default());
+        assert_eq!(problems(&report), [
+            (2, Some(Field::Login), Problem::MissingField),
+            (2, Some(Field::Password), Problem::EmptyPassword)
+        ]);
+    }
+
+    #[test]
+    fn every_problem_of_every_row_is_reported() {
+        let report = read("\
+login;cognome;nome;gruppo;classe;CF;password;
+mario.rossi;Rossi;Mario;;3B;;Secret#123;
+anna bianchi!;Bianchi;Anna;;3B;;Secret#123;
+
+luca.verdi.con.un.nome.lungo;Verdi;Luca;;3B;;Secret#123;
+Mario.Rossi;Rossi;;;3B;;;
+paola.neri;;Paola;;3B;;Secret#123;
+", &mut ImportOptions::default());
+
+        assert_eq!(problems(&report), [
+            (3, Some(Field::Login), Problem::BadLoginCharacters(" !".to_string())),
+            (5, Some(Field::Login), Problem::LoginTooLong(28)),
+            (6, Some(Field::Login), Problem::DuplicateLogin(2)),
+            (6, Some(Field::FirstName), Problem::MissingField),
+            (6, Some(Field::Password), Problem::EmptyPassword),
+            (7, Some(Field::LastName), Problem::MissingField)
+        ]);
+        assert_eq!(report.rows.len(), 5);
+        assert_eq!(report.invalid_count(), 4);
+
+        let users = report.valid_users();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].common_name, "mario.rossi");
+        assert_eq!(users[0].groups, ["3B"]);
+    }
+
+    #[test]
+    fn logins_follow_the_domain_rules() {
+        let report = read("\
+login;cognome;nome;password
+-mario;Rossi;Mario;Secret#123
+mario.;Rossi;Mario;Secret#123
+mario_rossi-2.b;Rossi;Mario;Secret#123
+", &mut ImportOptions::default());
+
+        assert_eq!(problems(&report), [
+            (2, Some(Field::Login), Problem::InvalidLogin("user name \"-mario\": starts with '-'".to_string())),
+            (3, Some(Field::Login), Problem::InvalidLogin(
+                "user name \"mario.\": starts or ends with a space, or ends with '.'".to_string()
+            ))
+        ]);
+        assert_eq!(report.valid_users()[0].common_name, "mario_rossi-2.b");
+    }
+
+    #[test]
+    fn missing_logins_are_generated_around_the_given_ones() {
+        let mut options = ImportOptions {
+            logins: Some(LoginGenerator::new(login::LoginPattern::default(), 2024)),
+            passwords: None
+        };
+        let report = read("\
+login;cognome;nome;password
+;D'Angelo;Nicolò;Secret#123
+;De Luca;Anna;Secret#123
+anna.deluca;Bianchi;Anna;Secret#123
+", &mut options);
+
+        assert!(!report.has_errors());
+        let logins: Vec<String> = report.valid_users().into_iter().map(|user| user.common_name.clone()).collect();
+        assert_eq!(logins, ["nicolo.dangelo", "anna.deluca2", "anna.deluca"]);
+    }
+
+    #[test]
+    fn lines_are_counted_on_the_file() {
+        let table = read_csv_table("h\r\n1\r\n\r\n\r\n2\r\n\"3\n3\"\n4".as_bytes(), b';').unwrap();
+        let lines: Vec<u64> = table.rows.iter().map(|row| row.line).collect();
+
+        assert_eq!(lines, [2, 5, 6, 8]);
+    }
+
+    #[test]
+    fn empty_passwords_follow_the_group_policy() {
+        let csv = "\
+login;cognome;nome;gruppo;classe;CF;password;
+mario.rossi;Rossi;Mario;;1A;;;
+anna.bianchi;Bianchi;Anna;;3B;; ;
+luca.verdi;Verdi;Luca;;3B;;Secret#123;
+";
+
+        // Without a generator empty passwords are rejected, blank ones are
+        // taken as they are
+        let report = read(csv, &mut ImportOptions::default());
+        assert_eq!(problems(&report), [(2, Some(Field::Password), Problem::EmptyPassword)]);
+        assert_eq!(report.valid_users()[0].password, " ");
+
+        let mut options = ImportOptions { logins: None, passwords: Some(generator()) };
+        let report = read(csv, &mut options);
+        assert!(!report.has_errors());
+
+        let generated: Vec<bool> = report.rows.iter().map(|row| row.generated_password).collect();
+        assert_eq!(generated, [true, false, false]);
+
+        let users = report.valid_users();
+        let pronounceable = &users[0].password;
+        assert_eq!(pronounceable.chars().count(), 8, "{}", pronounceable);
+        assert!(pronounceable.chars().next().unwrap().is_ascii_uppercase(), "{}", pronounceable);
+        assert!(pronounceable.chars().skip(6).all(|c| c.is_ascii_digit()), "{}", pronounceable);
+        assert_eq!(users[2].password, "Secret#123");
+    }
+
+    #[test]
+    fn weak_passwords_are_rejected_or_regenerated() {
+        let csv = "\
+login;cognome;nome;gruppo;classe;CF;password;
+mario.rossi;Rossi;Mario;;3B;;Secret#123;
+anna.bianchi;Bianchi;Anna;;3B;;anna.bianchi1;
+luca.verdi;Verdi;Luca;;1A;;short;
+";
+        let policy = DomainPasswordPolicy::default();
+
+        let mut report = read(csv, &mut ImportOptions::default());
+        assert!(report.enforce_password_policy(&policy, None).is_empty());
+        assert_eq!(problems(&report), [
+            (3, Some(Field::Password), Problem::WeakPassword(
+                "contains the name \"anna.bianchi\", contains the name \"Anna\", contains the name \"Bianchi\"".to_string()
+            )),
+            (4, Some(Field::Password), Problem::WeakPassword(
+                "shorter than 7 characters, needs three of uppercase, lowercase, digits and symbols".to_string()
+            ))
+        ]);
+
+        let mut report = read(csv, &mut ImportOptions::default());
+        let replaced = report.enforce_password_policy(&policy, Some(&generator()));
+        let replaced: Vec<&str> = replaced.iter().map(|replaced| replaced.login.as_str()).collect();
+        assert_eq!(replaced, ["anna.bianchi", "luca.verdi"]);
+        assert!(!report.has_errors());
+
+        for (row, user) in report.rows.iter().zip(report.valid_users()) {
+            assert_eq!(row.generated_password, user.common_name != "mario.rossi");
+            assert!(policy.violations(&user.password, &user.common_name, &[&user.first_name, &user.last_name]).is_empty());
+        }
+    }
+
+    #[test]
+    fn profile_maps_the_columns_of_other_layouts() {
+        let report = read_login_file(&fixture("secretary-windows-1252.csv"), &secretary_profile(), &mut ImportOptions::default()).unwrap();
+
+        // The configured login column wins over the standard one
+        assert_eq!(problems(&report), [(3, Some(Field::Login), Problem::MissingField)]);
+
+        let users = report.valid_users();
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].common_name, "nicolo.dangelo");
+        assert_eq!(users[0].last_name, "D'Angelo");
+        assert_eq!(users[0].first_name, "Nicolò");
+        assert_eq!(users[0].groups, ["3B", "laboratorio", "studenti"]);
+        assert_eq!(users[0].password, "Perché#2024");
+        assert_eq!(users[1].last_name, "Müller");
+        assert_eq!(users[1].groups, ["studenti"]);
+        assert_eq!(users[1].password, "Straße#2024");
+    }
+
+    #[test]
+    fn byte_order_mark_overrides_the_profile_encoding() {
+        let profile = secretary_profile();
+        let read = |name: &str| read_login_file(&fixture(name), &profile, &mut ImportOptions::default()).unwrap().valid_users();
+
+        let from_bom = read("secretary-utf8-bom.csv");
+        let from_ansi = read("secretary-windows-1252.csv");
+
+        assert_eq!(from_bom.len(), 2);
+        for (bom, ansi) in from_bom.iter().zip(&from_ansi) {
+            assert_eq!((&bom.common_name, &bom.first_name, &bom.password), (&ansi.common_name, &ansi.first_name, &ansi.password));
+        }
+    }
+
+    #[test]
+    fn unusable_encodings_are_reported() {
+        let path = fixture("secretary-windows-1252.csv");
+
+        let utf8 = ImportProfile { encoding: "UTF-8".to_string(), ..secretary_profile() };
+        let Err(Error::InvalidInput(message)) = read_login_file(&path, &utf8, &mut ImportOptions::default()) else {
+            panic!("Windows-1252 text read as UTF-8");
+        };
+        assert_eq!(message, format!("{}: the file is not valid UTF-8 text, check the encoding of the import profile", path));
+
+        let unknown = ImportProfile { encoding: "latin-99".to_string(), ..secretary_profile() };
+        assert!(matches!(read_login_file(&path, &unknown, &mut ImportOptions::default()), Err(Error::Config(_))));
+
+        let delimiter = ImportProfile { delimiter: '§', ..secretary_profile() };
+        assert!(matches!(read_login_file(&path, &delimiter, &mut ImportOptions::default()), Err(Error::Config(_))));
+    }
+}
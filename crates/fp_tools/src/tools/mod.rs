@@ -1,21 +1,12 @@
-use serde::Deserialize;
-use sudo::RunningAs;
-use crate::commands::user::DomainUser;
-use csv::ReaderBuilder;
+pub mod import;
+pub mod registry;
+pub mod schema;
+pub mod spreadsheet;
 
-///
-/// Raw user structure
-///
-#[derive(Deserialize, Debug)]
-struct RawUser {
-    pub login: String,
-    pub last_name: String,
-    pub first_name: String,
-    pub groups: String,
-    pub class: String,
-    pub cf: String,
-    pub password: String
-}
+use sudo::RunningAs;
+use crate::config::tools_config::ImportProfile;
+use crate::error::Result;
+use import::{ImportOptions, ImportReport};
 
 #[macro_export]
 macro_rules! debug_println {
@@ -39,48 +30,42 @@ pub fn has_privileges() -> bool {
 }
 
 ///
-/// Reads a login CSV file into a vector of Domain Users
+/// Reads and validates a login CSV file
+///
+/// With the default profile both the Italian legacy header and the English
+/// one are accepted, see `schema::SchemaVersion`; other layouts are described
+/// by an `ImportProfile` of the configuration.
 ///
 /// Arguments:
 /// * `file_path`: `&str` value with complete file path
+/// * `profile`: layout of the file
+/// * `options`: how missing logins and passwords are filled in
 ///
 /// Returns:
-/// * a `Vec<DomainUser>` with all the domain users to process
+/// * an `ImportReport` with either a `DomainUser` or the problems found
+///   for every row, an `Error` if the file cannot be read at all
 ///
-pub fn read_login_csv(file_path: &str) -> Vec<DomainUser> {
-    let reader = ReaderBuilder::new()
-        .delimiter(b';')
-        .has_headers(true)
-        .from_path(file_path);
-
-    if let Ok(mut reader) = reader {
-        let mut users = Vec::new();
-
-        for raw_user in reader.deserialize::<RawUser>() {
-            let raw_user = raw_user.expect("Could not read user");
-
-            // Retrieve groups from raw string
-            let mut groups = raw_user.groups
-                .split(',')
-                .map(|s| s.trim().to_owned())
-                .collect::<Vec<String>>();
-
-            // If there is a class, add it to the groups
-            if !raw_user.class.is_empty() {
-                groups.push(raw_user.class.clone());
-            }
-
-            users.push(DomainUser {
-                common_name: raw_user.login.clone(),
-                last_name: raw_user.last_name.clone(),
-                first_name: raw_user.last_name.clone(),
-                groups: groups.clone(),
-                password:  raw_user.password.clone()
-            });
-        }
-
-        return users;
-    }
+pub fn read_login_csv(file_path: &str, profile: &ImportProfile, options: &mut ImportOptions) -> Result<ImportReport> {
+    import::read_login_file(file_path, profile, options)
+}
 
-    Vec::new()
+///
+/// Reads and validates a sheet of an XLSX or ODS file holding login data
+///
+/// The rows go through the same validation as `read_login_csv`; delimiter
+/// and encoding of the profile do not apply to spreadsheets.
+///
+/// Arguments:
+/// * `file_path`: `&str` value with complete file path
+/// * `sheet`: name of the sheet, `None` for the first one
+/// * `profile`: column names, group separator and default group
+/// * `options`: how missing logins and passwords are filled in
+///
+/// Returns:
+/// * an `ImportReport` with either a `DomainUser` or the problems found
+///   for every row, an `Error` if the sheet cannot be read at all
+///
+pub fn read_login_spreadsheet(file_path: &str, sheet: Option<&str>, profile: &ImportProfile, options: &mut ImportOptions) -> Result<ImportReport> {
+    let table = spreadsheet::read_sheet(file_path, sheet)?;
+    import::import_login_table(&table, profile, options)
 }
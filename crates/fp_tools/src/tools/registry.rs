@@ -0,0 +1,307 @@
+use crate::error::{Error, Result};
+use super::import::{self, Candidate, ImportOptions, ImportReport, Table};
+use super::spreadsheet;
+
+///
+/// Columns of a class roster exported from the school registry
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryField {
+    LastName,
+    FirstName,
+    TaxCode,
+    Class,
+    Section,
+    BirthDate
+}
+
+impl RegistryField {
+    ///
+    /// All the columns, in the usual roster order
+    ///
+    pub const ALL: [RegistryField; 6] = [
+        RegistryField::LastName,
+        RegistryField::FirstName,
+        RegistryField::TaxCode,
+        RegistryField::Class,
+        RegistryField::Section,
+        RegistryField::BirthDate
+    ];
+
+    ///
+    /// Header names used by the registry exports for this column
+    ///
+    pub fn names(&self) -> &'static [&'static str] {
+        match self {
+            RegistryField::LastName => &["Cognome"],
+            RegistryField::FirstName => &["Nome"],
+            RegistryField::TaxCode => &["Codice Fiscale", "Codice_Fiscale", "CodiceFiscale", "C.F.", "CF"],
+            RegistryField::Class => &["Classe", "Anno"],
+            RegistryField::Section => &["Sezione", "Sez."],
+            RegistryField::BirthDate => &["Data di nascita", "Data nascita", "Data_Nascita", "DataNascita"]
+        }
+    }
+
+    ///
+    /// Columns every roster must have
+    ///
+    pub fn is_required(&self) -> bool {
+        matches!(self, RegistryField::LastName | RegistryField::FirstName | RegistryField::Class)
+    }
+}
+
+///
+/// Built-in layout of a registry export
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegistryProfile {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub delimiter: u8,
+    pub encoding: &'static str
+}
+
+///
+/// Registry export formats known to the importer
+///
+pub const PROFILES: [RegistryProfile; 2] = [
+    RegistryProfile {
+        name: "registro",
+        description: "class roster saved by a spreadsheet as semicolon separated CSV (Windows-1252)",
+        delimiter: b';',
+        encoding: "windows-1252"
+    },
+    RegistryProfile {
+        name: "registro-utf8",
+        description: "class roster downloaded from the registry web interface as comma separated CSV (UTF-8)",
+        delimiter: b',',
+        encoding: "utf-8"
+    }
+];
+
+///
+/// Find a built-in registry profile by name
+///
+/// Returns:
+/// * `None` if there is no profile with that name
+///
+pub fn find_profile(name: &str) -> Option<&'static RegistryProfile> {
+    PROFILES.iter().find(|profile| profile.name.eq_ignore_ascii_case(name))
+}
+
+///
+/// Read and validate a class roster exported from the school registry
+///
+/// Arguments:
+/// * `file_path`: complete path of the file
+/// * `profile`: registry export format
+/// * `options`: login and password generators
+///
+/// Returns:
+/// * the `ImportReport` with a result for every row, `Error::Io` if the
+///   file cannot be read, `Error::InvalidInput` if it is not a roster
+///
+pub fn read_registry_file(file_path: &str, profile: &RegistryProfile, options: &mut ImportOptions) -> Result<ImportReport> {
+    let text = import::read_text(file_path, profile.encoding)?;
+    let table = import::read_csv_table(text.as_bytes(), profile.delimiter)?;
+
+    import_registry_table(&table, options)
+}
+
+///
+/// Read and validate a class roster kept in a sheet of an XLSX or ODS file
+///
+/// Arguments:
+/// * `file_path`: complete path of the file
+/// * `sheet`: name of the sheet, `None` for the first one
+/// * `options`: login and password generators
+///
+/// Returns:
+/// * the `ImportReport` with a result for every row, `Error::InvalidInput`
+///   if the sheet cannot be read or is not a roster
+///
+pub fn read_registry_spreadsheet(file_path: &str, sheet: Option<&str>, options: &mut ImportOptions) -> Result<ImportReport> {
+    let table = spreadsheet::read_sheet(file_path, sheet)?;
+    import_registry_table(&table, options)
+}
+
+///
+/// Turn the rows of a class roster into domain users
+///
+/// Names written in capitals are converted to title case, logins are
+/// built by the generator of the options and the class group is the class
+/// followed by the section (`1` and `A` become `1A`). Tax code and birth
+/// date are recognised but not needed to build the accounts.
+///
+/// Arguments:
+/// * `table`: header and rows of the roster
+/// * `options`: login and password generators
+///
+/// Returns:
+/// * the `ImportReport` with a result for every row, `Error::InvalidInput`
+///   if a required column is missing from the header
+///
+pub fn import_registry_table(table: &Table, options: &mut ImportOptions) -> Result<ImportReport> {
+    let column = |field: RegistryField| table.column(field.names());
+
+    for field in RegistryField::ALL {
+        if field.is_required() && column(field).is_none() {
+            return Err(Error::InvalidInput(format!("missing column '{}' in header", field.names()[0])));
+        }
+    }
+
+    let rows = table.rows
+        .iter()
+        .map(|row| {
+            if let Err(reason) = &row.cells {
+                return (row.line, Err(reason.clone()));
+            }
+
+            let mut groups = Vec::new();
+            import::add_group(
+                &mut groups,
+                &class_group(row.cell(column(RegistryField::Class)), row.cell(column(RegistryField::Section)))
+            );
+
+            let candidate = Candidate {
+                login: String::new(),
+                last_name: title_case(row.cell(column(RegistryField::LastName))),
+                first_name: title_case(row.cell(column(RegistryField::FirstName))),
+                groups,
+                password: None
+            };
+
+            (row.line, Ok(candidate))
+        })
+        .collect();
+
+    Ok(import::validate(rows, options))
+}
+
+///
+/// Group of a class: class and section joined, without spaces
+///
+fn class_group(class: &str, section: &str) -> String {
+    let class: String = class.split_whitespace().collect();
+    let section: String = section.split_whitespace().collect();
+
+    if section.is_empty() || class.to_lowercase().ends_with(&section.to_lowercase()) {
+        class
+    } else {
+        format!("{}{}", class, section.to_uppercase())
+    }
+}
+
+///
+/// Convert names written in capitals (`DE LUCA`, `D'ANGELO`) to title case,
+/// leaving mixed case names untouched
+///
+fn title_case(name: &str) -> String {
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if name.chars().any(char::is_lowercase) {
+        return name;
+    }
+
+    let mut result = String::with_capacity(name.len());
+    let mut start = true;
+
+    for c in name.chars() {
+        if start {
+            result.extend(c.to_uppercase());
+        } else {
+            result.extend(c.to_lowercase());
+        }
+        start = !c.is_alphabetic();
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::login::{LoginGenerator, LoginPattern};
+    use crate::tools::import::tests::fixture;
+    use crate::tools::import::{Problem, RowError};
+    use crate::tools::schema::Field;
+    use super::*;
+
+    fn options() -> ImportOptions {
+        ImportOptions { logins: Some(LoginGenerator::new(LoginPattern::default(), 2024)), passwords: None }
+    }
+
+    fn read(name: &str, profile: &str) -> ImportReport {
+        read_registry_file(&fixture(name), find_profile(profile).unwrap(), &mut options()).unwrap()
+    }
+
+    fn accounts(report: &ImportReport) -> Vec<(String, String, String, Vec<String>)> {
+        report.valid_users()
+            .into_iter()
+            .map(|user| (user.common_name.clone(), user.last_name.clone(), user.first_name.clone(), user.groups.clone()))
+            .collect()
+    }
+
+    fn account(login: &str, last_name: &str, first_name: &str, group: &str) -> (String, String, String, Vec<String>) {
+        (login.to_string(), last_name.to_string(), first_name.to_string(), vec![group.to_string()])
+    }
+
+    #[test]
+    fn spreadsheet_roster_in_windows_1252() {
+        let report = read("registro-windows-1252.csv", "registro");
+
+        assert_eq!(accounts(&report), [
+            account("nicolo.dangelo", "D'Angelo", "Nicolò", "1A"),
+            account("annamaria.deluca", "De Luca", "Anna Maria", "1A"),
+            account("mario.rossi", "Rossi", "Mario", "3B"),
+            account("mario.rossi2", "Rossi", "Mario", "3B")
+        ]);
+
+        // No password column: the passwords are generated or asked for later
+        let errors: Vec<&RowError> = report.errors().collect();
+        assert_eq!(errors.len(), 2);
+        assert_eq!((errors[0].line, errors[0].column, &errors[0].problem), (6, Some(Field::Login), &Problem::MissingField));
+        assert_eq!((errors[1].line, errors[1].column, &errors[1].problem), (6, Some(Field::LastName), &Problem::MissingField));
+    }
+
+    #[test]
+    fn web_roster_in_utf8() {
+        let report = read("registro-utf8.csv", "REGISTRO-UTF8");
+
+        assert!(!report.has_errors());
+        assert_eq!(accounts(&report), [
+            account("nicolo.dangelo", "D'Angelo", "Nicolò", "1A"),
+            account("giulia.verdidettobia", "Verdi, Detto Bianchi", "Giulia", "2C")
+        ]);
+    }
+
+    #[test]
+    fn files_that_are_not_rosters_are_refused() {
+        // A login file has no class column
+        let Err(Error::InvalidInput(message)) = read_registry_file(
+            &fixture("secretary-windows-1252.csv"), find_profile("registro").unwrap(), &mut options()
+        ) else {
+            panic!("login file read as a roster");
+        };
+        assert_eq!(message, "missing column 'Cognome' in header");
+
+        // The Windows-1252 export is not UTF-8
+        let result = read_registry_file(&fixture("registro-windows-1252.csv"), find_profile("registro-utf8").unwrap(), &mut options());
+        assert!(matches!(result, Err(Error::InvalidInput(_))));
+
+        assert!(find_profile("argo").is_none());
+    }
+
+    #[test]
+    fn class_groups_and_names() {
+        let cases = [("1", "A", "1A"), ("1", "a", "1A"), ("3B", "B", "3B"), ("3 ", "b", "3B"), ("4 C", "", "4C"), ("5", " D ", "5D")];
+        for (class, section, group) in cases {
+            assert_eq!(class_group(class, section), group, "{:?} {:?}", class, section);
+        }
+
+        assert_eq!(title_case("D'ANGELO"), "D'Angelo");
+        assert_eq!(title_case("DE  LUCA"), "De Luca");
+        assert_eq!(title_case("MARIA-GRAZIA"), "Maria-Grazia");
+        assert_eq!(title_case("McDonald"), "McDonald");
+        assert_eq!(title_case("NICOLÒ"), "Nicolò");
+    }
+}
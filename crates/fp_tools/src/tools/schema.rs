@@ -0,0 +1,390 @@
+use std::fs::{OpenOptions, Permissions};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use csv::{StringRecord, WriterBuilder};
+use serde::{Deserialize, Serialize};
+use crate::commands::user::DomainUser;
+use crate::db::rget_login::MySQLDomainUser;
+use crate::error::{Error, Result};
+
+///
+/// Field delimiter of login CSV files
+///
+pub const DELIMITER: u8 = b';';
+
+///
+/// Versions of the login CSV schema
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SchemaVersion {
+    ///
+    /// Italian legacy header written by the old Python tools and by
+    /// `get-login`: `login;cognome;nome;gruppo;classe;CF;password;`
+    /// (note the trailing delimiter)
+    ///
+    #[default]
+    V1,
+
+    ///
+    /// English header: `login;last_name;first_name;groups;class;cf;password`
+    ///
+    V2
+}
+
+impl SchemaVersion {
+    ///
+    /// Header fields of this schema version, trailing empty field included
+    ///
+    pub fn header(&self) -> &'static [&'static str] {
+        match self {
+            SchemaVersion::V1 => &["login", "cognome", "nome", "gruppo", "classe", "CF", "password", ""],
+            SchemaVersion::V2 => &["login", "last_name", "first_name", "groups", "class", "cf", "password"]
+        }
+    }
+
+    ///
+    /// Detect the schema version from the header of a file
+    ///
+    /// Returns:
+    /// * `None` if the header does not match any known version
+    ///
+    pub fn detect(headers: &StringRecord) -> Option<SchemaVersion> {
+        [SchemaVersion::V1, SchemaVersion::V2]
+            .into_iter()
+            .find(|version| {
+                version.header()
+                    .iter()
+                    .filter(|field| !field.is_empty())
+                    .all(|field| headers.iter().any(|h| h.trim().eq_ignore_ascii_case(field)))
+            })
+    }
+}
+
+///
+/// Columns of a login CSV file
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Field {
+    Login,
+    LastName,
+    FirstName,
+    Groups,
+    Class,
+    Cf,
+    Password
+}
+
+impl Field {
+    ///
+    /// All the columns, in file order
+    ///
+    pub const ALL: [Field; 7] = [
+        Field::Login,
+        Field::LastName,
+        Field::FirstName,
+        Field::Groups,
+        Field::Class,
+        Field::Cf,
+        Field::Password
+    ];
+
+    ///
+    /// Header names accepted for this column (English first, then Italian legacy)
+    ///
+    pub fn names(&self) -> &'static [&'static str] {
+        match self {
+            Field::Login => &["login"],
+            Field::LastName => &["last_name", "cognome"],
+            Field::FirstName => &["first_name", "nome"],
+            Field::Groups => &["groups", "gruppo"],
+            Field::Class => &["class", "classe"],
+            Field::Cf => &["cf"],
+            Field::Password => &["password"]
+        }
+    }
+
+    ///
+    /// Columns that must be in the header: logins and passwords may be
+    /// generated when their column is missing
+    ///
+    pub fn is_required(&self) -> bool {
+        matches!(self, Field::LastName | Field::FirstName)
+    }
+}
+
+///
+/// Row of a login CSV file. Both the English and the Italian legacy
+/// column names are accepted when reading.
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoginRecord {
+    pub login: String,
+
+    #[serde(alias = "cognome")]
+    pub last_name: String,
+
+    #[serde(alias = "nome")]
+    pub first_name: String,
+
+    /// Comma separated list of groups
+    #[serde(alias = "gruppo")]
+    pub groups: String,
+
+    #[serde(alias = "classe")]
+    pub class: String,
+
+    #[serde(alias = "CF")]
+    pub cf: String,
+
+    pub password: String
+}
+
+impl LoginRecord {
+    fn fields(&self) -> [&str; 7] {
+        [
+            &self.login,
+            &self.last_name,
+            &self.first_name,
+            &self.groups,
+            &self.class,
+            &self.cf,
+            &self.password
+        ]
+    }
+}
+
+impl From<&MySQLDomainUser> for LoginRecord {
+    fn from(user: &MySQLDomainUser) -> LoginRecord {
+        LoginRecord {
+            login: user.login.clone(),
+            last_name: user.last_name.clone(),
+            first_name: user.first_name.clone(),
+            groups: user.group.clone(),
+            class: user.class.clone(),
+            cf: user.tax_code.clone(),
+            password: user.password.clone()
+        }
+    }
+}
+
+///
+/// Write login records, header included, using the given schema version
+///
+/// Arguments:
+/// * `output`: destination of the CSV text
+/// * `version`: schema version to write
+/// * `records`: rows to write
+///
+/// Returns:
+/// * `Ok(())` on success, `Error::Io` otherwise
+///
+pub fn write_records<W: Write>(output: W, version: SchemaVersion, records: &[LoginRecord]) -> Result<()> {
+    write_csv(output, version, true, records)
+}
+
+///
+/// Write login records without the header, to add them at the end of an
+/// existing file using the given schema version
+///
+/// Arguments:
+/// * `output`: destination of the CSV text
+/// * `version`: schema version of the existing file
+/// * `records`: rows to write
+///
+/// Returns:
+/// * `Ok(())` on success, `Error::Io` otherwise
+///
+pub fn append_records<W: Write>(output: W, version: SchemaVersion, records: &[LoginRecord]) -> Result<()> {
+    write_csv(output, version, false, records)
+}
+
+fn write_csv<W: Write>(output: W, version: SchemaVersion, header: bool, records: &[LoginRecord]) -> Result<()> {
+    let mut writer = WriterBuilder::new()
+        .delimiter(DELIMITER)
+        .has_headers(false)
+        .from_writer(output);
+
+    let to_io = |err: csv::Error| Error::Io(err.into());
+
+    if header {
+        writer.write_record(version.header()).map_err(to_io)?;
+    }
+
+    for record in records {
+        let fields = record.fields();
+
+        match version {
+            // Legacy files end every line with the delimiter
+            SchemaVersion::V1 => writer.write_record(fields.iter().chain(&[""])),
+            SchemaVersion::V2 => writer.write_record(fields)
+        }.map_err(to_io)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+///
+/// Save logins and passwords of the given users to a credentials file,
+/// readable by its owner only, using the English (V2) header. An existing
+/// file is appended to: it may hold the only copy of the passwords
+/// generated by a previous run.
+///
+/// Arguments:
+/// * `file_path`: file to create or append to
+/// * `users`: users whose passwords have to be handed out
+///
+/// Returns:
+/// * `Ok(())` on success, `Error::Io` otherwise
+///
+pub fn write_credentials(file_path: &str, users: &[&DomainUser]) -> Result<()> {
+    let file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .open(file_path)?;
+
+    // The mode only applies to new files
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    let is_new = file.metadata()?.len() == 0;
+
+    let records: Vec<LoginRecord> = users
+        .iter()
+        .map(|user| LoginRecord {
+            login: user.common_name.clone(),
+            last_name: user.last_name.clone(),
+            first_name: user.first_name.clone(),
+            groups: user.groups.join(","),
+            password: user.password.clone(),
+            ..Default::default()
+        })
+        .collect();
+
+    if is_new {
+        write_records(file, SchemaVersion::V2, &records)
+    } else {
+        append_records(file, SchemaVersion::V2, &records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::config::tools_config::ImportProfile;
+    use crate::tools::import::{self, ImportOptions};
+    use super::*;
+
+    fn header(line: &str) -> StringRecord {
+        StringRecord::from(line.split(';').collect::<Vec<_>>())
+    }
+
+    fn student(login: &str, class: &str, group: &str, password: &str) -> MySQLDomainUser {
+        MySQLDomainUser {
+            login: login.to_string(),
+            last_name: "D'Angelo".to_string(),
+            first_name: "Nicolò".to_string(),
+            class: class.to_string(),
+            password: password.to_string(),
+            tax_code: "DNGNCL08A01H501X".to_string(),
+            group: group.to_string(),
+            birth_date: "2008-01-01".to_string(),
+            date_modified: None
+        }
+    }
+
+    #[test]
+    fn header_detection() {
+        let cases = [
+            ("login;cognome;nome;gruppo;classe;CF;password;", Some(SchemaVersion::V1)),
+            ("login;cognome;nome;gruppo;classe;CF;password", Some(SchemaVersion::V1)),
+            (" LOGIN ;Cognome;NOME;gruppo;classe;cf;Password", Some(SchemaVersion::V1)),
+            ("login;last_name;first_name;groups;class;cf;password", Some(SchemaVersion::V2)),
+            ("password;cf;class;groups;first_name;last_name;login;notes", Some(SchemaVersion::V2)),
+            ("login;last_name;first_name;groups;class;cf", None),
+            ("login;cognome;first_name;gruppo;classe;CF;password", None),
+            ("", None)
+        ];
+
+        for (line, expected) in cases {
+            assert_eq!(SchemaVersion::detect(&header(line)), expected, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn legacy_lines_end_with_the_delimiter() {
+        let records = [LoginRecord::from(&student("nicolo.dangelo", "3B", "", "Pa;ss\"1"))];
+
+        let mut v1 = Vec::new();
+        write_records(&mut v1, SchemaVersion::V1, &records).unwrap();
+        assert_eq!(String::from_utf8(v1).unwrap(), "\
+login;cognome;nome;gruppo;classe;CF;password;
+nicolo.dangelo;D'Angelo;Nicolò;;3B;DNGNCL08A01H501X;\"Pa;ss\"\"1\";
+");
+
+        let mut v2 = Vec::new();
+        append_records(&mut v2, SchemaVersion::V2, &records).unwrap();
+        assert_eq!(String::from_utf8(v2).unwrap(), "nicolo.dangelo;D'Angelo;Nicolò;;3B;DNGNCL08A01H501X;\"Pa;ss\"\"1\"\n");
+    }
+
+    #[test]
+    fn written_files_are_read_back_by_the_importer() {
+        let students = [
+            student("nicolo.dangelo", "3B", "lab, coro", "Pa;ss\"1 "),
+            student("anna.deluca", "3B", "", "Secret#123")
+        ];
+        let records: Vec<LoginRecord> = students.iter().map(LoginRecord::from).collect();
+
+        for version in [SchemaVersion::V1, SchemaVersion::V2] {
+            // As get-login does: the header goes with the first batch only
+            let mut text = Vec::new();
+            write_records(&mut text, version, &records[..1]).unwrap();
+            append_records(&mut text, version, &records[1..]).unwrap();
+
+            let csv = String::from_utf8(text.clone()).unwrap();
+            let mut reader = csv::ReaderBuilder::new().delimiter(DELIMITER).from_reader(csv.as_bytes());
+            assert_eq!(SchemaVersion::detect(reader.headers().unwrap()), Some(version));
+
+            let report = import::read_login_records(text.as_slice(), &ImportProfile::default(), &mut ImportOptions::default()).unwrap();
+            assert!(!report.has_errors(), "{:?}: {:?}", version, report.errors().collect::<Vec<_>>());
+
+            let users = report.valid_users();
+            assert_eq!(users.len(), 2);
+            assert_eq!(users[0].common_name, "nicolo.dangelo");
+            assert_eq!(users[0].last_name, "D'Angelo");
+            assert_eq!(users[0].first_name, "Nicolò");
+            assert_eq!(users[0].groups, ["lab", "coro", "3B"]);
+            assert_eq!(users[0].password, "Pa;ss\"1 ");
+            assert_eq!(users[1].groups, ["3B"]);
+            assert_eq!(users[1].groups, students[1].groups());
+        }
+    }
+
+    #[test]
+    fn credentials_are_appended_under_a_single_header() {
+        let path = std::env::temp_dir().join(format!("fp-tools-credentials-{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+
+        let user = |name: &str| DomainUser {
+            common_name: name.to_string(),
+            last_name: "Rossi".to_string(),
+            first_name: "Mario".to_string(),
+            groups: vec!["3B".to_string(), "lab".to_string()],
+            password: "Secret#123".to_string()
+        };
+
+        write_credentials(path, &[&user("mario.rossi")]).unwrap();
+        write_credentials(path, &[&user("luca.rossi")]).unwrap();
+
+        let content = fs::read_to_string(path).unwrap();
+        let mode = fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(content, "\
+login;last_name;first_name;groups;class;cf;password
+mario.rossi;Rossi;Mario;3B,lab;;;Secret#123
+luca.rossi;Rossi;Mario;3B,lab;;;Secret#123
+");
+        assert_eq!(mode, 0o600);
+    }
+}
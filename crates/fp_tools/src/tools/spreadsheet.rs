@@ -0,0 +1,170 @@
+use std::path::Path;
+use calamine::{open_workbook_auto, Data, Reader};
+use crate::error::{Error, Result};
+use super::import::{Table, TableRow};
+
+///
+/// Extensions of the spreadsheet files that can be read
+///
+pub const EXTENSIONS: [&str; 2] = ["xlsx", "ods"];
+
+///
+/// Returns:
+/// * `true` if the file name has a spreadsheet extension, `false` otherwise
+///
+pub fn is_spreadsheet(file_path: &str) -> bool {
+    Path::new(file_path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| EXTENSIONS.iter().any(|known| ext.eq_ignore_ascii_case(known)))
+}
+
+///
+/// Read a sheet of an XLSX or ODS file into a `Table`
+///
+/// The first non-empty row is the header, blank rows are skipped. Text
+/// cells are taken as they are (accents and leading zeros included),
+/// whole numbers are written without decimals.
+///
+/// Arguments:
+/// * `file_path`: complete path of the file
+/// * `sheet`: name of the sheet, `None` for the first one
+///
+/// Returns:
+/// * the `Table`, `Error::InvalidInput` if the file cannot be opened as a
+///   spreadsheet or has no such sheet
+///
+pub fn read_sheet(file_path: &str, sheet: Option<&str>) -> Result<Table> {
+    let invalid = |what: String| Error::InvalidInput(format!("{}: {}", file_path, what));
+
+    let mut workbook = open_workbook_auto(file_path).map_err(|err| invalid(err.to_string()))?;
+    let names = workbook.sheet_names();
+
+    let name = match sheet {
+        Some(sheet) => names
+            .iter()
+            .find(|name| name.eq_ignore_ascii_case(sheet))
+            .ok_or_else(|| invalid(format!("no sheet named '{}', available sheets: {}", sheet, names.join(", "))))?,
+        None => names.first().ok_or_else(|| invalid("the file has no sheets".to_string()))?
+    }.clone();
+
+    let range = workbook.worksheet_range(&name).map_err(|err| invalid(err.to_string()))?;
+
+    // Sheet rows are numbered from 1, the range may start further down
+    let first_row = range.start().map(|(row, _)| row as u64).unwrap_or(0) + 1;
+
+    let mut rows = range
+        .rows()
+        .enumerate()
+        .map(|(index, cells)| (first_row + index as u64, cells))
+        .filter(|(_, cells)| cells.iter().any(|cell| !cell_text(cell).trim().is_empty()));
+
+    let header = match rows.next() {
+        Some((_, cells)) => cells.iter().map(cell_text).collect(),
+        None => return Err(invalid(format!("sheet '{}' is empty", name)))
+    };
+
+    let rows = rows
+        .map(|(line, cells)| {
+            let error = cells.iter().position(|cell| matches!(cell, Data::Error(_)));
+
+            let cells = match error {
+                Some(column) => Err(format!("cell in column {} contains {}", column + 1, cells[column])),
+                None => Ok(cells.iter().map(cell_text).collect())
+            };
+
+            TableRow { line, cells }
+        })
+        .collect();
+
+    Ok(Table { header, rows })
+}
+
+fn cell_text(cell: &Data) -> String {
+    match cell {
+        Data::Float(value) if value.fract() == 0.0 && value.abs() < 1e15 => format!("{}", *value as i64),
+        cell => cell.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::tools_config::ImportProfile;
+    use crate::tools::import::tests::fixture;
+    use crate::tools::import::{self, ImportOptions, Problem};
+    use super::*;
+
+    fn lines(table: &Table) -> Vec<u64> {
+        table.rows.iter().map(|row| row.line).collect()
+    }
+
+    #[test]
+    fn first_sheet_header_is_its_first_non_empty_row() {
+        for (name, rows) in [("roster.xlsx", vec![4, 6, 7]), ("roster.ods", vec![4, 6])] {
+            let table = read_sheet(&fixture(name), None).unwrap();
+
+            assert_eq!(table.header, ["login", "cognome", "nome", "gruppo", "classe", "CF", "password"], "{}", name);
+            assert_eq!(lines(&table), rows, "{}", name);
+            assert_eq!(table.rows[0].cell(Some(0)), "nicolo.dangelo", "{}", name);
+        }
+    }
+
+    #[test]
+    fn sheets_are_chosen_by_name() {
+        for name in ["roster.xlsx", "roster.ods"] {
+            let path = fixture(name);
+
+            let table = read_sheet(&path, Some("DOCENTI")).unwrap();
+            assert_eq!(lines(&table), [2], "{}", name);
+            assert_eq!(table.rows[0].cell(Some(0)), "paola.neri", "{}", name);
+
+            let Err(Error::InvalidInput(message)) = read_sheet(&path, Some("Studenti")) else {
+                panic!("{}: missing sheet read", name);
+            };
+            assert_eq!(message, format!("{}: no sheet named 'Studenti', available sheets: Classe 1A, Docenti", path));
+        }
+    }
+
+    #[test]
+    fn cells_keep_their_text() {
+        for name in ["roster.xlsx", "roster.ods"] {
+            let table = read_sheet(&fixture(name), None).unwrap();
+            let anna = table.rows[1].cells.as_ref().unwrap();
+
+            // Leading zeros and accents are kept, whole numbers lose the decimals
+            assert_eq!(anna, &["anna.deluca", "De Luca", "Anna", "", "3", "", "007Segreto!"], "{}", name);
+            assert_eq!(table.rows[0].cell(Some(6)), "Perché#2024", "{}", name);
+        }
+
+        let table = read_sheet(&fixture("roster.xlsx"), None).unwrap();
+        assert_eq!(table.rows[2].cells, Err("cell in column 5 contains #DIV/0!".to_string()));
+    }
+
+    #[test]
+    fn sheet_rows_go_through_the_login_validation() {
+        let table = read_sheet(&fixture("roster.xlsx"), None).unwrap();
+        let report = import::import_login_table(&table, &ImportProfile::default(), &mut ImportOptions::default()).unwrap();
+
+        let users = report.valid_users();
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].groups, ["laboratorio", "1A"]);
+        assert_eq!(users[1].groups, ["3"]);
+        assert_eq!(users[1].password, "007Segreto!");
+
+        let errors: Vec<_> = report.errors().collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 7);
+        assert!(matches!(errors[0].problem, Problem::Unreadable(_)));
+    }
+
+    #[test]
+    fn only_spreadsheets_are_opened() {
+        assert!(is_spreadsheet("classe-1a.xlsx"));
+        assert!(is_spreadsheet("/tmp/CLASSE.ODS"));
+        assert!(!is_spreadsheet("classe-1a.csv"));
+        assert!(!is_spreadsheet("xlsx"));
+
+        assert!(matches!(read_sheet(&fixture("registro-utf8.csv"), None), Err(Error::InvalidInput(_))));
+        assert!(matches!(read_sheet(&fixture("missing.xlsx"), None), Err(Error::InvalidInput(_))));
+    }
+}
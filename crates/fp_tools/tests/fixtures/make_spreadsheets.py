@@ -0,0 +1,139 @@
+#!/usr/bin/env python3
+#
+# Build the spreadsheet fixtures: the same roster saved as XLSX and ODS.
+# The first sheet has its header on row 3 (rows 1 and 2 are blank), a
+# blank row between records, a number cell and, in XLSX only, a row with
+# an error cell.
+#
+import zipfile
+from xml.sax.saxutils import escape
+
+HEADER = ["login", "cognome", "nome", "gruppo", "classe", "CF", "password"]
+
+# (sheet row, cells): None is an empty cell, int a number, "#..." an error
+STUDENTS = [
+    (3, HEADER),
+    (4, ["nicolo.dangelo", "D'Angelo", "Nicolò", "laboratorio", "1A", "DNGNCL17B01H501Y", "Perché#2024"]),
+    (6, ["anna.deluca", "De Luca", "Anna", None, 3, None, "007Segreto!"]),
+    (7, ["mario.rossi", "Rossi", "Mario", None, "#DIV/0!", None, "Secret#123"])
+]
+
+TEACHERS = [
+    (1, HEADER),
+    (2, ["paola.neri", "Neri", "Paola", "docenti", None, None, "Docente#1"])
+]
+
+SHEETS = [("Classe 1A", STUDENTS), ("Docenti", TEACHERS)]
+
+
+def column(index):
+    return "ABCDEFGHIJ"[index]
+
+
+def xlsx_cell(ref, value):
+    if value is None:
+        return ""
+    if isinstance(value, int):
+        return f'<c r="{ref}"><v>{value}</v></c>'
+    if value.startswith("#"):
+        return f'<c r="{ref}" t="e"><v>{escape(value)}</v></c>'
+    return f'<c r="{ref}" t="inlineStr"><is><t>{escape(value)}</t></is></c>'
+
+
+def xlsx_sheet(rows, first_column):
+    xml = []
+    for number, cells in rows:
+        refs = [f"{column(first_column + i)}{number}" for i in range(len(cells))]
+        xml.append(f'<row r="{number}">' + "".join(xlsx_cell(r, v) for r, v in zip(refs, cells)) + "</row>")
+    return ('<?xml version="1.0" encoding="UTF-8" standalone="yes"?>'
+            '<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">'
+            f'<sheetData>{"".join(xml)}</sheetData></worksheet>')
+
+
+def write_xlsx(path):
+    sheets = "".join(
+        f'<sheet name="{escape(name)}" sheetId="{i + 1}" r:id="rId{i + 1}"/>' for i, (name, _) in enumerate(SHEETS)
+    )
+    relations = "".join(
+        f'<Relationship Id="rId{i + 1}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" '
+        f'Target="worksheets/sheet{i + 1}.xml"/>' for i in range(len(SHEETS))
+    )
+    overrides = "".join(
+        f'<Override PartName="/xl/worksheets/sheet{i + 1}.xml" '
+        'ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>' for i in range(len(SHEETS))
+    )
+
+    with zipfile.ZipFile(path, "w", zipfile.ZIP_DEFLATED) as z:
+        z.writestr("[Content_Types].xml",
+                   '<?xml version="1.0" encoding="UTF-8" standalone="yes"?>'
+                   '<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">'
+                   '<Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>'
+                   '<Default Extension="xml" ContentType="application/xml"/>'
+                   '<Override PartName="/xl/workbook.xml" '
+                   'ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/>'
+                   f'{overrides}</Types>')
+        z.writestr("_rels/.rels",
+                   '<?xml version="1.0" encoding="UTF-8" standalone="yes"?>'
+                   '<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">'
+                   '<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" '
+                   'Target="xl/workbook.xml"/></Relationships>')
+        z.writestr("xl/workbook.xml",
+                   '<?xml version="1.0" encoding="UTF-8" standalone="yes"?>'
+                   '<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" '
+                   'xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships">'
+                   f'<sheets>{sheets}</sheets></workbook>')
+        z.writestr("xl/_rels/workbook.xml.rels",
+                   '<?xml version="1.0" encoding="UTF-8" standalone="yes"?>'
+                   '<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">'
+                   f'{relations}</Relationships>')
+        for i, (_, rows) in enumerate(SHEETS):
+            # The roster starts in column B, as when pasted with a margin
+            z.writestr(f"xl/worksheets/sheet{i + 1}.xml", xlsx_sheet(rows, 1 if i == 0 else 0))
+
+
+def ods_cell(value):
+    if value is None:
+        return "<table:table-cell/>"
+    if isinstance(value, int):
+        return f'<table:table-cell office:value-type="float" office:value="{value}"><text:p>{value}</text:p></table:table-cell>'
+    return f'<table:table-cell office:value-type="string"><text:p>{escape(value)}</text:p></table:table-cell>'
+
+
+def ods_table(name, rows):
+    xml = []
+    current = 1
+    for number, cells in rows:
+        # ODS has no error cells, only the formulas producing them
+        if any(isinstance(value, str) and value.startswith("#") for value in cells):
+            continue
+        if number > current:
+            xml.append(f'<table:table-row table:number-rows-repeated="{number - current}"><table:table-cell/></table:table-row>')
+        xml.append("<table:table-row>" + "".join(ods_cell(v) for v in cells) + "</table:table-row>")
+        current = number + 1
+    return f'<table:table table:name="{escape(name)}">{"".join(xml)}</table:table>'
+
+
+def write_ods(path):
+    tables = "".join(ods_table(name, rows) for name, rows in SHEETS)
+
+    with zipfile.ZipFile(path, "w") as z:
+        # The mimetype must come first and be stored uncompressed
+        z.writestr("mimetype", "application/vnd.oasis.opendocument.spreadsheet", zipfile.ZIP_STORED)
+        z.writestr("META-INF/manifest.xml",
+                   '<?xml version="1.0" encoding="UTF-8"?>'
+                   '<manifest:manifest xmlns:manifest="urn:oasis:names:tc:opendocument:xmlns:manifest:1.0" manifest:version="1.2">'
+                   '<manifest:file-entry manifest:full-path="/" manifest:media-type="application/vnd.oasis.opendocument.spreadsheet"/>'
+                   '<manifest:file-entry manifest:full-path="content.xml" manifest:media-type="text/xml"/>'
+                   '</manifest:manifest>', zipfile.ZIP_DEFLATED)
+        z.writestr("content.xml",
+                   '<?xml version="1.0" encoding="UTF-8"?>'
+                   '<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" '
+                   'xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0" '
+                   'xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0" office:version="1.2">'
+                   f'<office:body><office:spreadsheet>{tables}</office:spreadsheet></office:body>'
+                   '</office:document-content>', zipfile.ZIP_DEFLATED)
+
+
+if __name__ == "__main__":
+    write_xlsx("roster.xlsx")
+    write_ods("roster.ods")
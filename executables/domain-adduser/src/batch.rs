@@ -0,0 +1,233 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process;
+use fp_tools::backend::DirectoryBackend;
+use fp_tools::batch::{self, BatchSummary, Progress, RowOutcome, RowReport};
+use fp_tools::batch::journal::{Journal, Step};
+use crate::input::{self, Source};
+use crate::report::report;
+
+///
+/// Import all the users listed in an input file, printing a line per row
+/// and the final counts. Invalid rows, including passwords the domain
+/// would refuse, are reported and skipped, the valid ones are imported
+/// anyway. Passwords generated for the created users are saved to the
+/// credentials file.
+///
+/// Rows are imported by `source.jobs` workers at the same time. Progress is
+/// recorded in the journal file, so that an interrupted or failed import
+/// can be finished with `resume`.
+///
+/// Returns:
+/// * `false` if any row failed or is invalid
+///
+pub fn run(backend: &dyn DirectoryBackend, source: &Source) -> bool {
+    // Generated logins must not clash with the accounts already in the domain
+    let existing = match backend.list_users() {
+        Ok(existing) => existing,
+        Err(err) => {
+            eprintln!("domain-adduser: cannot list domain users. {}", err);
+            process::exit(1)
+        }
+    };
+
+    let mut report = input::read(source, true, Some(&existing));
+    input::check_passwords(backend, source, &mut report);
+    input::print_invalid(&report);
+
+    let invalid = report.invalid_count();
+    let users = report.valid_users();
+
+    if users.is_empty() && invalid == 0 {
+        eprintln!("domain-adduser: no users found in {}", source.filename);
+        process::exit(1)
+    }
+
+    let generated: HashSet<&str> = report.rows
+        .iter()
+        .filter(|row| row.generated_password)
+        .filter_map(|row| row.result.as_ref().ok())
+        .map(|user| user.common_name.as_str())
+        .collect();
+
+    let plan = match batch::plan_import(backend, &users, |user| generated.contains(user.common_name.as_str())) {
+        Ok(plan) => plan,
+        Err(err) => {
+            eprintln!("domain-adduser: cannot start batch import. {}", err);
+            process::exit(1)
+        }
+    };
+
+    for username in &plan.existing {
+        report!("[skipped] {}: already exists", username);
+    }
+
+    // A dry run must not leave a journal to resume behind
+    let mut journal = if source.dry_run {
+        Journal::in_memory(plan.steps)
+    } else {
+        start_journal(source, plan.steps)
+    };
+
+    let summary = execute(backend, source, &mut journal);
+    report!(
+        "{} rows: {} created, {} skipped, {} failed, {} invalid",
+        report.rows.len(),
+        summary.created,
+        plan.existing.len(),
+        summary.failed,
+        invalid
+    );
+
+    summary.failed == 0 && invalid == 0
+}
+
+///
+/// Finish the import recorded in the journal of the input file: steps
+/// completed by the previous runs are not repeated, failed ones are
+/// attempted again. The input file itself is not read again.
+///
+/// Returns:
+/// * `false` if any row failed
+///
+pub fn resume(backend: &dyn DirectoryBackend, source: &Source) -> bool {
+    let path = source.journal_path();
+    let mut journal = match Journal::open(&path) {
+        Ok(journal) => journal,
+        Err(err) => {
+            eprintln!("domain-adduser: cannot read the journal {}. {}", path, err);
+            process::exit(1)
+        }
+    };
+
+    if journal.is_finished() {
+        report!("Nothing to resume: every step in {} has been completed", path);
+        return true;
+    }
+
+    report!("Resuming {}: {} of {} steps already completed", path, journal.completed(), journal.steps().len());
+    report!();
+
+    let summary = execute(backend, source, &mut journal);
+    report!(
+        "{} rows: {} created, {} created by the previous runs, {} failed",
+        summary.created + summary.previously_created + summary.failed,
+        summary.created,
+        summary.previously_created,
+        summary.failed
+    );
+
+    summary.failed == 0
+}
+
+///
+/// Create the journal of a new run, refusing to overwrite the one of an
+/// import that has not been finished. The journal of a finished run that
+/// made changes is only replaced if it has been rolled back, or if the
+/// file has been chosen with `--journal`: otherwise that run could not be
+/// undone any more. The rollback journal of the previous run goes with it.
+///
+pub fn start_journal(source: &Source, steps: Vec<Step>) -> Journal {
+    let path = source.journal_path();
+
+    if Path::new(&path).exists() {
+        match Journal::open(&path) {
+            Ok(previous) if previous.is_finished() => {
+                if previous.completed() > 0 && source.journal.is_none() && !rolled_back(source) {
+                    eprintln!(
+                        "domain-adduser: {} records the changes of a previous run, that could not be undone with --rollback once it is replaced. Delete it, or choose another file with --journal",
+                        path
+                    );
+                    process::exit(1)
+                }
+            },
+            Ok(previous) => {
+                eprintln!(
+                    "domain-adduser: a previous import stopped after {} of {} steps. Run again with --resume to finish it, --rollback to undo it, or delete {}",
+                    previous.completed(),
+                    previous.steps().len(),
+                    path
+                );
+                process::exit(1)
+            },
+            Err(err) => {
+                eprintln!("domain-adduser: cannot read the journal {} of a previous run. {}", path, err);
+                process::exit(1)
+            }
+        }
+    }
+
+    let rollback_path = source.rollback_journal_path();
+    if let Err(err) = fs::remove_file(&rollback_path) && err.kind() != ErrorKind::NotFound {
+        eprintln!("domain-adduser: cannot remove the rollback journal {} of a previous run. {}", rollback_path, err);
+        process::exit(1)
+    }
+
+    match Journal::create(&path, steps) {
+        Ok(journal) => journal,
+        Err(err) => {
+            eprintln!("domain-adduser: cannot create the journal {}. {}", path, err);
+            process::exit(1)
+        }
+    }
+}
+
+///
+/// Returns:
+/// * `true` if the rollback of the previous run has been completed
+///
+fn rolled_back(source: &Source) -> bool {
+    let path = source.rollback_journal_path();
+    Path::new(&path).exists() && Journal::open(&path).is_ok_and(|rollback| rollback.is_finished())
+}
+
+///
+/// Run the pending steps of the journal and save the generated passwords
+/// of the users it created, then remove them from the journal
+///
+fn execute(backend: &dyn DirectoryBackend, source: &Source, journal: &mut Journal) -> BatchSummary {
+    let summary = match batch::run_import(backend, journal, source.jobs, print_row) {
+        Ok(summary) => summary,
+        Err(err) => {
+            eprintln!("domain-adduser: cannot record the progress in {}, stopping. {}", journal.path(), err);
+            process::exit(1)
+        }
+    };
+
+    // Generated passwords are useless unless someone can hand them out
+    report!();
+    input::write_credentials(source, &journal.generated_credentials());
+
+    if let Err(err) = journal.scrub_passwords() {
+        eprintln!("domain-adduser: cannot remove the saved passwords from the journal {}. {}", journal.path(), err);
+        process::exit(1)
+    }
+
+    if summary.failed > 0 && !source.dry_run {
+        report!("Failed steps are recorded in {}: run again with --resume to retry them", journal.path());
+    }
+
+    summary
+}
+
+///
+/// Print the outcome of a row, prefixed by the rows processed so far: with
+/// parallel workers the rows are not printed in the order of the file
+///
+fn print_row(report: &RowReport, progress: Progress) {
+    let counter = format!("{:>width$}/{}", progress.done, progress.total, width = progress.total.to_string().len());
+
+    match &report.outcome {
+        RowOutcome::Created => {
+            report!("{} [created] {} ({})", counter, report.username, report.joined_groups.join(", "));
+
+            for group in &report.created_groups {
+                report!("{:width$}           new group {}", "", group, width = counter.len());
+            }
+        },
+        RowOutcome::PreviouslyCreated => report!("{} [done]    {}: created by a previous run", counter, report.username),
+        RowOutcome::Failed(err) => report!("{} [failed]  {}: {}", counter, report.username, err)
+    }
+}
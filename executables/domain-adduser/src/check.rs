@@ -0,0 +1,30 @@
+use std::process;
+use crate::input::{self, Source};
+
+///
+/// Validate an input file without touching the domain, printing the
+/// problems of every rejected row. Exits with a non-zero status if any
+/// row is invalid.
+///
+pub fn run(source: &Source) {
+    let report = input::read(source, true, Some(&[]));
+
+    for row in &report.rows {
+        if let Ok(user) = &row.result {
+            println!("[ok]      line {}: {}", row.line, user.common_name);
+        }
+    }
+    input::print_invalid(&report);
+
+    println!();
+    println!(
+        "{} rows: {} valid, {} invalid",
+        report.rows.len(),
+        report.rows.len() - report.invalid_count(),
+        report.invalid_count()
+    );
+
+    if report.has_errors() {
+        process::exit(1)
+    }
+}
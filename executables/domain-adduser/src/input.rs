@@ -0,0 +1,199 @@
+use std::path::Path;
+use std::process;
+use fp_tools::backend::DirectoryBackend;
+use fp_tools::commands::user::DomainUser;
+use fp_tools::config::tools_config::ImportProfile;
+use fp_tools::login::{LoginGenerator, LoginPattern};
+use fp_tools::password::PasswordGenerator;
+use fp_tools::tools::{self, registry::{self, RegistryProfile}, spreadsheet};
+use fp_tools::tools::import::{ImportOptions, ImportReport};
+use fp_tools::tools::schema;
+use crate::report::report;
+
+///
+/// Layout of the input file
+///
+pub enum Format {
+    /// Login CSV file, standard or described by a profile of the configuration
+    Login(Box<ImportProfile>),
+
+    /// Class roster exported from the school registry
+    Registry(&'static RegistryProfile)
+}
+
+///
+/// Input file given on the command line
+///
+pub struct Source {
+    pub filename: String,
+
+    /// Sheet to read from XLSX and ODS files, `None` for the first one
+    pub sheet: Option<String>,
+
+    pub format: Format,
+
+    /// Pattern of the logins generated for users without one
+    pub login_pattern: LoginPattern,
+
+    /// Value of the year placeholders of the pattern
+    pub login_year: i32,
+
+    /// Builds the passwords of the users without one
+    pub passwords: PasswordGenerator,
+
+    /// Where generated passwords are saved, `None` for the default file
+    pub credentials: Option<String>,
+
+    /// Replace the passwords refused by the domain policy instead of
+    /// rejecting their rows
+    pub regenerate_weak: bool,
+
+    /// Only plan the changes: no credentials file is written
+    pub dry_run: bool,
+
+    /// Where the progress of a batch is recorded, `None` for the default file
+    pub journal: Option<String>,
+
+    /// Number of rows of a batch import processed at the same time
+    pub jobs: usize
+}
+
+impl Source {
+    ///
+    /// File receiving the generated passwords: the one given on the
+    /// command line, or `<input>-credentials.csv` next to the input
+    ///
+    pub fn credentials_path(&self) -> String {
+        match &self.credentials {
+            Some(path) => path.clone(),
+            None => self.beside_input("credentials.csv")
+        }
+    }
+
+    ///
+    /// File recording the progress of a batch: the one given on the
+    /// command line, or `<input>-journal.jsonl` next to the input
+    ///
+    pub fn journal_path(&self) -> String {
+        match &self.journal {
+            Some(path) => path.clone(),
+            None => self.beside_input("journal.jsonl")
+        }
+    }
+
+    ///
+    /// File recording the progress of the rollback of the journal
+    ///
+    pub fn rollback_journal_path(&self) -> String {
+        Path::new(&self.journal_path())
+            .with_extension("rollback.jsonl")
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn beside_input(&self, suffix: &str) -> String {
+        let input = Path::new(&self.filename);
+        let stem = input.file_stem().and_then(|stem| stem.to_str()).unwrap_or("users");
+
+        input
+            .with_file_name(format!("{}-{}", stem, suffix))
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+///
+/// Read an input file, CSV or spreadsheet, exiting if it cannot be read at all
+///
+/// With `existing` set, users without a login get a generated one that
+/// clashes neither with the rest of the file nor with those accounts;
+/// otherwise rows without a login are rejected. When `passwords` is set,
+/// users without a password get one following their group policy.
+///
+pub fn read(source: &Source, passwords: bool, existing: Option<&[String]>) -> ImportReport {
+    let filename = source.filename.as_str();
+    let sheet = source.sheet.as_deref();
+    let is_spreadsheet = spreadsheet::is_spreadsheet(filename);
+
+    let logins = existing.map(|existing| {
+        let mut logins = LoginGenerator::new(source.login_pattern.clone(), source.login_year);
+        logins.reserve(existing);
+        logins
+    });
+
+    let mut options = ImportOptions {
+        logins,
+        passwords: passwords.then(|| source.passwords.clone())
+    };
+
+    let report = match &source.format {
+        Format::Login(profile) if is_spreadsheet => tools::read_login_spreadsheet(filename, sheet, profile, &mut options),
+        Format::Login(profile) => tools::read_login_csv(filename, profile, &mut options),
+        Format::Registry(_) if is_spreadsheet => registry::read_registry_spreadsheet(filename, sheet, &mut options),
+        Format::Registry(profile) => registry::read_registry_file(filename, profile, &mut options)
+    };
+
+    match report {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("domain-adduser: cannot read {}. {}", filename, err);
+            process::exit(1)
+        }
+    }
+}
+
+///
+/// Check the passwords of the report against the domain password policy,
+/// read once from the backend, so that offenders are reported before any
+/// account is touched. Replaced passwords are printed, rejected rows are
+/// left for `print_invalid`.
+///
+pub fn check_passwords(backend: &dyn DirectoryBackend, source: &Source, report: &mut ImportReport) {
+    let policy = match backend.password_policy() {
+        Ok(policy) => policy,
+        Err(err) => {
+            eprintln!("domain-adduser: cannot read the domain password policy, passwords not checked in advance. {}", err);
+            return;
+        }
+    };
+
+    let regenerate = source.regenerate_weak.then_some(&source.passwords);
+
+    for replaced in report.enforce_password_policy(&policy, regenerate) {
+        report!("[regenerated] line {}: {}: {}", replaced.line, replaced.login, replaced.reason);
+    }
+}
+
+///
+/// Print a line for every problem of the rejected rows
+///
+pub fn print_invalid(report: &ImportReport) {
+    for error in report.errors() {
+        report!("[invalid] {}", error);
+    }
+}
+
+///
+/// Add logins and generated passwords of the given users to the
+/// credentials file, readable by its owner only. The passwords of the
+/// previous runs are kept. Nothing is written in a dry run.
+///
+pub fn write_credentials(source: &Source, users: &[&DomainUser]) {
+    if users.is_empty() {
+        return;
+    }
+
+    let path = source.credentials_path();
+    if source.dry_run {
+        report!("Generated passwords of {} users would be saved to {}", users.len(), path);
+        return;
+    }
+
+    match schema::write_credentials(&path, users) {
+        Ok(()) => report!("Generated passwords of {} users saved to {}", users.len(), path),
+        Err(err) => {
+            eprintln!("domain-adduser: cannot save generated passwords to {}. {}", path, err);
+            process::exit(1)
+        }
+    }
+}
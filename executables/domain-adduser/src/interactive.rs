@@ -0,0 +1,207 @@
+use std::{io::{self, Write}, process};
+use fp_tools::backend::DirectoryBackend;
+use fp_tools::commands::user::DomainUser;
+use rpassword::read_password;
+use zeroize::Zeroizing;
+
+///
+/// Number of attempts given to type matching passwords
+///
+const PASSWORD_ATTEMPTS: usize = 3;
+
+///
+/// Ask for the data of a single user, show a summary and provision it
+/// after confirmation: missing groups are created on request, then the
+/// user is created and joined to its groups.
+///
+pub fn run(backend: &dyn DirectoryBackend) {
+    let username = prompt("Enter user name: ");
+
+    if username.is_empty() {
+        eprintln!("domain-adduser: empty user name. Aborting!");
+        process::exit(1)
+    }
+
+    match backend.user_exists(&username) {
+        Ok(false) => {},
+        Ok(true) => {
+            eprintln!("domain-adduser: user {} already exists in the domain. Aborting!", username);
+            process::exit(1)
+        },
+        Err(err) => {
+            eprintln!("domain-adduser: cannot look up user {}. {}", username, err);
+            process::exit(1)
+        }
+    }
+
+    // Additional user information
+    let last_name = prompt("\tLast name []: ");
+    let first_name = prompt("\tFirst name []: ");
+    let groups: Vec<String> = prompt("\tGroups [] (comma separated): ")
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    // Without the policy the domain controller still has the last word
+    let policy = match backend.password_policy() {
+        Ok(policy) => Some(policy),
+        Err(err) => {
+            eprintln!("domain-adduser: cannot read the domain password policy. {}", err);
+            None
+        }
+    };
+
+    let password = read_confirmed_password(|password| {
+        let violations = policy
+            .map(|policy| policy.violations(password, &username, &[&first_name, &last_name]))
+            .unwrap_or_default();
+
+        violations.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ")
+    });
+
+    // Decide what to do with the groups that do not exist yet
+    let mut new_groups = Vec::new();
+    let mut member_of = Vec::new();
+
+    for group in groups {
+        match backend.group_exists(&group) {
+            Ok(true) => member_of.push(group),
+            Ok(false) => {
+                if confirm(&format!("Group {} does not exist. Create it? [y/N] ", group)) {
+                    new_groups.push(group.clone());
+                    member_of.push(group);
+                } else {
+                    println!("\tUser will not be added to {}", group);
+                }
+            },
+            Err(err) => {
+                eprintln!("domain-adduser: cannot look up group {}. {}", group, err);
+                process::exit(1)
+            }
+        }
+    }
+
+    let user = DomainUser {
+        common_name: username,
+        last_name,
+        first_name,
+        groups: member_of,
+        password
+    };
+
+    print_summary(&user, &new_groups);
+
+    if !confirm("Proceed? [y/N] ") {
+        println!("Aborted, nothing has been changed.");
+        return;
+    }
+
+    provision(backend, &user, &new_groups);
+}
+
+///
+/// Create groups and user, then add the memberships
+///
+fn provision(backend: &dyn DirectoryBackend, user: &DomainUser, new_groups: &[String]) {
+    for group in new_groups {
+        if let Err(err) = backend.create_group(group) {
+            eprintln!("domain-adduser: cannot create group {}. {}", group, err);
+            process::exit(1)
+        }
+        println!("Created group {}", group);
+    }
+
+    if let Err(err) = backend.create_user(user) {
+        eprintln!("domain-adduser: cannot create user {}. {}", user.common_name, err);
+        process::exit(1)
+    }
+    println!("Created user {}", user.common_name);
+
+    let mut failed = false;
+    for group in &user.groups {
+        match backend.add_member(group, &user.common_name) {
+            Ok(()) => println!("Added {} to {}", user.common_name, group),
+            Err(err) => {
+                eprintln!("domain-adduser: cannot add {} to {}. {}", user.common_name, group, err);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        process::exit(1)
+    }
+}
+
+fn print_summary(user: &DomainUser, new_groups: &[String]) {
+    println!();
+    println!("User common name: {}", user.common_name);
+    println!("Last name: {}", user.last_name);
+    println!("First name: {}", user.first_name);
+
+    for group in &user.groups {
+        if new_groups.contains(group) {
+            println!("Member of: {} (new group)", group);
+        } else {
+            println!("Member of: {}", group);
+        }
+    }
+
+    println!();
+}
+
+///
+/// Read the password twice, asking again when the two do not match or
+/// `refusal` finds a reason the domain would refuse it. Rejected attempts
+/// and the confirmation are wiped from memory.
+///
+/// Arguments:
+/// * `refusal`: why a password is not acceptable, empty if it is
+///
+fn read_confirmed_password<F: Fn(&str) -> String>(refusal: F) -> String {
+    for _ in 0..PASSWORD_ATTEMPTS {
+        let password = prompt_password("Enter password: ");
+        let confirmation = prompt_password("Re-enter password for confirmation: ");
+        let reason = refusal(&password);
+
+        if password.is_empty() {
+            eprintln!("domain-adduser: the password cannot be empty, try again.");
+        } else if password != confirmation {
+            eprintln!("domain-adduser: passwords do not match, try again.");
+        } else if !reason.is_empty() {
+            eprintln!("domain-adduser: the domain would refuse this password ({}), try again.", reason);
+        } else {
+            return password.to_string();
+        }
+    }
+
+    eprintln!("domain-adduser: too many failed password attempts. Aborting!");
+    process::exit(1)
+}
+
+fn prompt_password(label: &str) -> Zeroizing<String> {
+    print!("{}", label);
+    io::stdout().flush().unwrap();
+
+    match read_password() {
+        Ok(pw) => Zeroizing::new(pw),
+        Err(err) => {
+            eprintln!("domain-adduser: error reading password. {:#?}", err);
+            process::exit(1)
+        }
+    }
+}
+
+fn prompt(label: &str) -> String {
+    print!("{}", label);
+    io::stdout().flush().unwrap();
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).unwrap();
+    input.trim().to_string()
+}
+
+pub fn confirm(question: &str) -> bool {
+    matches!(prompt(question).to_lowercase().as_str(), "y" | "yes" | "s" | "si")
+}
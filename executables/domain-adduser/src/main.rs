@@ -1,7 +1,23 @@
-use std::{io::{self, Write}, process};
-use clap::{ArgAction, CommandFactory, Parser};
-use fp_tools::{self, commands::user::is_existing_user, config::tools_config, debug_println, tools};
-use rpassword::read_password;
+mod batch;
+mod check;
+mod input;
+mod interactive;
+mod report;
+mod rollback;
+mod update;
+
+use std::process;
+use chrono::{Datelike, Local};
+use clap::{ArgAction, CommandFactory, Parser, ValueEnum};
+use fp_tools::{self, backend, batch::update::UpdateOptions, debug_println, tools, tools::registry};
+use fp_tools::backend::DirectoryBackend;
+use fp_tools::backend::plan::{Operation, PlanBackend};
+use fp_tools::batch::rollback::RollbackOptions;
+use fp_tools::config::tools_config::{self, ToolsConfiguration};
+use fp_tools::config::password_config::PasswordSettings;
+use fp_tools::login::{self, LoginPattern};
+use fp_tools::password::PasswordGenerator;
+use input::{Format, Source};
 
 /// 
 /// Command line arguments
@@ -9,37 +25,152 @@ use rpassword::read_password;
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Input file name
+    /// Input file name: CSV, or XLSX / ODS spreadsheet
     #[arg(short, long)]
     filename: Option<String>,
 
+    /// Sheet to read from a spreadsheet input file (default: the first one)
+    #[arg(short, long, requires = "filename")]
+    sheet: Option<String>,
+
     /// Interactive option
     #[arg(short, long, action = ArgAction::SetTrue)]
     interactive: bool,
 
-    /// Update
-    #[arg(short, long, action = ArgAction::SetTrue)]
-    update: bool
+    /// Update the existing accounts listed in the input file instead of creating them
+    #[arg(short, long, action = ArgAction::SetTrue, requires = "filename")]
+    update: bool,
+
+    /// With --update, also reset passwords to the ones in the input file
+    #[arg(long, action = ArgAction::SetTrue, requires = "update")]
+    reset_passwords: bool,
+
+    /// Name of the import profile of the configuration describing the input file
+    #[arg(short, long, requires = "filename")]
+    profile: Option<String>,
+
+    /// Read the input file as a class roster exported from the school registry
+    /// (formats: registro, registro-utf8)
+    #[arg(short, long, value_name = "FORMAT", requires = "filename", conflicts_with = "profile")]
+    registry: Option<String>,
+
+    /// Year used by the login pattern placeholders (default: current year)
+    #[arg(long, requires = "filename")]
+    year: Option<i32>,
+
+    /// File the generated passwords are added to (default: <input>-credentials.csv)
+    #[arg(long, requires = "filename")]
+    credentials: Option<String>,
+
+    /// Replace the passwords the domain password policy would refuse with
+    /// generated ones, instead of skipping their rows
+    #[arg(long, action = ArgAction::SetTrue, requires = "filename")]
+    regenerate_weak: bool,
+
+    /// Only validate the input file and print the report, without touching the domain
+    #[arg(long, action = ArgAction::SetTrue, requires = "filename", conflicts_with_all = ["interactive", "update"])]
+    check: bool,
+
+    /// Print the changes that would be made to the domain, without making them
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "check")]
+    dry_run: bool,
+
+    /// Format of the plan printed by --dry-run
+    #[arg(long, value_enum, default_value_t = PlanFormat::Text, requires = "dry_run")]
+    plan_format: PlanFormat,
+
+    /// Continue the interrupted or failed import of the input file from its journal
+    #[arg(long, action = ArgAction::SetTrue, requires = "filename", conflicts_with_all = ["update", "check", "dry_run"])]
+    resume: bool,
+
+    /// Number of users created at the same time by a batch import
+    #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..=64), requires = "filename", conflicts_with_all = ["update", "check", "rollback"])]
+    jobs: u16,
+
+    /// File recording the changes of the run (default: <input>-journal.jsonl,
+    /// not replaced while it holds changes that can be rolled back)
+    #[arg(long, requires = "filename", conflicts_with = "check")]
+    journal: Option<String>,
+
+    /// Undo the changes recorded in the journal of a previous run on the input file
+    #[arg(long, action = ArgAction::SetTrue, requires = "filename", conflicts_with_all = ["update", "check", "resume", "interactive"])]
+    rollback: bool,
+
+    /// With --rollback, also add the users back to the groups the run removed them from
+    #[arg(long, action = ArgAction::SetTrue, requires = "rollback")]
+    restore_groups: bool,
+
+    /// With --rollback, do not ask for confirmation
+    #[arg(short, long, action = ArgAction::SetTrue, requires = "rollback")]
+    yes: bool
+}
+
+///
+/// Output formats of the dry run plan
+///
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum PlanFormat {
+    /// One numbered line per operation, after the usual report
+    Text,
+
+    /// JSON array of operations on standard output, the report goes to standard error
+    Json
 }
 
 fn main() {
 
+    // Parse command line arguments
+    let cli = Args::parse();
+
+    // Validation only: no privileges or domain access needed
+    if cli.check && let Some(filename) = &cli.filename {
+        // The config file is readable by root only: check with the defaults without it
+        let config = match tools_config::read_config_from_file("/etc/ad/settings.json") {
+            Ok(config) => Some(config),
+            Err(err) => {
+                eprintln!("domain-adduser: {}. Checking with the default login pattern and password policies", err);
+                None
+            }
+        };
+
+        check::run(&input_source(&cli, filename, config.as_ref()));
+        return;
+    }
+
     if !tools::has_privileges() {
         eprintln!("domain-adduser: this program needs to be run in privileged mode!");
         process::exit(1);
     }
 
-    // Parse command line arguments
-    let cli = Args::parse();
-
     // Load configuration
-    let config = tools_config::read_config_from_file("/etc/ad/settings.json");
-    if config.is_none() {
-        eprintln!("domain-adduser: cannot load config file '/etc/ad/settings.json'");
-        process::exit(1)
-    }
+    let config = load_config();
+
+    debug_println!("{:#?}", &config);
+
+    // Directory backend used for every domain operation
+    let backend = match backend::from_config(&config) {
+        Ok(backend) => backend,
+        Err(err) => {
+            eprintln!("domain-adduser: cannot set up directory backend. {}", err);
+            process::exit(1)
+        }
+    };
+
+    // In a dry run changes are only recorded, queries still reach the domain
+    let planner = cli.dry_run.then(|| PlanBackend::new(backend.as_ref()));
+    let target: &dyn DirectoryBackend = match &planner {
+        Some(planner) => planner,
+        None => backend.as_ref()
+    };
 
-    debug_println!("{:#?}", &config.as_ref().unwrap());
+    if cli.dry_run && cli.plan_format == PlanFormat::Json {
+        // Prompts would end up mixed with the JSON document
+        if cli.interactive {
+            eprintln!("domain-adduser: --plan-format json cannot be used in interactive mode");
+            process::exit(1)
+        }
+        report::use_stderr();
+    }
 
     // If no command has been provided, exit
     if cli.filename.is_none() && !cli.interactive && !cli.update {
@@ -47,73 +178,137 @@ fn main() {
         process::exit(1)
     }
 
+    let mut succeeded = true;
+
+    // Batch mode handling
+    if let Some(filename) = &cli.filename {
+        let source = input_source(&cli, filename, Some(&config));
+
+        succeeded = if cli.rollback {
+            let options = RollbackOptions { restore_groups: cli.restore_groups };
+            rollback::run(target, &source, options, cli.yes)
+        } else if cli.update {
+            let options = UpdateOptions { passwords: cli.reset_passwords };
+            update::run(target, &source, options)
+        } else if cli.resume {
+            batch::resume(target, &source)
+        } else {
+            batch::run(target, &source)
+        };
+    }
+
     // Interactive mode handling
     if cli.interactive {
-        print!("Enter user name: ");
-        io::stdout().flush().unwrap();
+        interactive::run(target);
+    }
 
-        let mut username = String::new();
-        io::stdin().read_line(&mut username).unwrap();
+    if let Some(planner) = &planner {
+        print_plan(&planner.operations(), cli.plan_format);
+    }
 
-        let username = username.trim();
-        
-        if is_existing_user(&config.unwrap(), &username.to_string()) {
-            eprintln!("domain-adduser: user {} already exists in the domain. Aborting!", username);
-            process::exit(1)
-        }
+    if !succeeded {
+        process::exit(1)
+    }
+}
 
-        // Additional user information
-        let mut last_name = String::new();
-        print!("\tLast name []: ");
-        io::stdout().flush().unwrap();
-        io::stdin().read_line(&mut last_name).unwrap();
-        let last_name = last_name.trim();
-
-        let mut first_name = String::new();
-        print!("\tFirst name []: ");
-        io::stdout().flush().unwrap();
-        io::stdin().read_line(&mut first_name).unwrap();
-        let first_name = first_name.trim();
-
-        let mut groups_input = String::new();
-        print!("\tGroups [] (comma separated): ");
-        io::stdout().flush().unwrap();
-        io::stdin().read_line(&mut groups_input).unwrap();
-        let groups: Vec<&str> = groups_input
-            .trim()
-            .split(',')
-            .map(|s| s.trim()).filter(|s| !s.is_empty())
-            .collect();
-
-        let mut password1 = "".to_string();
-        let mut password2 = "".to_string();
-
-        print!("Enter password: ");
-        io::stdout().flush().unwrap();
-
-        match read_password() {
-            Ok(pw) => password1 = pw.clone(),
-            Err(err) => eprintln!("domain-adduser: error reading password. {:#?}", err)
-        };
+///
+/// Print the operations recorded by a dry run
+///
+fn print_plan(operations: &[Operation], format: PlanFormat) {
+    match format {
+        PlanFormat::Text => {
+            println!();
+            if operations.is_empty() {
+                println!("Dry run: nothing to change.");
+                return;
+            }
 
-        print!("Re-enter password for confirmation: ");
-        io::stdout().flush().unwrap();
-        
-        match read_password() {
-            Ok(pw) => password2 = pw.clone(),
-            Err(err) => eprintln!("domain-adduser: error reading password confirmation. {:#?}", err)
-        };
+            println!("Dry run: {} operations planned, none executed", operations.len());
+            for (index, operation) in operations.iter().enumerate() {
+                println!("{:>5}. {}", index + 1, operation);
+            }
+        },
+        PlanFormat::Json => match serde_json::to_string_pretty(operations) {
+            Ok(json) => println!("{}", json),
+            Err(err) => {
+                eprintln!("domain-adduser: cannot format the plan. {}", err);
+                process::exit(1)
+            }
+        }
+    }
+}
+
+fn load_config() -> ToolsConfiguration {
+    match tools_config::read_config_from_file("/etc/ad/settings.json") {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("domain-adduser: cannot load config file. {}", err);
+            process::exit(1)
+        }
+    }
+}
 
-        if password1 != password2 {
-            eprintln!("domain-adduser: ")
+///
+/// Input file and layout chosen on the command line, with the defaults of
+/// the configuration when there is none
+///
+fn input_source(cli: &Args, filename: &str, config: Option<&ToolsConfiguration>) -> Source {
+    let pattern = config.map_or(login::DEFAULT_PATTERN, |config| config.login_pattern.as_str());
+    let login_pattern = match LoginPattern::parse(pattern) {
+        Ok(pattern) => pattern,
+        Err(err) => {
+            eprintln!("domain-adduser: {}", err);
+            process::exit(1)
         }
+    };
 
-        debug_println!("Collected user information: ");
-        debug_println!("User common name: {}", username);
-        debug_println!("Last name: {}", last_name);
-        debug_println!("First name: {}", first_name);
-        for group in groups {
-            debug_println!("Member of: {}", group);
+    let default_passwords = PasswordSettings::default();
+    let passwords = match PasswordGenerator::new(config.map_or(&default_passwords, |config| &config.passwords)) {
+        Ok(passwords) => passwords,
+        Err(err) => {
+            eprintln!("domain-adduser: {}", err);
+            process::exit(1)
         }
+    };
+
+    Source {
+        filename: filename.to_string(),
+        sheet: cli.sheet.clone(),
+        format: input_format(cli, config),
+        login_pattern,
+        login_year: cli.year.unwrap_or_else(|| Local::now().year()),
+        passwords,
+        credentials: cli.credentials.clone(),
+        regenerate_weak: cli.regenerate_weak,
+        dry_run: cli.dry_run,
+        journal: cli.journal.clone(),
+        jobs: cli.jobs.into()
+    }
+}
+
+fn input_format(cli: &Args, config: Option<&ToolsConfiguration>) -> Format {
+    if let Some(name) = &cli.registry {
+        return match registry::find_profile(name) {
+            Some(profile) => Format::Registry(profile),
+            None => {
+                eprintln!("domain-adduser: unknown registry format '{}'", name);
+                process::exit(1)
+            }
+        };
+    }
+
+    match &cli.profile {
+        Some(name) => match config.map(|config| config.import_profiles.get(name)) {
+            Some(Some(profile)) => Format::Login(Box::new(profile.clone())),
+            None => {
+                eprintln!("domain-adduser: import profile '{}' needs the config file", name);
+                process::exit(1)
+            },
+            Some(None) => {
+                eprintln!("domain-adduser: no import profile named '{}' in config file", name);
+                process::exit(1)
+            }
+        },
+        None => Format::Login(Box::default())
     }
 }
@@ -0,0 +1,33 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+///
+/// The human readable report goes to standard error, standard output
+/// being reserved to a machine readable document (the JSON plan)
+///
+static TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+///
+/// Send the report lines to standard error from now on
+///
+pub fn use_stderr() {
+    TO_STDERR.store(true, Ordering::Relaxed);
+}
+
+pub fn is_stderr() -> bool {
+    TO_STDERR.load(Ordering::Relaxed)
+}
+
+///
+/// Print a line of the run report, like `println!`
+///
+macro_rules! report {
+    ($($arg:tt)*) => {
+        if $crate::report::is_stderr() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    }
+}
+
+pub(crate) use report;
@@ -0,0 +1,126 @@
+use std::path::Path;
+use std::process;
+use fp_tools::backend::DirectoryBackend;
+use fp_tools::batch::journal::{Journal, Step};
+use fp_tools::batch::rollback::{self, RollbackOptions, UndoOutcome, UndoReport};
+use crate::input::Source;
+use crate::interactive;
+use crate::report::report;
+
+///
+/// Undo the changes recorded in the journal of a previous run on the input
+/// file, after showing them and asking for confirmation. The rollback has
+/// a journal of its own: if it is interrupted, running it again finishes it.
+///
+/// Returns:
+/// * `false` if any change could not be undone, or the rollback was cancelled
+///
+pub fn run(backend: &dyn DirectoryBackend, source: &Source, options: RollbackOptions, assume_yes: bool) -> bool {
+    let path = source.rollback_journal_path();
+    let resuming = Path::new(&path).exists();
+
+    let steps = if resuming {
+        let previous = open_journal(&path);
+        if previous.is_finished() {
+            report!("Nothing to undo: the rollback recorded in {} has been completed", path);
+            return true;
+        }
+
+        report!("Resuming the rollback recorded in {}: {} of {} changes already undone", path, previous.completed(), previous.steps().len());
+        previous.steps().to_vec()
+    } else {
+        let journal = open_journal(&source.journal_path());
+        let plan = match rollback::plan_rollback(backend, &journal, options) {
+            Ok(plan) => plan,
+            Err(err) => {
+                eprintln!("domain-adduser: cannot compare the journal with the domain. {}", err);
+                process::exit(1)
+            }
+        };
+
+        for step in &plan.kept {
+            report!("[kept]    {}{}", step, kept_reason(step));
+        }
+
+        if plan.steps.is_empty() {
+            report!("Nothing to undo in {}", journal.path());
+            return true;
+        }
+
+        report!("Rolling back {}: {} changes to undo", journal.path(), plan.steps.len());
+        plan.steps
+    };
+
+    for (index, step) in steps.iter().enumerate() {
+        report!("{:>5}. {}", index + 1, step);
+    }
+    report!();
+
+    let journal = if source.dry_run {
+        Journal::in_memory(steps)
+    } else {
+        if !assume_yes && !interactive::confirm("Undo these changes? [y/N] ") {
+            report!("Rollback cancelled");
+            return false;
+        }
+
+        if resuming {
+            open_journal(&path)
+        } else {
+            match Journal::create(&path, steps) {
+                Ok(journal) => journal,
+                Err(err) => {
+                    eprintln!("domain-adduser: cannot create the journal {}. {}", path, err);
+                    process::exit(1)
+                }
+            }
+        }
+    };
+
+    let summary = match rollback::run_rollback(backend, &journal, print_step) {
+        Ok(summary) => summary,
+        Err(err) => {
+            eprintln!("domain-adduser: cannot record the progress in {}, stopping. {}", path, err);
+            process::exit(1)
+        }
+    };
+
+    report!();
+    if summary.failed > 0 && !source.dry_run {
+        report!("Run --rollback again to retry the changes that could not be undone");
+    }
+    report!(
+        "{} changes: {} undone, {} undone by a previous run, {} failed",
+        journal.steps().len(),
+        summary.undone,
+        summary.previously_undone,
+        summary.failed
+    );
+
+    summary.failed == 0
+}
+
+fn open_journal(path: &str) -> Journal {
+    match Journal::open(path) {
+        Ok(journal) => journal,
+        Err(err) => {
+            eprintln!("domain-adduser: cannot read the journal {}. {}", path, err);
+            process::exit(1)
+        }
+    }
+}
+
+fn kept_reason(step: &Step) -> &'static str {
+    match step {
+        Step::RemoveMember { .. } => " (use --restore-groups to undo)",
+        _ => " (cannot be undone)"
+    }
+}
+
+fn print_step(report: &UndoReport) {
+    match &report.outcome {
+        UndoOutcome::Undone => report!("[undone]  {}", report.step),
+        UndoOutcome::PreviouslyUndone => report!("[done]    {}: undone by a previous run", report.step),
+        UndoOutcome::Failed(err) => report!("[failed]  {}: {}", report.step, err)
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashSet;
+use std::process;
+use fp_tools::backend::DirectoryBackend;
+use fp_tools::batch::journal::Journal;
+use fp_tools::batch::update::{self, Change, UpdateOptions, UpdateOutcome, UpdateReport};
+use crate::batch;
+use crate::input::{self, Source};
+use crate::report::report;
+
+///
+/// Update the existing accounts listed in an input file, printing what
+/// changed for every user and the final counts. Invalid rows are reported
+/// and skipped. With password reset, users without a password in the file
+/// get a generated one, saved to the credentials file. The changes are
+/// recorded in the journal file, so that they can be rolled back.
+///
+/// Returns:
+/// * `false` if any update failed or any row is invalid
+///
+pub fn run(backend: &dyn DirectoryBackend, source: &Source, options: UpdateOptions) -> bool {
+    // Accounts are never guessed: rows without a login are rejected
+    let mut report = input::read(source, options.passwords, None);
+
+    // Passwords are only sent when they are reset
+    if options.passwords {
+        input::check_passwords(backend, source, &mut report);
+    }
+    input::print_invalid(&report);
+
+    let invalid = report.invalid_count();
+    let users = report.valid_users();
+
+    if users.is_empty() && invalid == 0 {
+        eprintln!("domain-adduser: no users found in {}", source.filename);
+        process::exit(1)
+    }
+
+    // Changes are recorded as they are made
+    let mut journal = if source.dry_run {
+        Journal::in_memory(Vec::new())
+    } else {
+        batch::start_journal(source, Vec::new())
+    };
+
+    let mut reset = HashSet::new();
+    let result = update::update_users(backend, &users, options, &mut journal, |user| {
+        print_user(user);
+
+        let changes = match &user.outcome {
+            UpdateOutcome::Updated(changes) | UpdateOutcome::Failed(_, changes) => changes.as_slice(),
+            _ => &[]
+        };
+        if changes.contains(&Change::Password) {
+            reset.insert(user.username.clone());
+        }
+    });
+
+    let generated: Vec<_> = report.rows
+        .iter()
+        .filter(|row| row.generated_password)
+        .filter_map(|row| row.result.as_ref().ok())
+        .filter(|user| reset.contains(&user.common_name))
+        .collect();
+
+    report!();
+    input::write_credentials(source, &generated);
+
+    let summary = match result {
+        Ok(summary) => summary,
+        Err(err) => {
+            eprintln!("domain-adduser: cannot record the changes in {}, stopping. {}", journal.path(), err);
+            process::exit(1)
+        }
+    };
+
+    report!(
+        "{} users: {} updated, {} unchanged, {} not in the domain, {} failed, {} invalid",
+        report.rows.len(),
+        summary.updated,
+        summary.unchanged,
+        summary.missing,
+        summary.failed,
+        invalid
+    );
+
+    summary.failed == 0 && invalid == 0
+}
+
+fn print_user(report: &UpdateReport) {
+    match &report.outcome {
+        UpdateOutcome::Updated(changes) => {
+            let changes: Vec<String> = changes.iter().map(|change| change.to_string()).collect();
+            report!("[updated]   {}: {}", report.username, changes.join(", "));
+        },
+        UpdateOutcome::Unchanged => report!("[unchanged] {}", report.username),
+        UpdateOutcome::Missing => report!("[missing]   {}: not in the domain", report.username),
+        UpdateOutcome::Failed(err, applied) => {
+            report!("[failed]    {}: {}", report.username, err);
+
+            for change in applied {
+                report!("            applied before failing: {}", change);
+            }
+        }
+    }
+}
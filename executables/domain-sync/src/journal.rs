@@ -0,0 +1,178 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process;
+use fp_tools::backend::DirectoryBackend;
+use fp_tools::batch::{self, BatchSummary, Progress, RowOutcome, RowReport};
+use fp_tools::batch::journal::{Journal, Step};
+use fp_tools::commands::user::DomainUser;
+use fp_tools::tools::schema;
+
+///
+/// File recording the progress of the rollback of a journal
+///
+pub fn rollback_path(journal_path: &str) -> String {
+    Path::new(journal_path)
+        .with_extension("rollback.jsonl")
+        .to_string_lossy()
+        .into_owned()
+}
+
+///
+/// Create the journal of a new run, refusing to overwrite the one of a run
+/// that has not been finished. The journal of a finished run is replaced,
+/// along with the rollback journal of that run.
+///
+pub fn start(path: &str, steps: Vec<Step>) -> Journal {
+    if Path::new(path).exists() {
+        match Journal::open(path) {
+            Ok(previous) if previous.is_finished() => {},
+            Ok(previous) => {
+                eprintln!(
+                    "domain-sync: a previous run stopped after {} of {} changes. Run again with --resume to finish it, --rollback to undo it, or delete {}",
+                    previous.completed(),
+                    previous.steps().len(),
+                    path
+                );
+                process::exit(1)
+            },
+            Err(err) => {
+                eprintln!("domain-sync: cannot read the journal {} of a previous run. {}", path, err);
+                process::exit(1)
+            }
+        }
+    }
+
+    let rollback = rollback_path(path);
+    if let Err(err) = fs::remove_file(&rollback) && err.kind() != ErrorKind::NotFound {
+        eprintln!("domain-sync: cannot remove the rollback journal {} of a previous run. {}", rollback, err);
+        process::exit(1)
+    }
+
+    let journal = create_parent(path).and_then(|()| Journal::create(path, steps));
+    match journal {
+        Ok(journal) => journal,
+        Err(err) => {
+            eprintln!("domain-sync: cannot create the journal {}. {}", path, err);
+            process::exit(1)
+        }
+    }
+}
+
+///
+/// Finish the changes recorded in the journal of a previous run: completed
+/// ones are not repeated, failed ones are attempted again
+///
+/// Returns:
+/// * `false` if any account failed
+///
+pub fn resume(backend: &dyn DirectoryBackend, path: &str, credentials: &str) -> bool {
+    let mut journal = open(path);
+
+    if journal.is_finished() {
+        println!("Nothing to resume: every change in {} has been made", path);
+        return true;
+    }
+
+    println!("Resuming {}: {} of {} changes already made", path, journal.completed(), journal.steps().len());
+    println!();
+
+    execute(backend, &mut journal, credentials).failed == 0
+}
+
+///
+/// Make the pending changes of the journal, account by account, and save
+/// the generated passwords of the accounts it created, then remove them
+/// from the journal
+///
+/// Returns:
+/// * the final counts, once printed
+///
+pub fn execute(backend: &dyn DirectoryBackend, journal: &mut Journal, credentials: &str) -> BatchSummary {
+    let summary = batch::run_import(backend, journal, 1, |report, progress| print_row(journal, report, progress));
+    let summary = match summary {
+        Ok(summary) => summary,
+        Err(err) => {
+            eprintln!("domain-sync: cannot record the progress in {}, stopping. {}", journal.path(), err);
+            process::exit(1)
+        }
+    };
+
+    // Generated passwords are useless unless someone can hand them out
+    println!();
+    let created = journal.generated_credentials();
+    if !created.is_empty() {
+        match save_credentials(credentials, &created) {
+            Ok(()) => println!("Generated passwords of {} users added to {}", created.len(), credentials),
+            Err(err) => {
+                eprintln!("domain-sync: cannot save generated passwords to {}. {}", credentials, err);
+                process::exit(1)
+            }
+        }
+    }
+
+    if let Err(err) = journal.scrub_passwords() {
+        eprintln!("domain-sync: cannot remove the saved passwords from the journal {}. {}", journal.path(), err);
+        process::exit(1)
+    }
+
+    if summary.failed > 0 {
+        println!("Failed changes are recorded in {}: run again with --resume to retry them, or --rollback to undo the run", journal.path());
+    }
+    println!(
+        "{} accounts: {} changed, {} changed by a previous run, {} failed",
+        summary.created + summary.previously_created + summary.failed,
+        summary.created,
+        summary.previously_created,
+        summary.failed
+    );
+
+    summary
+}
+
+///
+/// Open the journal of a previous run
+///
+pub fn open(path: &str) -> Journal {
+    match Journal::open(path) {
+        Ok(journal) => journal,
+        Err(err) => {
+            eprintln!("domain-sync: cannot read the journal {}. {}", path, err);
+            process::exit(1)
+        }
+    }
+}
+
+///
+/// Add the generated passwords to the credentials file, creating its
+/// directory on the first run
+///
+fn save_credentials(file_path: &str, users: &[&DomainUser]) -> fp_tools::error::Result<()> {
+    create_parent(file_path)?;
+    schema::write_credentials(file_path, users)
+}
+
+fn create_parent(file_path: &str) -> fp_tools::error::Result<()> {
+    if let Some(parent) = Path::new(file_path).parent() && !parent.as_os_str().is_empty() {
+        fs::create_dir_all(parent)?;
+    }
+
+    Ok(())
+}
+
+///
+/// Print the changes made for an account, or the reason they stopped
+///
+fn print_row(journal: &Journal, report: &RowReport, progress: Progress) {
+    let counter = format!("{:>width$}/{}", progress.done, progress.total, width = progress.total.to_string().len());
+
+    for index in &report.steps {
+        println!("{} [done]    {}", counter, journal.steps()[*index]);
+    }
+
+    match &report.outcome {
+        RowOutcome::Created => {},
+        RowOutcome::PreviouslyCreated => println!("{} [done]    {}: changed by a previous run", counter, report.username),
+        RowOutcome::Failed(err) => println!("{} [failed]  {}: {}", counter, report.username, err)
+    }
+}
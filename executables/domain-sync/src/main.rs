@@ -0,0 +1,301 @@
+mod journal;
+mod rollback;
+
+use std::process;
+use chrono::NaiveDateTime;
+use clap::{ArgAction, Parser, ValueEnum};
+use fp_tools::{backend, debug_println, tools};
+use fp_tools::batch::rollback::RollbackOptions;
+use fp_tools::commands::parse::names_equal;
+use fp_tools::config::{generator_config, tools_config};
+use fp_tools::db::rget_login::{
+    generate_connection_string, get_db_connection, get_login_data, get_login_data_since, get_student_groups,
+    latest_modification, parse_timestamp, MySQLDomainUser
+};
+use fp_tools::db::watermark::{self, Watermark};
+use fp_tools::password::PasswordGenerator;
+use fp_tools::sync::{self, OrphanPolicy, SyncOptions, SyncPlan};
+
+///
+/// Command line arguments
+///
+#[derive(Parser, Debug)]
+#[command(version, about = "Mirror the students of the school database into the domain", long_about = None)]
+struct Args {
+    /// Make the changes, instead of only listing them
+    #[arg(short, long, action = ArgAction::SetTrue)]
+    apply: bool,
+
+    /// What to do with accounts of the managed groups missing from the database
+    #[arg(short, long, value_enum, default_value_t = Orphans::Report)]
+    orphans: Orphans,
+
+    /// Allow --apply to delete the orphan accounts with --orphans delete
+    #[arg(long, action = ArgAction::SetTrue, requires = "apply")]
+    confirm_delete: bool,
+
+    /// File the generated passwords of the new accounts are added to, run after run
+    #[arg(short, long, default_value = "/var/lib/ad/domain-sync-credentials.csv")]
+    credentials: String,
+
+    /// Only push the students changed since the last successful incremental run
+    #[arg(short, long, action = ArgAction::SetTrue, conflicts_with = "since")]
+    incremental: bool,
+
+    /// Only push the students changed from this time on (YYYY-MM-DD[ HH:MM:SS])
+    #[arg(long, value_parser = parse_timestamp)]
+    since: Option<NaiveDateTime>,
+
+    /// File keeping the time of the last change pushed by an incremental run
+    #[arg(long, default_value = "/var/lib/ad/domain-sync.json")]
+    state: String,
+
+    /// File recording the changes of the last --apply run, to finish them
+    /// with --resume or undo them with --rollback
+    #[arg(long, default_value = "/var/lib/ad/domain-sync-journal.jsonl")]
+    journal: String,
+
+    /// Finish the changes of an interrupted or failed --apply run, without reading the database
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with_all = ["apply", "incremental", "since", "rollback"])]
+    resume: bool,
+
+    /// Undo the changes of the last --apply run: they are listed, and undone with --apply
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with_all = ["incremental", "since", "confirm_delete"])]
+    rollback: bool,
+
+    /// With --rollback, also add the users back to the groups the run removed them from
+    #[arg(long, action = ArgAction::SetTrue, requires = "rollback")]
+    restore_groups: bool
+}
+
+///
+/// Orphan policies selectable from the command line
+///
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Orphans {
+    /// Only list them
+    Report,
+
+    /// Remove them from the managed groups
+    Detach,
+
+    /// Delete their accounts, with --apply only if --confirm-delete is given
+    Delete
+}
+
+impl Orphans {
+    fn policy(&self) -> OrphanPolicy {
+        match self {
+            Orphans::Report => OrphanPolicy::Report,
+            Orphans::Detach => OrphanPolicy::Detach,
+            Orphans::Delete => OrphanPolicy::Delete
+        }
+    }
+}
+
+fn main() {
+    let cli = Args::parse();
+
+    // Every member of a managed group is a candidate, staff in a shared group included
+    if cli.apply && !cli.rollback && matches!(cli.orphans, Orphans::Delete) && !cli.confirm_delete {
+        eprintln!("domain-sync: --orphans delete removes every account of the managed groups missing from the database. Review the orphans without --apply, then add --confirm-delete");
+        process::exit(1);
+    }
+
+    if !tools::has_privileges() {
+        eprintln!("domain-sync: this program needs to be run in privileged mode!");
+        process::exit(1);
+    }
+
+    let config = match tools_config::read_config_from_file("/etc/ad/settings.json") {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("domain-sync: cannot load config file. {}", err);
+            process::exit(1)
+        }
+    };
+
+    let sql_config = match generator_config::read_config_from_file("/etc/ad/mysql_config.json") {
+        Ok(sql_config) => sql_config,
+        Err(err) => {
+            eprintln!("domain-sync: error loading MySQL configuration data. {}", err);
+            process::exit(1)
+        }
+    };
+
+    debug_println!("{:#?}", &config);
+    debug_println!("{:#?}", &sql_config);
+
+    let backend = match backend::from_config(&config) {
+        Ok(backend) => backend,
+        Err(err) => {
+            eprintln!("domain-sync: cannot set up directory backend. {}", err);
+            process::exit(1)
+        }
+    };
+
+    // Both work on the journal of the last run only
+    if cli.rollback {
+        let options = RollbackOptions { restore_groups: cli.restore_groups };
+        let undone = rollback::run(backend.as_ref(), &cli.journal, options, cli.apply);
+        process::exit(if undone { 0 } else { 1 })
+    }
+
+    if cli.resume {
+        let finished = journal::resume(backend.as_ref(), &cli.journal, &cli.credentials);
+        process::exit(if finished { 0 } else { 1 })
+    }
+
+    let mut mark = if cli.incremental {
+        match watermark::read_watermark(&cli.state) {
+            Ok(mark) => mark,
+            Err(err) => {
+                eprintln!("domain-sync: cannot read the last incremental run. {}", err);
+                process::exit(1)
+            }
+        }
+    } else {
+        Watermark::default()
+    };
+
+    let since = cli.since.or(mark.last_modified);
+
+    let passwords = match PasswordGenerator::new(sql_config.get_passwords()) {
+        Ok(passwords) => passwords,
+        Err(err) => {
+            eprintln!("domain-sync: invalid password settings. {}", err);
+            process::exit(1)
+        }
+    };
+
+    let mut connection = match get_db_connection(&generate_connection_string(&sql_config)) {
+        Ok(connection) => connection,
+        Err(err) => {
+            eprintln!("domain-sync: cannot connect to the database. {}", err);
+            process::exit(1)
+        }
+    };
+
+    let options = SyncOptions { orphans: cli.orphans.policy(), passwords };
+
+    // Authoritative student list, or the students changed since the last run
+    let (students, plan) = match &since {
+        Some(since) => {
+            let changed = get_login_data_since(&mut connection, since).and_then(|changed| {
+                let managed = get_student_groups(&mut connection)?;
+                Ok((changed, managed))
+            });
+
+            match changed {
+                Ok((changed, managed)) => {
+                    println!("Students changed since {} (orphans are only checked by full runs)", since);
+                    let plan = sync::compute_changes(backend.as_ref(), &changed, &managed, &options);
+                    (changed, plan)
+                },
+                Err(err) => {
+                    eprintln!("domain-sync: cannot load students from the database. {}", err);
+                    process::exit(1)
+                }
+            }
+        },
+        None => match get_login_data(&mut connection) {
+            Ok(students) => {
+                let plan = sync::compute(backend.as_ref(), &students, &options);
+                (students, plan)
+            },
+            Err(err) => {
+                eprintln!("domain-sync: cannot load students from the database. {}", err);
+                process::exit(1)
+            }
+        }
+    };
+
+    let plan = match plan {
+        Ok(plan) => plan,
+        Err(err) => {
+            eprintln!("domain-sync: cannot compare the database with the domain. {}", err);
+            process::exit(1)
+        }
+    };
+
+    print_plan(&plan, students.len());
+
+    if plan.is_empty() {
+        if cli.incremental {
+            save_watermark(&cli.state, &mut mark, &students, &plan);
+        }
+        return;
+    }
+
+    if !cli.apply {
+        println!("Run again with --apply to make these changes.");
+        return;
+    }
+
+    println!();
+    let mut journal = journal::start(&cli.journal, plan.steps.clone());
+    let summary = journal::execute(backend.as_ref(), &mut journal, &cli.credentials);
+
+    if summary.failed > 0 {
+        process::exit(1)
+    }
+
+    if cli.incremental {
+        save_watermark(&cli.state, &mut mark, &students, &plan);
+    }
+}
+
+///
+/// Record that the changes of `students` have been pushed, so that the
+/// next incremental run starts from the latest of them. Students left out
+/// of the plan have not been pushed: the mark stops at the earliest of
+/// them, so that the next run tries them again.
+///
+fn save_watermark(file_path: &str, mark: &mut Watermark, students: &[MySQLDomainUser], plan: &SyncPlan) {
+    let left_out = students
+        .iter()
+        .filter(|student| plan.problems.iter().any(|problem| names_equal(&problem.login, student.login.trim())))
+        .filter_map(|student| student.date_modified)
+        .min();
+
+    mark.advance(left_out.or_else(|| latest_modification(students)));
+
+    if let Err(err) = watermark::write_watermark(file_path, mark) {
+        eprintln!("domain-sync: cannot save the time of this run to {}. {}", file_path, err);
+        process::exit(1)
+    }
+
+    if let Some(last_modified) = &mark.last_modified {
+        println!("Next incremental run starts from {}", last_modified);
+    }
+}
+
+///
+/// Print the differences found between database and domain
+///
+fn print_plan(plan: &SyncPlan, students: usize) {
+    for problem in &plan.problems {
+        println!("[problem] {}", problem);
+    }
+
+    for orphan in &plan.orphans {
+        println!("[orphan]  {}: not in the database", orphan);
+    }
+
+    if !plan.problems.is_empty() || !plan.orphans.is_empty() {
+        println!();
+    }
+
+    println!(
+        "{} students: {} up to date, {} left out, {} orphan accounts, {} changes",
+        students,
+        plan.unchanged,
+        plan.problems.len(),
+        plan.orphans.len(),
+        plan.steps.len()
+    );
+
+    for (index, step) in plan.steps.iter().enumerate() {
+        println!("{:>5}. {}", index + 1, step);
+    }
+}
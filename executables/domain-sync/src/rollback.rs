@@ -0,0 +1,121 @@
+use std::path::Path;
+use std::process;
+use fp_tools::backend::DirectoryBackend;
+use fp_tools::batch::journal::{Journal, Step};
+use fp_tools::batch::rollback::{self, RollbackOptions, UndoOutcome, UndoReport};
+use crate::journal;
+
+///
+/// Undo the changes recorded in the journal of the last run. Without
+/// `apply` the changes undoing it are only listed. The rollback has a
+/// journal of its own: if it is interrupted, running it again finishes it.
+///
+/// Arguments:
+/// * `backend`: directory backend
+/// * `path`: journal of the run to undo
+/// * `options`: rollback options
+/// * `apply`: make the changes, instead of only listing them
+///
+/// Returns:
+/// * `false` if any change could not be undone
+///
+pub fn run(backend: &dyn DirectoryBackend, path: &str, options: RollbackOptions, apply: bool) -> bool {
+    let rollback_path = journal::rollback_path(path);
+    let resuming = Path::new(&rollback_path).exists();
+
+    let steps = if resuming {
+        let previous = journal::open(&rollback_path);
+        if previous.is_finished() {
+            println!("Nothing to undo: the rollback recorded in {} has been completed", rollback_path);
+            return true;
+        }
+
+        println!(
+            "Resuming the rollback recorded in {}: {} of {} changes already undone",
+            rollback_path,
+            previous.completed(),
+            previous.steps().len()
+        );
+        previous.steps().to_vec()
+    } else {
+        let run = journal::open(path);
+        let plan = match rollback::plan_rollback(backend, &run, options) {
+            Ok(plan) => plan,
+            Err(err) => {
+                eprintln!("domain-sync: cannot compare the journal with the domain. {}", err);
+                process::exit(1)
+            }
+        };
+
+        for step in &plan.kept {
+            println!("[kept]    {}{}", step, kept_reason(step));
+        }
+
+        if plan.steps.is_empty() {
+            println!("Nothing to undo in {}", path);
+            return true;
+        }
+
+        println!("Rolling back {}: {} changes to undo", path, plan.steps.len());
+        plan.steps
+    };
+
+    for (index, step) in steps.iter().enumerate() {
+        println!("{:>5}. {}", index + 1, step);
+    }
+
+    if !apply {
+        println!("Run again with --rollback --apply to undo these changes.");
+        return true;
+    }
+    println!();
+
+    let journal = if resuming {
+        journal::open(&rollback_path)
+    } else {
+        match Journal::create(&rollback_path, steps) {
+            Ok(journal) => journal,
+            Err(err) => {
+                eprintln!("domain-sync: cannot create the journal {}. {}", rollback_path, err);
+                process::exit(1)
+            }
+        }
+    };
+
+    let summary = match rollback::run_rollback(backend, &journal, print_step) {
+        Ok(summary) => summary,
+        Err(err) => {
+            eprintln!("domain-sync: cannot record the progress in {}, stopping. {}", rollback_path, err);
+            process::exit(1)
+        }
+    };
+
+    println!();
+    if summary.failed > 0 {
+        println!("Run --rollback --apply again to retry the changes that could not be undone");
+    }
+    println!(
+        "{} changes: {} undone, {} undone by a previous run, {} failed",
+        journal.steps().len(),
+        summary.undone,
+        summary.previously_undone,
+        summary.failed
+    );
+
+    summary.failed == 0
+}
+
+fn kept_reason(step: &Step) -> &'static str {
+    match step {
+        Step::RemoveMember { .. } => " (use --restore-groups to undo)",
+        _ => " (cannot be undone)"
+    }
+}
+
+fn print_step(report: &UndoReport) {
+    match &report.outcome {
+        UndoOutcome::Undone => println!("[undone]  {}", report.step),
+        UndoOutcome::PreviouslyUndone => println!("[done]    {}: undone by a previous run", report.step),
+        UndoOutcome::Failed(err) => println!("[failed]  {}: {}", report.step, err)
+    }
+}
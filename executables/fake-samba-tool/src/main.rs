@@ -0,0 +1,608 @@
+mod state;
+
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, BufRead};
+use std::process;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use state::{key, DomainState, FakeGroup, FakeUser, StateFile, DOMAIN_USERS};
+
+///
+/// Environment variable with the path of the JSON state file
+///
+const STATE_ENV: &str = "FAKE_SAMBA_TOOL_STATE";
+
+///
+/// State file used when the environment variable is not set
+///
+const DEFAULT_STATE_PATH: &str = "fake-samba-tool.json";
+
+///
+/// Exit status of samba-tool when a command fails
+///
+const EXIT_FAILURE: i32 = 255;
+
+///
+/// Exit status of samba-tool on command line errors
+///
+const EXIT_USAGE: i32 = 2;
+
+///
+/// Parsed command line of a subcommand: positional arguments and `--key=value` options
+///
+struct Invocation {
+    args: Vec<String>,
+    options: HashMap<String, Option<String>>
+}
+
+impl Invocation {
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).and_then(|value| value.as_deref())
+    }
+
+    fn arg(&self, index: usize, what: &str) -> Result<&str, Failure> {
+        self.args
+            .get(index)
+            .map(String::as_str)
+            .ok_or_else(|| Failure::usage(format!("missing argument: {}", what)))
+    }
+}
+
+///
+/// Reason why the simulated command failed
+///
+struct Failure {
+    message: String,
+    status: i32
+}
+
+impl Failure {
+    fn error(message: String) -> Failure {
+        Failure { message, status: EXIT_FAILURE }
+    }
+
+    fn usage(message: String) -> Failure {
+        Failure { message: format!("samba-tool: error: {}", message), status: EXIT_USAGE }
+    }
+}
+
+fn main() {
+    let argv: Vec<String> = env::args().skip(1).collect();
+    let path = env::var(STATE_ENV).unwrap_or_else(|_| DEFAULT_STATE_PATH.to_string());
+
+    let mut file = match StateFile::open(&path) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("ERROR: cannot open state file {}: {}", path, err);
+            process::exit(EXIT_FAILURE)
+        }
+    };
+
+    let mut state = match file.load() {
+        Ok(state) => state,
+        Err(err) => {
+            eprintln!("ERROR: cannot read state file {}: {}", path, err);
+            process::exit(EXIT_FAILURE)
+        }
+    };
+
+    match run(&mut state, &argv) {
+        Ok((output, modified)) => {
+            if modified && let Err(err) = file.save(&state) {
+                eprintln!("ERROR: cannot write state file {}: {}", path, err);
+                process::exit(EXIT_FAILURE)
+            }
+
+            for line in output {
+                println!("{}", line);
+            }
+        },
+        Err(failure) => {
+            eprintln!("{}", failure.message);
+            process::exit(failure.status)
+        }
+    }
+}
+
+///
+/// Dispatch a samba-tool command line
+///
+/// Returns:
+/// * the lines to print and whether the state must be saved
+///
+fn run(state: &mut DomainState, argv: &[String]) -> Result<(Vec<String>, bool), Failure> {
+    let (Some(object), Some(action)) = (argv.first(), argv.get(1)) else {
+        return Err(Failure::usage("expected '<user|group|domain> <subcommand>'".to_string()));
+    };
+
+    let inv = parse_args(&argv[2..])?;
+
+    match (object.as_str(), action.as_str()) {
+        ("user", "create") | ("user", "add") => user_create(state, &inv).map(modified),
+        ("user", "list") => Ok(unmodified(state.users.values().map(|u| u.name.clone()).collect())),
+        ("user", "show") => user_show(state, &inv).map(unmodified),
+        ("user", "rename") => user_rename(state, &inv).map(modified),
+        ("user", "setpassword") => user_setpassword(state, &inv).map(modified),
+        ("user", "getgroups") => user_getgroups(state, &inv).map(unmodified),
+        ("user", "delete") => user_delete(state, &inv).map(modified),
+        ("group", "add") | ("group", "create") => group_add(state, &inv).map(modified),
+        ("group", "list") => Ok(unmodified(state.groups.values().map(|g| g.name.clone()).collect())),
+        ("group", "delete") => group_delete(state, &inv).map(modified),
+        ("group", "addmembers") => group_members(state, &inv, true).map(modified),
+        ("group", "removemembers") => group_members(state, &inv, false).map(modified),
+        ("group", "listmembers") => group_listmembers(state, &inv).map(unmodified),
+        ("domain", "passwordsettings") => passwordsettings(state, &inv).map(unmodified),
+        _ => Err(Failure::usage(format!("unsupported command: {} {}", object, action)))
+    }
+}
+
+fn modified(output: Vec<String>) -> (Vec<String>, bool) {
+    (output, true)
+}
+
+fn unmodified(output: Vec<String>) -> (Vec<String>, bool) {
+    (output, false)
+}
+
+///
+/// Split arguments into positionals and options, the way optparse does
+///
+fn parse_args(args: &[String]) -> Result<Invocation, Failure> {
+    const KNOWN: &[&str] = &[
+        "--use-username-as-cn", "--random-password", "--surname", "--given-name",
+        "--home-drive", "--home-directory", "--profile-path", "--newpassword",
+        "--must-change-at-next-login", "--description", "-H"
+    ];
+
+    let mut inv = Invocation { args: Vec::new(), options: HashMap::new() };
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with('-') {
+            inv.args.push(arg.clone());
+            continue;
+        }
+
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (arg.as_str(), None)
+        };
+
+        if !KNOWN.contains(&name) {
+            return Err(Failure::usage(format!("no such option: {}", name)));
+        }
+
+        // -H takes the database URL as a separate argument, which we ignore
+        let value = if name == "-H" { iter.next().cloned() } else { value };
+        inv.options.insert(name.to_string(), value);
+    }
+
+    Ok(inv)
+}
+
+fn passwordsettings(state: &DomainState, inv: &Invocation) -> Result<Vec<String>, Failure> {
+    let action = inv.arg(0, "subcommand")?;
+    if action != "show" {
+        return Err(Failure::usage(format!("unsupported command: domain passwordsettings {}", action)));
+    }
+
+    let settings = &state.password_settings;
+    let on_off = |flag: bool| if flag { "on" } else { "off" };
+
+    Ok(vec![
+        format!("Password information for domain '{}'", state.base_dn),
+        String::new(),
+        format!("Password complexity: {}", on_off(settings.complexity)),
+        "Store plaintext passwords: off".to_string(),
+        format!("Password history length: {}", settings.history_length),
+        format!("Minimum password length: {}", settings.min_length),
+        "Minimum password age (days): 1".to_string(),
+        "Maximum password age (days): 42".to_string()
+    ])
+}
+
+fn user_create(state: &mut DomainState, inv: &Invocation) -> Result<Vec<String>, Failure> {
+    let name = inv.arg(0, "username")?;
+    let failed = |reason: String| Failure::error(format!("ERROR(ldb): Failed to add user '{}':  - {}", name, reason));
+
+    let password = match (inv.args.get(1), inv.options.contains_key("--random-password")) {
+        (Some(password), _) => password.clone(),
+        (None, true) => "Random-Passw0rd!".to_string(),
+        (None, false) => read_password()?
+    };
+
+    if state.user(name).is_some() || state.group(name).is_some() {
+        return Err(failed(format!(
+            "LDAP error 68 LDAP_ENTRY_ALREADY_EXISTS -  <00002071: samldb: Account name (sAMAccountName) '{}' already in use!>",
+            name
+        )));
+    }
+
+    check_password(state, name, &password).map_err(failed)?;
+
+    state.users.insert(key(name), FakeUser {
+        name: name.to_string(),
+        given_name: inv.option("--given-name").map(str::to_string),
+        surname: inv.option("--surname").map(str::to_string),
+        home_drive: inv.option("--home-drive").map(str::to_string),
+        home_directory: inv.option("--home-directory").map(str::to_string),
+        profile_path: inv.option("--profile-path").map(str::to_string),
+        password
+    });
+
+    Ok(vec![format!("User '{}' added successfully", name)])
+}
+
+fn user_show(state: &DomainState, inv: &Invocation) -> Result<Vec<String>, Failure> {
+    let name = inv.arg(0, "username")?;
+    let user = find_user(state, name)?;
+
+    let mut attrs: Vec<(&str, String)> = vec![
+        ("objectClass", "top".to_string()),
+        ("objectClass", "person".to_string()),
+        ("objectClass", "organizationalPerson".to_string()),
+        ("objectClass", "user".to_string()),
+        ("cn", user.name.clone())
+    ];
+
+    let optional = [
+        ("sn", &user.surname),
+        ("givenName", &user.given_name),
+        ("homeDrive", &user.home_drive),
+        ("homeDirectory", &user.home_directory),
+        ("profilePath", &user.profile_path)
+    ];
+    for (attr, value) in optional {
+        if let Some(value) = value {
+            attrs.push((attr, value.clone()));
+        }
+    }
+
+    attrs.push(("sAMAccountName", user.name.clone()));
+    for group in state.groups_of(name) {
+        attrs.push(("memberOf", state.dn(&group.name)));
+    }
+
+    let mut lines = vec![ldif_line("dn", &state.dn(&user.name))];
+    lines.extend(attrs.iter().map(|(attr, value)| ldif_line(attr, value)));
+    Ok(lines)
+}
+
+fn user_rename(state: &mut DomainState, inv: &Invocation) -> Result<Vec<String>, Failure> {
+    let name = inv.arg(0, "username")?;
+    find_user(state, name)?;
+
+    let surname = inv.option("--surname").map(str::to_string);
+    let given_name = inv.option("--given-name").map(str::to_string);
+
+    if let Some(user) = state.user_mut(name) {
+        if surname.is_some() {
+            user.surname = surname;
+        }
+        if given_name.is_some() {
+            user.given_name = given_name;
+        }
+    }
+
+    Ok(vec![format!("User '{}' renamed successfully", name)])
+}
+
+fn user_setpassword(state: &mut DomainState, inv: &Invocation) -> Result<Vec<String>, Failure> {
+    let name = inv.arg(0, "username")?;
+    let password = match inv.option("--newpassword") {
+        Some(password) => password.to_string(),
+        None => read_password()?
+    };
+    let password = password.as_str();
+
+    find_user(state, name)?;
+    check_password(state, name, password).map_err(|reason| {
+        Failure::error(format!("ERROR: Failed to set password for user '{}': {}", name, reason))
+    })?;
+
+    if let Some(user) = state.user_mut(name) {
+        user.password = password.to_string();
+    }
+
+    Ok(vec!["Changed password OK".to_string()])
+}
+
+///
+/// Answer the "New Password" and "Retype Password" prompts from the
+/// standard input, as samba-tool does when it has no terminal
+///
+fn read_password() -> Result<String, Failure> {
+    let mut lines = io::stdin().lock().lines();
+    let mut prompt = |label: &str| {
+        eprint!("{}", label);
+        match lines.next() {
+            Some(Ok(line)) => Ok(line),
+            _ => Err(Failure::error("ERROR: a password is required".to_string()))
+        }
+    };
+
+    let password = prompt("New Password: ")?;
+    let verify = prompt("Retype Password: ")?;
+    eprintln!();
+
+    if password != verify {
+        return Err(Failure::error("Sorry, passwords do not match.".to_string()));
+    }
+    if password.is_empty() {
+        return Err(Failure::error("ERROR: a password is required".to_string()));
+    }
+
+    Ok(password)
+}
+
+fn user_getgroups(state: &DomainState, inv: &Invocation) -> Result<Vec<String>, Failure> {
+    let name = inv.arg(0, "username")?;
+    find_user(state, name)?;
+
+    let mut groups = vec![DOMAIN_USERS.to_string()];
+    groups.extend(state.groups_of(name).into_iter().map(|group| group.name.clone()));
+    Ok(groups)
+}
+
+fn user_delete(state: &mut DomainState, inv: &Invocation) -> Result<Vec<String>, Failure> {
+    let name = inv.arg(0, "username")?;
+    let user = find_user(state, name)?.name.clone();
+
+    state.users.remove(&key(name));
+    for group in state.groups.values_mut() {
+        group.members.retain(|member| key(member) != key(name));
+    }
+
+    Ok(vec![format!("Deleted user {}", user)])
+}
+
+fn group_add(state: &mut DomainState, inv: &Invocation) -> Result<Vec<String>, Failure> {
+    let name = inv.arg(0, "groupname")?;
+
+    if state.group(name).is_some() || state.user(name).is_some() {
+        return Err(Failure::error(format!(
+            "ERROR(ldb): Failed to create group \"{}\" - LDAP error 68 LDAP_ENTRY_ALREADY_EXISTS -  <00002071: samldb: Account name (sAMAccountName) '{}' already in use!>",
+            name, name
+        )));
+    }
+
+    state.groups.insert(key(name), FakeGroup { name: name.to_string(), members: Vec::new() });
+    Ok(vec![format!("Added group {}", name)])
+}
+
+fn group_delete(state: &mut DomainState, inv: &Invocation) -> Result<Vec<String>, Failure> {
+    let name = inv.arg(0, "groupname")?;
+    let group = find_group(state, name)?.name.clone();
+
+    state.groups.remove(&key(name));
+    Ok(vec![format!("Deleted group {}", group)])
+}
+
+fn group_members(state: &mut DomainState, inv: &Invocation, add: bool) -> Result<Vec<String>, Failure> {
+    let name = inv.arg(0, "groupname")?;
+    let members: Vec<String> = inv.arg(1, "listofmembers")?
+        .split(',')
+        .map(|member| member.trim().to_string())
+        .filter(|member| !member.is_empty())
+        .collect();
+
+    let action = if add { "add" } else { "remove" };
+    let listed = members.join(",");
+    let failed = |reason: String| Failure::error(format!(
+        "ERROR(exception): Failed to {} members \"{}\" to group \"{}\" - {}", action, listed, name, reason
+    ));
+
+    find_group(state, name).map_err(|f| failed(f.message))?;
+    for member in &members {
+        if state.user(member).is_none() && state.group(member).is_none() {
+            return Err(failed(format!("Unable to find \"{}\". Operation cancelled.", member)));
+        }
+    }
+
+    let group = state.group_mut(name).expect("group checked above");
+    for member in members {
+        let present = group.members.iter().any(|m| key(m) == key(&member));
+
+        match (add, present) {
+            (true, true) => return Err(failed(format!(
+                "LDAP error 68 LDAP_ENTRY_ALREADY_EXISTS - member {} already exists", member
+            ))),
+            (true, false) => group.members.push(member),
+            (false, true) => group.members.retain(|m| key(m) != key(&member)),
+            (false, false) => return Err(failed(format!(
+                "LDAP error 16 LDAP_NO_SUCH_ATTRIBUTE - member {} is not in the group", member
+            )))
+        }
+    }
+
+    let verb = if add { "Added members to" } else { "Removed members from" };
+    Ok(vec![format!("{} group {}", verb, name)])
+}
+
+fn group_listmembers(state: &DomainState, inv: &Invocation) -> Result<Vec<String>, Failure> {
+    let name = inv.arg(0, "groupname")?;
+    Ok(find_group(state, name)?.members.clone())
+}
+
+fn find_user<'a>(state: &'a DomainState, name: &str) -> Result<&'a FakeUser, Failure> {
+    state.user(name).ok_or_else(|| Failure::error(format!("ERROR: Unable to find user \"{}\"", name)))
+}
+
+fn find_group<'a>(state: &'a DomainState, name: &str) -> Result<&'a FakeGroup, Failure> {
+    state.group(name).ok_or_else(|| Failure::error(format!("ERROR: Unable to find group \"{}\"", name)))
+}
+
+///
+/// Enforce the simulated domain password settings
+///
+fn check_password(state: &DomainState, username: &str, password: &str) -> Result<(), String> {
+    let settings = &state.password_settings;
+    let prefix = "0000052D: Constraint violation - check_password_restrictions";
+
+    if password.chars().count() < settings.min_length {
+        return Err(format!(
+            "{}: the password is too short. It should be equal or longer than {} characters!",
+            prefix, settings.min_length
+        ));
+    }
+
+    if settings.complexity {
+        let categories = [
+            password.chars().any(|c| c.is_uppercase()),
+            password.chars().any(|c| c.is_lowercase()),
+            password.chars().any(|c| c.is_ascii_digit()),
+            password.chars().any(|c| !c.is_alphanumeric())
+        ];
+        let contains_name = username.chars().count() >= 3
+            && password.to_lowercase().contains(&username.to_lowercase());
+
+        if categories.iter().filter(|&&present| present).count() < 3 || contains_name {
+            return Err(format!("{}: the password does not meet the complexity criteria!", prefix));
+        }
+    }
+
+    Ok(())
+}
+
+///
+/// Format an LDIF attribute, base64 encoding values that are not plain ASCII
+///
+fn ldif_line(attr: &str, value: &str) -> String {
+    let safe = value.chars().all(|c| c.is_ascii() && !c.is_ascii_control())
+        && !value.starts_with([' ', ':', '<']);
+
+    if safe {
+        format!("{}: {}", attr, value)
+    } else {
+        format!("{}:: {}", attr, STANDARD.encode(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::PasswordSettings;
+
+    fn args(line: &[&str]) -> Vec<String> {
+        line.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn domain() -> DomainState {
+        let mut state = DomainState::default();
+        run(&mut state, &args(&["user", "create", "mario.rossi", "Passw0rd!"])).ok().unwrap();
+        run(&mut state, &args(&["group", "add", "3B"])).ok().unwrap();
+        state
+    }
+
+    fn failure(result: Result<(Vec<String>, bool), Failure>) -> Failure {
+        match result {
+            Ok((output, _)) => panic!("command succeeded: {:?}", output),
+            Err(failure) => failure
+        }
+    }
+
+    #[test]
+    fn options_and_arguments_are_split() {
+        let inv = parse_args(&args(&[
+            "mario.rossi", "--surname=Rossi", "--use-username-as-cn", "-H", "ldap://dc1", "--given-name=Mario"
+        ])).ok().unwrap();
+
+        assert_eq!(inv.args, ["mario.rossi"]);
+        assert_eq!(inv.option("--surname"), Some("Rossi"));
+        assert_eq!(inv.option("--given-name"), Some("Mario"));
+        assert_eq!(inv.option("-H"), Some("ldap://dc1"));
+        assert!(inv.options.contains_key("--use-username-as-cn"));
+        assert_eq!(inv.option("--use-username-as-cn"), None);
+
+        // The value after = is kept whole
+        let inv = parse_args(&args(&["--description=a=b"])).ok().unwrap();
+        assert_eq!(inv.option("--description"), Some("a=b"));
+    }
+
+    #[test]
+    fn unknown_options_are_usage_errors() {
+        for line in [&["--surnam=Rossi"][..], &["-x"], &["--password=secret"]] {
+            let failure = parse_args(&args(line)).err().unwrap();
+            assert_eq!(failure.status, EXIT_USAGE);
+            assert!(failure.message.starts_with("samba-tool: error: no such option: -"), "{}", failure.message);
+        }
+    }
+
+    #[test]
+    fn missing_arguments_are_usage_errors() {
+        let mut state = domain();
+
+        for line in [&["user"][..], &["user", "show"], &["group", "addmembers", "3B"], &["user", "frobnicate", "x"]] {
+            let failure = failure(run(&mut state, &args(line)));
+            assert_eq!(failure.status, EXIT_USAGE, "{:?}", line);
+            assert!(failure.message.starts_with("samba-tool: error: "), "{}", failure.message);
+        }
+    }
+
+    #[test]
+    fn passwords_follow_the_domain_settings() {
+        let state = DomainState::default();
+        let rejected = |password: &str| check_password(&state, "mario.rossi", password).err().unwrap();
+
+        assert!(check_password(&state, "mario.rossi", "Passw0rd!").is_ok());
+        assert!(check_password(&state, "mario.rossi", "Passw0rd").is_ok());
+        assert!(rejected("Pa0!").contains("too short. It should be equal or longer than 7 characters!"));
+        assert!(rejected("password").contains("does not meet the complexity criteria"));
+        assert!(rejected("password!").contains("does not meet the complexity criteria"));
+        assert!(rejected("X-Mario.Rossi-1").contains("does not meet the complexity criteria"));
+
+        for password in ["Pa0!", "password", "X-Mario.Rossi-1"] {
+            assert!(rejected(password).starts_with("0000052D: Constraint violation - check_password_restrictions: "));
+        }
+
+        // Short account names may appear in the password
+        assert!(check_password(&state, "ab", "Xab-1234").is_ok());
+
+        let state = DomainState {
+            password_settings: PasswordSettings { complexity: false, min_length: 4, history_length: 0 },
+            ..DomainState::default()
+        };
+        assert!(check_password(&state, "mario.rossi", "mario.rossi").is_ok());
+        assert!(check_password(&state, "mario.rossi", "abc").is_err());
+    }
+
+    #[test]
+    fn weak_passwords_leave_the_domain_unchanged() {
+        let mut state = domain();
+
+        let failure = failure(run(&mut state, &args(&["user", "create", "anna.bianchi", "short"])));
+        assert_eq!(failure.status, EXIT_FAILURE);
+        assert!(failure.message.starts_with("ERROR(ldb): Failed to add user 'anna.bianchi':  - 0000052D:"), "{}", failure.message);
+        assert!(state.user("anna.bianchi").is_none());
+    }
+
+    #[test]
+    fn membership_errors() {
+        let mut state = domain();
+        let mut members = |line: &[&str]| failure(run(&mut state, &args(line))).message;
+
+        assert_eq!(
+            members(&["group", "addmembers", "4C", "mario.rossi"]),
+            "ERROR(exception): Failed to add members \"mario.rossi\" to group \"4C\" - ERROR: Unable to find group \"4C\""
+        );
+        assert_eq!(
+            members(&["group", "addmembers", "3B", "mario.rossi,nobody"]),
+            "ERROR(exception): Failed to add members \"mario.rossi,nobody\" to group \"3B\" - Unable to find \"nobody\". Operation cancelled."
+        );
+        assert!(members(&["group", "removemembers", "3B", "mario.rossi"]).ends_with(
+            "LDAP error 16 LDAP_NO_SUCH_ATTRIBUTE - member mario.rossi is not in the group"
+        ));
+
+        run(&mut state, &args(&["group", "addmembers", "3B", "Mario.Rossi"])).ok().unwrap();
+        let message = failure(run(&mut state, &args(&["group", "addmembers", "3B", "mario.rossi"]))).message;
+        assert!(message.ends_with("LDAP error 68 LDAP_ENTRY_ALREADY_EXISTS - member mario.rossi already exists"), "{}", message);
+
+        // A cancelled operation adds none of the listed members
+        failure(run(&mut state, &args(&["group", "addmembers", "Domain Admins", "mario.rossi,nobody"])));
+        assert!(state.group("Domain Admins").unwrap().members.is_empty());
+
+        run(&mut state, &args(&["group", "removemembers", "3b", "MARIO.ROSSI"])).ok().unwrap();
+        assert!(state.group("3B").unwrap().members.is_empty());
+    }
+}
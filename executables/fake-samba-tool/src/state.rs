@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use serde::{Deserialize, Serialize};
+
+///
+/// Default naming context of the simulated domain
+///
+const DEFAULT_BASE_DN: &str = "DC=samdom,DC=example,DC=com";
+
+///
+/// Built-in group every user belongs to (primary group)
+///
+pub const DOMAIN_USERS: &str = "Domain Users";
+
+///
+/// Simulated domain user
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FakeUser {
+    pub name: String,
+    pub given_name: Option<String>,
+    pub surname: Option<String>,
+    pub home_drive: Option<String>,
+    pub home_directory: Option<String>,
+    pub profile_path: Option<String>,
+    pub password: String
+}
+
+///
+/// Simulated domain group
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FakeGroup {
+    pub name: String,
+    pub members: Vec<String>
+}
+
+///
+/// Password settings enforced on `user create` and `user setpassword`
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PasswordSettings {
+    pub complexity: bool,
+    pub min_length: usize,
+    pub history_length: usize
+}
+
+impl Default for PasswordSettings {
+    fn default() -> PasswordSettings {
+        // Samba defaults for a freshly provisioned domain
+        PasswordSettings {
+            complexity: true,
+            min_length: 7,
+            history_length: 24
+        }
+    }
+}
+
+///
+/// Whole simulated domain, persisted as JSON between invocations.
+/// Maps are keyed by the lowercase account name, as AD names are
+/// case-insensitive.
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DomainState {
+    pub base_dn: String,
+    #[serde(default)]
+    pub password_settings: PasswordSettings,
+    pub users: BTreeMap<String, FakeUser>,
+    pub groups: BTreeMap<String, FakeGroup>
+}
+
+impl Default for DomainState {
+    fn default() -> DomainState {
+        let mut groups = BTreeMap::new();
+        for name in [DOMAIN_USERS, "Domain Admins"] {
+            groups.insert(key(name), FakeGroup { name: name.to_string(), members: Vec::new() });
+        }
+
+        DomainState {
+            base_dn: DEFAULT_BASE_DN.to_string(),
+            password_settings: PasswordSettings::default(),
+            users: BTreeMap::new(),
+            groups
+        }
+    }
+}
+
+impl DomainState {
+    pub fn user(&self, name: &str) -> Option<&FakeUser> {
+        self.users.get(&key(name))
+    }
+
+    pub fn user_mut(&mut self, name: &str) -> Option<&mut FakeUser> {
+        self.users.get_mut(&key(name))
+    }
+
+    pub fn group(&self, name: &str) -> Option<&FakeGroup> {
+        self.groups.get(&key(name))
+    }
+
+    pub fn group_mut(&mut self, name: &str) -> Option<&mut FakeGroup> {
+        self.groups.get_mut(&key(name))
+    }
+
+    ///
+    /// Distinguished name of an object in the users container
+    ///
+    pub fn dn(&self, name: &str) -> String {
+        format!("CN={},CN=Users,{}", name, self.base_dn)
+    }
+
+    ///
+    /// Groups a user is explicitly member of (primary group excluded)
+    ///
+    pub fn groups_of(&self, username: &str) -> Vec<&FakeGroup> {
+        self.groups
+            .values()
+            .filter(|group| group.members.iter().any(|member| key(member) == key(username)))
+            .collect()
+    }
+}
+
+///
+/// Map key for an account name
+///
+pub fn key(name: &str) -> String {
+    name.to_lowercase()
+}
+
+///
+/// State file opened and locked for the whole run, so that concurrent
+/// invocations do not overwrite each other's changes
+///
+pub struct StateFile {
+    file: File
+}
+
+impl StateFile {
+    pub fn open(path: &str) -> io::Result<StateFile> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        file.lock()?;
+        Ok(StateFile { file })
+    }
+
+    pub fn load(&mut self) -> io::Result<DomainState> {
+        let mut content = String::new();
+        self.file.read_to_string(&mut content)?;
+
+        if content.trim().is_empty() {
+            return Ok(DomainState::default());
+        }
+
+        serde_json::from_str(&content).map_err(io::Error::other)
+    }
+
+    pub fn save(&mut self, state: &DomainState) -> io::Result<()> {
+        let content = serde_json::to_string_pretty(state).map_err(io::Error::other)?;
+
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(content.as_bytes())?;
+        self.file.sync_all()
+    }
+}
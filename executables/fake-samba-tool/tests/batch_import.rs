@@ -0,0 +1,166 @@
+//!
+//! Batch imports run by `domain-adduser`, against the fake samba-tool
+//! binary instead of a domain controller
+//!
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process;
+use fp_tools::backend::DirectoryBackend;
+use fp_tools::backend::samba_tool::SambaToolBackend;
+use fp_tools::batch::journal::{Journal, StepState};
+use fp_tools::batch::{self, BatchSummary, RowOutcome};
+use fp_tools::config::tools_config::{self, ImportProfile, ToolsConfiguration};
+use fp_tools::error::Error;
+use fp_tools::tools::import::{self, ImportOptions};
+use serde_json::Value;
+
+///
+/// Scratch directory with a settings file whose samba-tool is the fake
+/// one, working on its own state file
+///
+struct FakeDomain {
+    dir: PathBuf
+}
+
+impl FakeDomain {
+    fn new(name: &str) -> FakeDomain {
+        let dir = std::env::temp_dir().join(format!("fake-samba-tool-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let domain = FakeDomain { dir };
+
+        // The runner starts samba-tool without a custom environment, so the
+        // state file is handed over by a wrapper script
+        let wrapper = domain.path("samba-tool");
+        fs::write(&wrapper, format!(
+            "#!/bin/sh\nFAKE_SAMBA_TOOL_STATE='{}' exec '{}' \"$@\"\n",
+            domain.path("state.json"),
+            env!("CARGO_BIN_EXE_fake-samba-tool")
+        )).unwrap();
+        fs::set_permissions(&wrapper, fs::Permissions::from_mode(0o755)).unwrap();
+
+        fs::write(domain.path("settings.json"), format!(r#"{{
+            "sambaPath": "{}",
+            "srvName": "dc1",
+            "homeDirsPath": "/home",
+            "homeDirsShare": "homes",
+            "domainFqdn": "samdom.example.com",
+            "ntDomainName": "SAMDOM",
+            "poolPath": "/pool",
+            "poolShare": "pool",
+            "poolOwner": "root",
+            "winbindSeparator": "\\"
+        }}"#, wrapper)).unwrap();
+
+        domain
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.dir.join(name).to_str().unwrap().to_string()
+    }
+
+    fn config(&self) -> ToolsConfiguration {
+        tools_config::read_config_from_file(&self.path("settings.json")).unwrap()
+    }
+
+    fn state(&self) -> Value {
+        serde_json::from_str(&fs::read_to_string(self.path("state.json")).unwrap()).unwrap()
+    }
+
+    ///
+    /// Plan the import of a login CSV file and journal it
+    ///
+    fn plan(&self, backend: &dyn DirectoryBackend, csv: &str) -> Journal {
+        let input = self.path("logins.csv");
+        fs::write(&input, csv).unwrap();
+
+        let report = import::read_login_file(&input, &ImportProfile::default(), &mut ImportOptions::default()).unwrap();
+        assert!(!report.has_errors());
+
+        let plan = batch::plan_import(backend, &report.valid_users(), |_| false).unwrap();
+        Journal::create(&self.path("journal.json"), plan.steps).unwrap()
+    }
+}
+
+impl Drop for FakeDomain {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+const LOGINS: &str = "\
+login;last_name;first_name;groups;class;cf;password
+mario.rossi;Rossi;Mario;3B;;;Passw0rd!
+anna.bianchi;Bianchi;Anna;3B,4C;;;Secret#123
+luca.verdi;Verdi;Luca;4C;;;short
+";
+
+#[test]
+fn batch_import_provisions_the_fake_domain() {
+    let domain = FakeDomain::new("import");
+    let backend = SambaToolBackend::new(&domain.config());
+
+    let journal = domain.plan(&backend, LOGINS);
+    let mut failures = Vec::new();
+    let summary = batch::run_import(&backend, &journal, 2, |report, _| {
+        if let RowOutcome::Failed(err) = &report.outcome {
+            failures.push((report.username.clone(), err.to_string(), matches!(err, Error::ConstraintViolation(_))));
+        }
+    }).unwrap();
+
+    assert_eq!(summary, BatchSummary { created: 2, previously_created: 0, failed: 1 });
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].0, "luca.verdi");
+    assert!(failures[0].2, "weak password not classified: {}", failures[0].1);
+
+    // The failed row is journaled, the other ones are complete
+    let journal = Journal::open(&domain.path("journal.json")).unwrap();
+    assert!(!journal.is_finished());
+    let pending: Vec<String> = (0..journal.steps().len())
+        .filter(|index| journal.state(*index) != StepState::Completed)
+        .map(|index| journal.steps()[index].to_string())
+        .collect();
+    assert_eq!(pending, ["create user luca.verdi", "add luca.verdi to group 4C"]);
+
+    assert_eq!(backend.user_groups("anna.bianchi").unwrap(), ["Domain Users", "3B", "4C"]);
+    assert_eq!(backend.list_members("4C").unwrap(), ["anna.bianchi"]);
+    assert!(!backend.user_exists("luca.verdi").unwrap());
+    assert_eq!(backend.password_policy().unwrap().min_length, 7);
+
+    // Passwords reach samba-tool through stdin, names and home through options
+    let state = domain.state();
+    let mario = &state["users"]["mario.rossi"];
+    assert_eq!(mario["password"], "Passw0rd!");
+    assert_eq!(mario["surname"], "Rossi");
+    assert_eq!(mario["given_name"], "Mario");
+    assert_eq!(mario["home_directory"], "\\\\dc1\\homes\\mario.rossi");
+    assert_eq!(state["users"]["anna.bianchi"]["password"], "Secret#123");
+}
+
+#[test]
+fn resumed_import_retries_the_failed_rows() {
+    let domain = FakeDomain::new("resume");
+    let backend = SambaToolBackend::new(&domain.config());
+
+    let journal = domain.plan(&backend, LOGINS);
+    batch::run_import(&backend, &journal, 1, |_, _| {}).unwrap();
+
+    // The domain policy is relaxed, the resumed run retries the refused user
+    let mut state = domain.state();
+    state["password_settings"]["complexity"] = Value::Bool(false);
+    state["password_settings"]["min_length"] = Value::from(5);
+    fs::write(domain.path("state.json"), state.to_string()).unwrap();
+
+    let journal = Journal::open(&domain.path("journal.json")).unwrap();
+    let summary = batch::run_import(&backend, &journal, 2, |_, _| {}).unwrap();
+
+    assert_eq!(summary, BatchSummary { created: 1, previously_created: 2, failed: 0 });
+    assert!(journal.is_finished());
+    assert_eq!(backend.list_members("4C").unwrap(), ["anna.bianchi", "luca.verdi"]);
+    assert_eq!(domain.state()["users"]["luca.verdi"]["password"], "short");
+
+    // Nothing else has been made twice
+    assert_eq!(backend.list_members("3B").unwrap(), ["mario.rossi", "anna.bianchi"]);
+}
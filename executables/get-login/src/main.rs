@@ -1,19 +1,21 @@
 use std::fs::canonicalize;
 use std::{fs::OpenOptions, process::exit};
-use std::io::prelude::*;
 use std::env::current_dir;
-use chrono::Datelike;
-use fp_tools::db::rget_login::filter_by_class;
+use chrono::{Datelike, NaiveDateTime};
+use fp_tools::db::rget_login::{filter_by_class, get_login_data_since, latest_modification, parse_timestamp};
+use fp_tools::db::watermark::{read_watermark, write_watermark, Watermark};
 use fp_tools::{
     config::generator_config::read_config_from_file,
     db::rget_login::{
         generate_connection_string,
         get_db_connection, get_login_data,
         MySQLDomainUser},
-    debug_println
+    debug_println,
+    password::PasswordGenerator,
+    tools::schema::{append_records, write_records, LoginRecord, SchemaVersion}
 };
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 
 /// 
 /// Command line arguments
@@ -27,7 +29,44 @@ struct Args {
 
     /// File name prefix
     #[arg(short, long)]
-    file_prefix: Option<String>
+    file_prefix: Option<String>,
+
+    /// CSV header written in the output files
+    #[arg(short, long, value_enum, default_value_t = Schema::Legacy)]
+    schema: Schema,
+
+    /// Only export the students changed since the last successful incremental run
+    #[arg(short, long, conflicts_with = "since")]
+    incremental: bool,
+
+    /// Only export the students changed from this time on (YYYY-MM-DD[ HH:MM:SS])
+    #[arg(long, value_parser = parse_timestamp)]
+    since: Option<NaiveDateTime>,
+
+    /// File keeping the time of the last change exported by an incremental run
+    #[arg(long, default_value = "/var/lib/ad/get-login.json")]
+    state: String
+}
+
+///
+/// Login CSV schema versions selectable from the command line
+///
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Schema {
+    /// Italian legacy header (login;cognome;nome;gruppo;classe;CF;password;)
+    Legacy,
+
+    /// English header (login;last_name;first_name;groups;class;cf;password)
+    English
+}
+
+impl Schema {
+    fn version(&self) -> SchemaVersion {
+        match self {
+            Schema::Legacy => SchemaVersion::V1,
+            Schema::English => SchemaVersion::V2
+        }
+    }
 }
 
 ///
@@ -39,9 +78,6 @@ struct Paths {
 }
 
 fn main() {
-    // Standard CSV format
-    const CSV_HEADER: &'static str = "login;cognome;nome;gruppo;classe;CF;password;";
-
     // Command line arguments
     let cli = Args::parse();
 
@@ -49,26 +85,66 @@ fn main() {
     let paths = get_paths(cli.output_dir, cli.file_prefix);
 
     // Load SQL configuration from file
-    let sql_config = read_config_from_file("/etc/ad/mysql_config.json");
-
-    // Check for SQL configuration loading
-    if sql_config.is_none() {
-        eprintln!("get-login: error loading MySQL configuration data");
-        exit(1)
-    }
+    let sql_config = match read_config_from_file("/etc/ad/mysql_config.json") {
+        Ok(sql_config) => sql_config,
+        Err(err) => {
+            eprintln!("get-login: error loading MySQL configuration data. {}", err);
+            exit(1)
+        }
+    };
 
     // Get an SQL connection
-    let conn_str = generate_connection_string(&sql_config.unwrap());
-    let connection = get_db_connection(&conn_str);
+    let conn_str = generate_connection_string(&sql_config);
+    let mut connection = match get_db_connection(&conn_str) {
+        Ok(connection) => connection,
+        Err(err) => {
+            eprintln!("get-login: error getting a MySQL connection to the server. {}", err);
+            exit(1)
+        }
+    };
 
-    // Check the SQL connection
-    if connection.is_none() {
-        eprintln!("get-login: error getting a MySQL connection to the server...");
-        exit(1)
-    }
+    // Time of the last change exported by an incremental run
+    let mut mark = if cli.incremental {
+        match read_watermark(&cli.state) {
+            Ok(mark) => mark,
+            Err(err) => {
+                eprintln!("get-login: error reading the last incremental run. {}", err);
+                exit(1)
+            }
+        }
+    } else {
+        Watermark::default()
+    };
+
+    // Load all users from database, or only the changed ones
+    let loaded = match cli.since.as_ref().or(mark.last_modified.as_ref()) {
+        Some(since) => get_login_data_since(&mut connection, since),
+        None => get_login_data(&mut connection)
+    };
+
+    let mut users = match loaded {
+        Ok(users) => users,
+        Err(err) => {
+            eprintln!("get-login: error loading login data. {}", err);
+            exit(1)
+        }
+    };
+
+    // Students changed at the time of the mark have been exported by the last run
+    users.retain(|user| !mark.is_handled(user));
 
-    // Load all users from database
-    let mut users = get_login_data(&mut connection.unwrap());
+    // Blank passwords get one following the policy of the class or group
+    let passwords = match PasswordGenerator::new(sql_config.get_passwords()) {
+        Ok(passwords) => passwords,
+        Err(err) => {
+            eprintln!("get-login: invalid password settings. {}", err);
+            exit(1)
+        }
+    };
+
+    for user in users.iter_mut().filter(|user| user.password.trim().is_empty()) {
+        user.password = passwords.generate(&user.groups());
+    }
 
     // Sort by class
     users.sort_by(|a, b| a.class.cmp(&b.class));
@@ -88,14 +164,25 @@ fn main() {
 
     // Create files for each class
     for class in classes {
-        write_class_file(CSV_HEADER, &users, &class, &paths.out_dir, &paths.prefix);
+        write_class_file(&users, &class, &paths.out_dir, &paths.prefix, cli.schema);
+    }
+
+    // Next incremental run starts from the latest exported change
+    if cli.incremental {
+        mark.advance(latest_modification(&users));
+        mark.record_handled(&users);
+
+        if let Err(err) = write_watermark(&cli.state, &mark) {
+            eprintln!("get-login: cannot save the time of this run to {}. {}", &cli.state, err);
+            exit(1)
+        }
     }
 }
 
 ///
 /// Get all classes as strings
 /// 
-fn get_classes(users: &Vec<MySQLDomainUser>) -> Vec<String> {
+fn get_classes(users: &[MySQLDomainUser]) -> Vec<String> {
     let mut classes: Vec<String> = users
         .iter()
         .map(|el| el.class.clone())
@@ -139,43 +226,35 @@ fn get_paths(out_dir: Option<String>, prefix: Option<String>) -> Paths {
 /// Write file for the given class
 /// 
 fn write_class_file(
-    header: &str, 
-    users: &Vec<MySQLDomainUser>, 
+    users: &[MySQLDomainUser], 
     class: &String,
     directory: &String, 
-    prefix: &String) {
+    prefix: &String,
+    schema: Schema) {
 
     // Create the class file
     let file_path = format!("{}/{}_{}.csv", directory, prefix, class);
-    let mut file = OpenOptions::new()
+    let file = OpenOptions::new()
         .create(true)
-        .write(true)
         .append(true)
         .open(&file_path)
         .unwrap();
 
-    // Write the header
-    if let Err(_) = writeln!(file, "{}", header) {
-        eprintln!("get-login: cannot write to file {}", &file_path);
-        exit(1)
-    }
+    // Write every user in the class, after the header if the file is new
+    let records: Vec<LoginRecord> = filter_by_class(users, class)
+        .iter()
+        .map(LoginRecord::from)
+        .collect();
 
-    // For each user in the class, write it
-    let users = filter_by_class(users, class);
-
-    for user in users {
-        if let Err(_e) = writeln!(
-            file, "{};{};{};{};{};{};{};",
-            user.login,
-            user.last_name,
-            user.first_name,
-            user.group,
-            user.class,
-            "CF",
-            user.password
-        ) {
-            eprintln!("get-login: cannot write to file {}", &file_path);
-            exit(1)
-        }
+    let is_new = file.metadata().map(|metadata| metadata.len() == 0).unwrap_or(true);
+    let written = if is_new {
+        write_records(file, schema.version(), &records)
+    } else {
+        append_records(file, schema.version(), &records)
+    };
+
+    if let Err(err) = written {
+        eprintln!("get-login: cannot write to file {}. {}", &file_path, err);
+        exit(1)
     }
 }
\ No newline at end of file